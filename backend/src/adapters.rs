@@ -0,0 +1,270 @@
+use crate::db::DbPool;
+use crate::models::{AdapterConfig, CategorizeMode, CategoryRule, CategoryRules, CreateDocumentRequest, FieldTemplate, ImportAdapter};
+use crate::rag::RagSystem;
+use regex::Regex;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use std::sync::OnceLock;
+
+/// Default similarity an embedding match must reach before the fallback category is used.
+const DEFAULT_EMBEDDING_THRESHOLD: f32 = 0.25;
+
+#[derive(Serialize)]
+pub struct AdapterPreset {
+    pub slug: &'static str,
+    pub name: &'static str,
+    pub description: &'static str,
+    pub config: AdapterConfig,
+}
+
+/// Built-in adapters. Saved adapters cannot reuse these slugs.
+pub fn presets() -> Vec<AdapterPreset> {
+    vec![
+        AdapterPreset {
+            slug: "instruction",
+            name: "Instruction JSONL",
+            description: "{system, user, assistant} records, as produced by instruction-tuning datasets.",
+            config: AdapterConfig { template: Some(instruction_template("instruction-dataset")), categorize: None },
+        },
+        AdapterPreset {
+            slug: "alpaca",
+            name: "Alpaca JSONL",
+            description: "{instruction, input, output} records.",
+            config: AdapterConfig {
+                template: Some(FieldTemplate {
+                    content: "User Query: {instruction}\n{input}\n\nAnalysis & Response:\n{output}".to_string(),
+                    metadata: metadata(&[("source", "alpaca-dataset"), ("type", "educational_content")]),
+                }),
+                categorize: None,
+            },
+        },
+        AdapterPreset {
+            slug: "keyword-categories",
+            name: "Keyword categories",
+            description: "Assigns metadata.category from security keywords in the content.",
+            config: AdapterConfig { template: None, categorize: Some(security_categories()) },
+        },
+        AdapterPreset {
+            slug: "cybersec-dataset",
+            name: "CyberSec dataset",
+            description: "Instruction records mapped to documents and categorized by keyword.",
+            config: AdapterConfig { template: Some(instruction_template("CyberSec-Dataset")), categorize: Some(security_categories()) },
+        },
+    ]
+}
+
+fn metadata(pairs: &[(&str, &str)]) -> Map<String, Value> {
+    pairs.iter().map(|(k, v)| (k.to_string(), Value::from(*v))).collect()
+}
+
+fn instruction_template(source: &str) -> FieldTemplate {
+    FieldTemplate {
+        content: "User Query: {user}\n\nAnalysis & Response:\n{assistant}".to_string(),
+        metadata: metadata(&[("source", source), ("type", "educational_content"), ("system_prompt", "{system:100}")]),
+    }
+}
+
+fn security_categories() -> CategoryRules {
+    let rule = |name: &str, keywords: &[&str]| CategoryRule {
+        name: name.to_string(),
+        keywords: keywords.iter().map(|k| k.to_string()).collect(),
+        description: None,
+    };
+    CategoryRules {
+        mode: CategorizeMode::Keyword,
+        categories: vec![
+            rule("Malware_Analysis", &["malware", "obfuscation", "packing", "anti-debugging", "anti-analysis", "reverse engineering", "binary", "virtualization"]),
+            rule("Forensics_IR", &["forensic", "timestomping", "snapshot", "logging", "artifact", "incident response", "evidence", "memory"]),
+            rule("Network_Security", &["network", "traffic", "tls", "c2", "wireless", "pcap", "handshake", "dns"]),
+            rule("Exploitation", &["exploit", "injection", "rop", "jop", "buffer overflow", "vulnerability", "bypass", "privilege escalation"]),
+            rule("Cloud_Security", &["cloud", "aws", "azure", "container", "kubernetes", "iac", "infrastructure as code", "serverless"]),
+            rule("Defense_Compliance", &["nist", "policy", "compliance", "dlp", "ueba", "monitor", "mitigate", "governance"]),
+            rule("Cryptography", &["crypto", "encryption", "hashing", "quantum", "cipher", "key"]),
+            rule("Web_Security", &["xss", "csrf", "sql injection", "web", "browser", "cookie", "session"]),
+        ],
+        fallback: "General".to_string(),
+        threshold: None,
+        overwrite: false,
+    }
+}
+
+/// Looks up an adapter by slug, built-ins first.
+pub async fn resolve(pool: &DbPool, slug: &str) -> Result<Option<AdapterConfig>, sqlx::Error> {
+    if let Some(preset) = presets().into_iter().find(|p| p.slug == slug) {
+        return Ok(Some(preset.config));
+    }
+    let adapter = sqlx::query_as::<_, ImportAdapter>("SELECT * FROM import_adapters WHERE slug = $1")
+        .bind(slug)
+        .fetch_optional(pool)
+        .await?;
+    Ok(adapter.map(|a| a.config()))
+}
+
+/// Checks a config before it is saved; keeps bad adapters from failing every record of an import.
+pub fn validate_config(config: &AdapterConfig) -> Result<(), String> {
+    if let Some(template) = &config.template {
+        if !placeholder().is_match(&template.content) {
+            return Err("template.content needs at least one {field} placeholder".to_string());
+        }
+    }
+    if let Some(rules) = &config.categorize {
+        if rules.categories.is_empty() {
+            return Err("categorize.categories must not be empty".to_string());
+        }
+        if rules.categories.iter().any(|c| c.name.trim().is_empty()) {
+            return Err("category names must not be empty".to_string());
+        }
+        if rules.mode == CategorizeMode::Keyword && rules.categories.iter().all(|c| c.keywords.is_empty()) {
+            return Err("keyword categorization needs at least one keyword".to_string());
+        }
+    }
+    Ok(())
+}
+
+fn placeholder() -> &'static Regex {
+    static PLACEHOLDER: OnceLock<Regex> = OnceLock::new();
+    PLACEHOLDER.get_or_init(|| Regex::new(r"\{([A-Za-z0-9_.]+)(?::(\d+))?\}").unwrap())
+}
+
+fn field(record: &Value, path: &str) -> String {
+    match record.pointer(&format!("/{}", path.replace('.', "/"))) {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string(),
+    }
+}
+
+fn render(template: &str, record: &Value) -> String {
+    placeholder()
+        .replace_all(template, |c: &regex::Captures| {
+            let value = field(record, &c[1]);
+            match c.get(2).and_then(|n| n.as_str().parse::<usize>().ok()) {
+                Some(limit) if value.chars().count() > limit => format!("{}...", value.chars().take(limit).collect::<String>()),
+                _ => value,
+            }
+        })
+        .trim()
+        .to_string()
+}
+
+/// Maps a source record to a `{content, metadata}` record. Metadata the record already
+/// carries is kept unless the template sets the same key.
+pub fn apply_template(template: &FieldTemplate, record: Value) -> Result<Value, String> {
+    if !record.is_object() {
+        return Err("record must be a JSON object".to_string());
+    }
+    // Literal text around the placeholders does not count as content
+    let has_fields = placeholder().captures_iter(&template.content).any(|c| !field(&record, &c[1]).trim().is_empty());
+    let content = render(&template.content, &record);
+    if content.is_empty() || !has_fields {
+        return Err("template produced empty content".to_string());
+    }
+
+    let mut metadata = match record.get("metadata") {
+        Some(Value::Object(m)) => m.clone(),
+        _ => Map::new(),
+    };
+    for (key, value) in &template.metadata {
+        match value {
+            Value::String(s) => {
+                let rendered = render(s, &record);
+                if rendered.is_empty() {
+                    metadata.remove(key);
+                } else {
+                    metadata.insert(key.clone(), Value::from(rendered));
+                }
+            }
+            other => {
+                metadata.insert(key.clone(), other.clone());
+            }
+        }
+    }
+    Ok(json!({"content": content, "metadata": metadata}))
+}
+
+/// Assigns `metadata.category` to documents. Embedding mode embeds each document once more
+/// for the comparison, so it roughly doubles embedding work for the import.
+pub struct Categorizer {
+    rules: CategoryRules,
+    /// Lowercased keywords, per category.
+    keywords: Vec<Vec<String>>,
+    /// Description embeddings, per category (embedding mode only).
+    centroids: Vec<Vec<f32>>,
+}
+
+impl Categorizer {
+    pub async fn new(rag: &RagSystem, rules: CategoryRules) -> Result<Self, String> {
+        let keywords = rules.categories.iter().map(|c| c.keywords.iter().map(|k| k.to_lowercase()).collect()).collect();
+        let centroids = if rules.mode == CategorizeMode::Embedding {
+            let descriptions = rules
+                .categories
+                .iter()
+                .map(|c| c.description.clone().unwrap_or_else(|| format!("{}: {}", c.name.replace('_', " "), c.keywords.join(", "))))
+                .collect();
+            rag.embedder.embed(descriptions).await.map_err(|e| format!("failed to embed category descriptions: {}", e))?
+        } else {
+            Vec::new()
+        };
+        Ok(Self { rules, keywords, centroids })
+    }
+
+    pub async fn categorize(&self, rag: &RagSystem, docs: &mut [CreateDocumentRequest]) -> Result<(), String> {
+        let pending: Vec<usize> = (0..docs.len())
+            .filter(|&i| self.rules.overwrite || docs[i].metadata.as_ref().and_then(|m| m.get("category")).is_none())
+            .collect();
+        if pending.is_empty() {
+            return Ok(());
+        }
+
+        let categories: Vec<&str> = match self.rules.mode {
+            CategorizeMode::Keyword => pending.iter().map(|&i| self.by_keyword(&docs[i].content)).collect(),
+            CategorizeMode::Embedding => {
+                let texts = pending.iter().map(|&i| docs[i].content.clone()).collect();
+                let embeddings = rag.embedder.embed(texts).await.map_err(|e| format!("categorization embedding failed: {}", e))?;
+                embeddings.iter().map(|e| self.by_embedding(e)).collect()
+            }
+        };
+
+        for (i, category) in pending.into_iter().zip(categories) {
+            let doc = &mut docs[i];
+            if !matches!(doc.metadata, Some(Value::Object(_))) {
+                doc.metadata = Some(json!({}));
+            }
+            if let Some(Value::Object(metadata)) = doc.metadata.as_mut() {
+                metadata.insert("category".to_string(), Value::from(category));
+            }
+        }
+        Ok(())
+    }
+
+    fn by_keyword(&self, content: &str) -> &str {
+        let content = content.to_lowercase();
+        self.rules
+            .categories
+            .iter()
+            .zip(&self.keywords)
+            .find(|(_, keywords)| keywords.iter().any(|k| content.contains(k.as_str())))
+            .map(|(category, _)| category.name.as_str())
+            .unwrap_or(self.rules.fallback.as_str())
+    }
+
+    fn by_embedding(&self, embedding: &[f32]) -> &str {
+        let threshold = self.rules.threshold.unwrap_or(DEFAULT_EMBEDDING_THRESHOLD);
+        self.rules
+            .categories
+            .iter()
+            .zip(&self.centroids)
+            .map(|(category, centroid)| (category, cosine(embedding, centroid)))
+            .filter(|(_, score)| *score >= threshold)
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(category, _)| category.name.as_str())
+            .unwrap_or(self.rules.fallback.as_str())
+    }
+}
+
+fn cosine(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm = |v: &[f32]| v.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let denom = norm(a) * norm(b);
+    if denom == 0.0 { 0.0 } else { dot / denom }
+}
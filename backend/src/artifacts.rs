@@ -0,0 +1,530 @@
+use regex::Regex;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+
+/// Maximum number of printable strings kept in a report.
+const MAX_STRINGS: usize = 200;
+/// Minimum run length for the strings extractor (same default as `strings -n 6`).
+const MIN_STRING_LEN: usize = 6;
+const MAX_FLOWS: usize = 15;
+
+/// Builds the structured analysis report for an uploaded artifact.
+/// Every extractor is best effort: a malformed file yields a partial report, never an error.
+pub fn analyze(filename: &str, data: &[u8]) -> Value {
+    let file_type = detect_type(data);
+    let mut report = json!({
+        "filename": filename,
+        "size_bytes": data.len(),
+        "file_type": file_type,
+        "entropy": entropy_summary(data),
+    });
+
+    match file_type {
+        "elf" => report["elf"] = elf_summary(data),
+        "pe" => report["pe"] = pe_summary(data),
+        "pcap" => report["pcap"] = pcap_summary(data),
+        "pcapng" => report["pcap"] = pcapng_summary(data),
+        "jpeg" | "tiff" | "png" | "webp" | "heif" => report["metadata"] = image_metadata(data, file_type),
+        "text" => report["text"] = text_summary(data),
+        _ => {}
+    }
+
+    if file_type != "text" {
+        report["strings"] = json!(extract_strings(data));
+    }
+    report
+}
+
+pub fn detect_type(data: &[u8]) -> &'static str {
+    let magic: &[(&[u8], &str)] = &[
+        (&b"\x7fELF"[..], "elf"),
+        (&b"MZ"[..], "pe"),
+        (&b"\x89PNG\r\n\x1a\n"[..], "png"),
+        (&b"\xff\xd8\xff"[..], "jpeg"),
+        (&b"GIF87a"[..], "gif"),
+        (&b"GIF89a"[..], "gif"),
+        (&b"II*\x00"[..], "tiff"),
+        (&b"MM\x00*"[..], "tiff"),
+        (&b"%PDF"[..], "pdf"),
+        (&b"PK\x03\x04"[..], "zip"),
+        (&b"\x1f\x8b"[..], "gzip"),
+        (&b"BZh"[..], "bzip2"),
+        (&b"7z\xbc\xaf\x27\x1c"[..], "7z"),
+        (&b"Rar!\x1a\x07"[..], "rar"),
+        (&b"\xd4\xc3\xb2\xa1"[..], "pcap"),
+        (&b"\xa1\xb2\xc3\xd4"[..], "pcap"),
+        (&b"\x4d\x3c\xb2\xa1"[..], "pcap"),
+        (&b"\xa1\xb2\x3c\x4d"[..], "pcap"),
+        (&b"\x0a\x0d\x0d\x0a"[..], "pcapng"),
+        (&b"SQLite format 3\x00"[..], "sqlite"),
+        (&b"\xca\xfe\xba\xbe"[..], "java-class"),
+    ];
+    for (prefix, name) in magic {
+        if data.starts_with(prefix) {
+            // MZ alone is too weak; require the PE signature the header points to
+            if *name == "pe" && pe_header_offset(data).is_none() {
+                continue;
+            }
+            return name;
+        }
+    }
+    if data.len() >= 12 && &data[0..4] == b"RIFF" && &data[8..12] == b"WEBP" {
+        return "webp";
+    }
+    if data.len() >= 12 && &data[4..8] == b"ftyp" && (&data[8..12] == b"heic" || &data[8..12] == b"mif1") {
+        return "heif";
+    }
+    if looks_like_text(data) {
+        return "text";
+    }
+    "unknown"
+}
+
+fn looks_like_text(data: &[u8]) -> bool {
+    let sample = &data[..data.len().min(8192)];
+    if sample.is_empty() {
+        return false;
+    }
+    std::str::from_utf8(sample).is_ok() || sample.iter().all(|b| b.is_ascii_graphic() || b.is_ascii_whitespace())
+}
+
+fn shannon_entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for b in data {
+        counts[*b as usize] += 1;
+    }
+    let len = data.len() as f64;
+    counts
+        .iter()
+        .filter(|c| **c > 0)
+        .map(|c| {
+            let p = *c as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+fn entropy_summary(data: &[u8]) -> Value {
+    let overall = shannon_entropy(data);
+    let blocks: Vec<f64> = data.chunks(4096).map(shannon_entropy).collect();
+    let max = blocks.iter().cloned().fold(0.0, f64::max);
+    let min = blocks.iter().cloned().fold(8.0, f64::min);
+    let assessment = if overall > 7.5 {
+        "very high (likely compressed or encrypted)"
+    } else if overall > 6.0 {
+        "high (packed code, compressed sections or encoded data)"
+    } else {
+        "normal"
+    };
+    json!({
+        "overall": (overall * 1000.0).round() / 1000.0,
+        "block_max": (max * 1000.0).round() / 1000.0,
+        "block_min": if blocks.is_empty() { 0.0 } else { (min * 1000.0).round() / 1000.0 },
+        "assessment": assessment,
+    })
+}
+
+pub fn extract_strings(data: &[u8]) -> Vec<String> {
+    let mut strings = Vec::new();
+    let mut current = Vec::new();
+    for &b in data {
+        if b.is_ascii_graphic() || b == b' ' {
+            current.push(b);
+        } else {
+            if current.len() >= MIN_STRING_LEN {
+                strings.push(String::from_utf8_lossy(&current).to_string());
+                if strings.len() >= MAX_STRINGS {
+                    return strings;
+                }
+            }
+            current.clear();
+        }
+    }
+    if current.len() >= MIN_STRING_LEN && strings.len() < MAX_STRINGS {
+        strings.push(String::from_utf8_lossy(&current).to_string());
+    }
+    strings
+}
+
+fn text_summary(data: &[u8]) -> Value {
+    let text = String::from_utf8_lossy(data);
+    let ip_re = Regex::new(r"\b(?:\d{1,3}\.){3}\d{1,3}\b").expect("valid regex");
+    let mut ips: HashMap<&str, usize> = HashMap::new();
+    for m in ip_re.find_iter(&text) {
+        *ips.entry(m.as_str()).or_insert(0) += 1;
+    }
+    let mut top_ips: Vec<(&str, usize)> = ips.into_iter().collect();
+    top_ips.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    top_ips.truncate(10);
+
+    let lower = text.to_lowercase();
+    let keywords: Vec<&str> = ["error", "failed", "denied", "unauthorized", "sudo", "ssh", "password", "select", "union", "<script"]
+        .into_iter()
+        .filter(|k| lower.contains(k))
+        .collect();
+
+    json!({
+        "lines": text.lines().count(),
+        "head": text.lines().take(20).collect::<Vec<_>>(),
+        "top_ipv4": top_ips.into_iter().map(|(ip, count)| json!({"ip": ip, "count": count})).collect::<Vec<_>>(),
+        "keywords_present": keywords,
+    })
+}
+
+fn read_u16(data: &[u8], off: usize, le: bool) -> Option<u16> {
+    let b: [u8; 2] = data.get(off..off.checked_add(2)?)?.try_into().ok()?;
+    Some(if le { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) })
+}
+
+fn read_u32(data: &[u8], off: usize, le: bool) -> Option<u32> {
+    let b: [u8; 4] = data.get(off..off.checked_add(4)?)?.try_into().ok()?;
+    Some(if le { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) })
+}
+
+fn read_u64(data: &[u8], off: usize, le: bool) -> Option<u64> {
+    let b: [u8; 8] = data.get(off..off.checked_add(8)?)?.try_into().ok()?;
+    Some(if le { u64::from_le_bytes(b) } else { u64::from_be_bytes(b) })
+}
+
+fn elf_summary(data: &[u8]) -> Value {
+    let is_64 = data.get(4) == Some(&2);
+    let le = data.get(5) != Some(&2);
+    let e_type = read_u16(data, 16, le).unwrap_or(0);
+    let machine = read_u16(data, 18, le).unwrap_or(0);
+    let entry = if is_64 { read_u64(data, 24, le) } else { read_u32(data, 24, le).map(u64::from) };
+
+    let type_name = match e_type {
+        1 => "relocatable",
+        2 => "executable",
+        3 => "shared object / PIE",
+        4 => "core dump",
+        _ => "unknown",
+    };
+    let machine_name = match machine {
+        0x03 => "x86",
+        0x08 => "MIPS",
+        0x28 => "ARM",
+        0x3e => "x86-64",
+        0xb7 => "AArch64",
+        0xf3 => "RISC-V",
+        _ => "other",
+    };
+
+    // Program headers tell us about NX (PT_GNU_STACK) and dynamic linking (PT_INTERP)
+    let (ph_off, ph_size, ph_num) = if is_64 {
+        (read_u64(data, 32, le).unwrap_or(0) as usize, read_u16(data, 54, le).unwrap_or(0) as usize, read_u16(data, 56, le).unwrap_or(0) as usize)
+    } else {
+        (read_u32(data, 28, le).unwrap_or(0) as usize, read_u16(data, 42, le).unwrap_or(0) as usize, read_u16(data, 44, le).unwrap_or(0) as usize)
+    };
+    let mut nx = None;
+    let mut dynamic = false;
+    for i in 0..ph_num.min(64) {
+        // Header fields are attacker-controlled, so offsets that overflow end the scan
+        let Some(off) = i.checked_mul(ph_size).and_then(|o| o.checked_add(ph_off)) else { break };
+        let Some(p_type) = read_u32(data, off, le) else { break };
+        match p_type {
+            3 => dynamic = true,
+            0x6474e551 => {
+                let flags_off = off.checked_add(if is_64 { 4 } else { 24 });
+                nx = flags_off.and_then(|o| read_u32(data, o, le)).map(|flags| flags & 1 == 0);
+            }
+            _ => {}
+        }
+    }
+
+    let strings = extract_strings(data);
+    let canary = strings.iter().any(|s| s.contains("__stack_chk_fail"));
+
+    json!({
+        "class": if is_64 { "ELF64" } else { "ELF32" },
+        "endianness": if le { "little" } else { "big" },
+        "type": type_name,
+        "machine": machine_name,
+        "entry_point": entry.map(|e| format!("{:#x}", e)),
+        "pie": e_type == 3,
+        "nx": nx,
+        "stack_canary": canary,
+        "dynamically_linked": dynamic,
+    })
+}
+
+fn pe_header_offset(data: &[u8]) -> Option<usize> {
+    let off = read_u32(data, 0x3c, true)? as usize;
+    if data.get(off..off.checked_add(4)?)? == b"PE\x00\x00" {
+        Some(off)
+    } else {
+        None
+    }
+}
+
+fn pe_summary(data: &[u8]) -> Value {
+    let Some(pe) = pe_header_offset(data) else {
+        return json!({"error": "PE signature not found"});
+    };
+    let machine = read_u16(data, pe + 4, true).unwrap_or(0);
+    let sections = read_u16(data, pe + 6, true).unwrap_or(0);
+    let timestamp = read_u32(data, pe + 8, true).unwrap_or(0);
+    let opt_size = read_u16(data, pe + 20, true).unwrap_or(0) as usize;
+    let characteristics = read_u16(data, pe + 22, true).unwrap_or(0);
+    let opt = pe + 24;
+    let magic = read_u16(data, opt, true).unwrap_or(0);
+    let dll_characteristics = read_u16(data, opt + 70, true).unwrap_or(0);
+
+    let mut section_names = Vec::new();
+    let table = opt + opt_size;
+    for i in 0..(sections as usize).min(96) {
+        let Some(raw) = data.get(table + i * 40..table + i * 40 + 8) else { break };
+        section_names.push(String::from_utf8_lossy(raw).trim_end_matches('\0').to_string());
+    }
+
+    json!({
+        "format": if magic == 0x20b { "PE32+" } else { "PE32" },
+        "machine": match machine { 0x14c => "x86", 0x8664 => "x86-64", 0xaa64 => "ARM64", _ => "other" },
+        "sections": section_names,
+        "compile_timestamp": chrono::DateTime::from_timestamp(timestamp as i64, 0).map(|t| t.to_rfc3339()),
+        "is_dll": characteristics & 0x2000 != 0,
+        "aslr": dll_characteristics & 0x0040 != 0,
+        "dep_nx": dll_characteristics & 0x0100 != 0,
+        "dotnet": String::from_utf8_lossy(data).contains("mscoree.dll"),
+    })
+}
+
+#[derive(Default)]
+struct TrafficStats {
+    packets: usize,
+    protocols: HashMap<&'static str, usize>,
+    flows: HashMap<(String, String, &'static str), (usize, usize)>,
+}
+
+impl TrafficStats {
+    fn add_ethernet_frame(&mut self, frame: &[u8]) {
+        self.packets += 1;
+        let ether_type = read_u16(frame, 12, false).unwrap_or(0);
+        if ether_type == 0x0806 {
+            *self.protocols.entry("ARP").or_insert(0) += 1;
+            return;
+        }
+        if ether_type == 0x86dd {
+            *self.protocols.entry("IPv6").or_insert(0) += 1;
+            return;
+        }
+        if ether_type != 0x0800 {
+            *self.protocols.entry("other").or_insert(0) += 1;
+            return;
+        }
+        self.add_ipv4(&frame[14.min(frame.len())..]);
+    }
+
+    fn add_ipv4(&mut self, ip: &[u8]) {
+        let Some(&ver_ihl) = ip.first() else { return };
+        let ihl = ((ver_ihl & 0x0f) as usize) * 4;
+        let (Some(proto), Some(src), Some(dst)) = (ip.get(9), ip.get(12..16), ip.get(16..20)) else { return };
+        let src = format!("{}.{}.{}.{}", src[0], src[1], src[2], src[3]);
+        let dst = format!("{}.{}.{}.{}", dst[0], dst[1], dst[2], dst[3]);
+        let payload = ip.get(ihl..).unwrap_or(&[]);
+
+        let (proto_name, ports) = match proto {
+            6 => ("TCP", read_u16(payload, 0, false).zip(read_u16(payload, 2, false))),
+            17 => ("UDP", read_u16(payload, 0, false).zip(read_u16(payload, 2, false))),
+            1 => ("ICMP", None),
+            _ => ("other-ip", None),
+        };
+        let app = ports.and_then(|(s, d)| guess_service(s).or_else(|| guess_service(d)));
+        *self.protocols.entry(app.unwrap_or(proto_name)).or_insert(0) += 1;
+
+        let (a, b) = match ports {
+            Some((sp, dp)) => (format!("{}:{}", src, sp), format!("{}:{}", dst, dp)),
+            None => (src, dst),
+        };
+        // Direction-independent key so both halves of a conversation share a flow
+        let key = if a <= b { (a, b, proto_name) } else { (b, a, proto_name) };
+        let entry = self.flows.entry(key).or_insert((0, 0));
+        entry.0 += 1;
+        entry.1 += ip.len();
+    }
+
+    fn into_report(self, link_type: u32) -> Value {
+        let mut flows: Vec<_> = self.flows.into_iter().collect();
+        flows.sort_by_key(|(_, (_, bytes))| std::cmp::Reverse(*bytes));
+        let total_flows = flows.len();
+        flows.truncate(MAX_FLOWS);
+        json!({
+            "link_type": link_type,
+            "packets": self.packets,
+            "protocols": self.protocols,
+            "total_flows": total_flows,
+            "top_flows": flows.into_iter().map(|((a, b, proto), (packets, bytes))| json!({
+                "endpoints": [a, b],
+                "protocol": proto,
+                "packets": packets,
+                "bytes": bytes,
+            })).collect::<Vec<_>>(),
+        })
+    }
+}
+
+fn guess_service(port: u16) -> Option<&'static str> {
+    Some(match port {
+        20 | 21 => "FTP",
+        22 => "SSH",
+        23 => "Telnet",
+        25 | 587 => "SMTP",
+        53 => "DNS",
+        80 | 8080 => "HTTP",
+        110 => "POP3",
+        143 => "IMAP",
+        443 => "TLS",
+        445 => "SMB",
+        3306 => "MySQL",
+        3389 => "RDP",
+        _ => return None,
+    })
+}
+
+fn pcap_summary(data: &[u8]) -> Value {
+    let le = matches!(data.get(0..4), Some(b"\xd4\xc3\xb2\xa1") | Some(b"\x4d\x3c\xb2\xa1"));
+    let link_type = read_u32(data, 20, le).unwrap_or(0);
+    let mut stats = TrafficStats::default();
+    let mut off = 24;
+    while let Some(cap_len) = read_u32(data, off + 8, le) {
+        let start = off + 16;
+        let Some(frame) = data.get(start..start + cap_len as usize) else { break };
+        if link_type == 1 {
+            stats.add_ethernet_frame(frame);
+        } else if link_type == 101 {
+            stats.packets += 1;
+            stats.add_ipv4(frame);
+        } else {
+            stats.packets += 1;
+        }
+        off = start + cap_len as usize;
+    }
+    stats.into_report(link_type)
+}
+
+fn pcapng_summary(data: &[u8]) -> Value {
+    // Byte order comes from the section header's magic
+    let le = read_u32(data, 8, true) == Some(0x1a2b3c4d);
+    let mut link_type = 0;
+    let mut stats = TrafficStats::default();
+    let mut off = 0;
+    while let (Some(block_type), Some(block_len)) = (read_u32(data, off, le), read_u32(data, off + 4, le)) {
+        let block_len = block_len as usize;
+        if block_len < 12 {
+            break;
+        }
+        match block_type {
+            // Interface Description Block
+            1 => link_type = read_u16(data, off + 8, le).unwrap_or(0) as u32,
+            // Enhanced Packet Block
+            6 => {
+                let cap_len = read_u32(data, off + 20, le).unwrap_or(0) as usize;
+                if let Some(frame) = data.get(off + 28..off + 28 + cap_len) {
+                    if link_type == 1 {
+                        stats.add_ethernet_frame(frame);
+                    } else {
+                        stats.packets += 1;
+                    }
+                }
+            }
+            _ => {}
+        }
+        off += block_len;
+    }
+    stats.into_report(link_type)
+}
+
+fn image_metadata(data: &[u8], file_type: &str) -> Value {
+    let mut fields = serde_json::Map::new();
+    if let Ok(exif) = exif::Reader::new().read_from_container(&mut std::io::Cursor::new(data)) {
+        for f in exif.fields() {
+            fields.insert(f.tag.to_string(), json!(f.display_value().with_unit(&exif).to_string()));
+        }
+    }
+
+    // PNG text chunks are a classic hiding place that EXIF readers skip
+    let mut text_chunks = Vec::new();
+    let mut trailing_bytes = 0;
+    if file_type == "png" {
+        let mut off = 8;
+        while let (Some(len), Some(kind)) = (read_u32(data, off, false), data.get(off + 4..off + 8)) {
+            let body = data.get(off + 8..off + 8 + len as usize).unwrap_or(&[]);
+            if kind == b"tEXt" || kind == b"iTXt" || kind == b"zTXt" {
+                text_chunks.push(String::from_utf8_lossy(body).replace('\0', ": "));
+            }
+            off += 12 + len as usize;
+            if kind == b"IEND" {
+                trailing_bytes = data.len().saturating_sub(off);
+                break;
+            }
+        }
+    }
+    if file_type == "jpeg" {
+        if let Some(pos) = data.windows(2).rposition(|w| w == b"\xff\xd9") {
+            trailing_bytes = data.len() - pos - 2;
+        }
+    }
+
+    json!({
+        "exif": fields,
+        "text_chunks": text_chunks,
+        // Data appended after the end-of-image marker often hides a second file
+        "trailing_bytes_after_image": trailing_bytes,
+    })
+}
+
+/// Compact rendering of a report for the system prompt.
+pub fn prompt_section(reports: &[Value], max_chars: usize) -> String {
+    let mut out = String::from("## ATTACHMENT ANALYSIS\nServer-side analysis of files the user attached. Treat it as ground truth about the files.\n");
+    for report in reports {
+        let rendered = serde_json::to_string_pretty(report).unwrap_or_default();
+        let truncated: String = rendered.chars().take(max_chars).collect();
+        out.push_str("\n```json\n");
+        out.push_str(&truncated);
+        if truncated.len() < rendered.len() {
+            out.push_str("\n... (truncated)");
+        }
+        out.push_str("\n```\n");
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn elf64_header() -> Vec<u8> {
+        let mut data = vec![0u8; 64];
+        data[..6].copy_from_slice(&[0x7f, b'E', b'L', b'F', 2, 1]);
+        data[16..18].copy_from_slice(&3u16.to_le_bytes());
+        data[18..20].copy_from_slice(&0x3eu16.to_le_bytes());
+        data[24..32].copy_from_slice(&0x1040u64.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn truncated_elf_header_does_not_panic() {
+        let header = elf64_header();
+        for len in [6, 17, 20, 30, 40, 55] {
+            let summary = elf_summary(&header[..len]);
+            assert_eq!(summary["class"], "ELF64");
+            assert_eq!(summary["nx"], Value::Null);
+        }
+        assert_eq!(elf_summary(&header[..20])["entry_point"], Value::Null);
+    }
+
+    #[test]
+    fn overflowing_program_header_offsets_end_the_scan() {
+        let mut data = elf64_header();
+        data[32..40].copy_from_slice(&u64::MAX.to_le_bytes());
+        data[54..56].copy_from_slice(&u16::MAX.to_le_bytes());
+        data[56..58].copy_from_slice(&64u16.to_le_bytes());
+        let summary = elf_summary(&data);
+        assert_eq!(summary["entry_point"], "0x1040");
+        assert_eq!(summary["machine"], "x86-64");
+        assert_eq!(summary["dynamically_linked"], false);
+    }
+}
@@ -0,0 +1,73 @@
+use crate::db::DbPool;
+use crate::models::{User, COLLECTION_VISIBILITIES};
+use chrono::Utc;
+use sha2::{Digest, Sha256};
+
+/// Collection for documents that do not name one. Seeded by `db::init_db` and never deleted.
+pub const DEFAULT_COLLECTION: &str = "general";
+
+const ROLES: &[&str] = &["user", "editor", "admin"];
+
+/// Ids of the collections `user` may read, or `None` for admins, who read everything.
+pub async fn readable_by(pool: &DbPool, user: &User) -> Result<Option<Vec<String>>, sqlx::Error> {
+    if user.role == "admin" {
+        return Ok(None);
+    }
+    let subscribed = user.subscription_end.map(|end| end > Utc::now().naive_utc()).unwrap_or(false);
+    let ids: Vec<(String,)> = sqlx::query_as(
+        "SELECT c.id FROM kb_collections c
+         WHERE c.visibility = 'public'
+            OR (c.visibility = 'subscribers' AND $2)
+            OR (c.visibility = 'roles' AND $3 = ANY(c.roles))
+            OR EXISTS (
+                SELECT 1 FROM kb_collection_grants g
+                WHERE g.collection_id = c.id
+                  AND ((g.principal_type = 'user' AND g.principal_id = $1)
+                    OR (g.principal_type = 'group' AND g.principal_id IN (SELECT group_id FROM user_group_members WHERE user_id = $1)))
+            )
+         ORDER BY c.id",
+    )
+    .bind(&user.id)
+    .bind(subscribed)
+    .bind(&user.role)
+    .fetch_all(pool)
+    .await?;
+    Ok(Some(ids.into_iter().map(|(id,)| id).collect()))
+}
+
+/// Stable key for a set of readable collections, so cached answers are only shared between
+/// users who could have retrieved the same context.
+pub fn access_key(collection_ids: Option<&[String]>) -> String {
+    match collection_ids {
+        None => "all".to_string(),
+        Some(ids) => format!("{:x}", Sha256::digest(ids.join(",").as_bytes())),
+    }
+}
+
+pub async fn exists(pool: &DbPool, id: &str) -> Result<bool, sqlx::Error> {
+    let row: Option<(String,)> = sqlx::query_as("SELECT id FROM kb_collections WHERE id = $1")
+        .bind(id)
+        .fetch_optional(pool)
+        .await?;
+    Ok(row.is_some())
+}
+
+pub fn validate_policy(visibility: &str, roles: &[String]) -> Result<(), String> {
+    if !COLLECTION_VISIBILITIES.contains(&visibility) {
+        return Err(format!("visibility must be one of: {}", COLLECTION_VISIBILITIES.join(", ")));
+    }
+    if let Some(role) = roles.iter().find(|r| !ROLES.contains(&r.as_str())) {
+        return Err(format!("Unknown role '{}'", role));
+    }
+    if visibility == "roles" && roles.is_empty() {
+        return Err("roles visibility needs at least one role".to_string());
+    }
+    Ok(())
+}
+
+pub fn validate_slug(slug: &str) -> Result<(), String> {
+    if slug.is_empty() || !slug.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-') {
+        return Err("slug must be lowercase letters, digits and dashes".to_string());
+    }
+    Ok(())
+}
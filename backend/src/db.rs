@@ -3,7 +3,9 @@ use std::env;
 
 pub type DbPool = Pool<Postgres>;
 
-pub async fn init_db() -> DbPool {
+/// `embedding_dim` sizes the vector columns of a new database. Existing columns keep their size
+/// until the re-index job migrates them (see `reindex`).
+pub async fn init_db(embedding_dim: usize) -> DbPool {
     let database_url = env::var("DATABASE_URL").expect("DATABASE_URL must be set");
 
     let pool = PgPoolOptions::new()
@@ -18,13 +20,34 @@ pub async fn init_db() -> DbPool {
         .await
         .expect("Failed to create vector extension");
 
-    let queries = vec![
+    let documents_table = format!(
         r#"CREATE TABLE IF NOT EXISTS documents (
             id TEXT PRIMARY KEY,
             content TEXT NOT NULL,
             metadata TEXT,
-            embedding vector(384) NOT NULL
+            embedding vector({}) NOT NULL
+        )"#,
+        embedding_dim
+    );
+    let response_cache_table = format!(
+        r#"CREATE TABLE IF NOT EXISTS response_cache (
+            id TEXT PRIMARY KEY,
+            model TEXT NOT NULL,
+            persona_id TEXT,
+            challenge_id TEXT,
+            kb_version BIGINT NOT NULL,
+            question TEXT NOT NULL,
+            embedding vector({}) NOT NULL,
+            response TEXT NOT NULL,
+            hits INTEGER NOT NULL DEFAULT 0,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            last_hit_at TIMESTAMP
         )"#,
+        embedding_dim
+    );
+
+    let queries = vec![
+        documents_table.as_str(),
         r#"CREATE TABLE IF NOT EXISTS users (
             id TEXT PRIMARY KEY,
             username TEXT NOT NULL UNIQUE,
@@ -94,7 +117,271 @@ pub async fn init_db() -> DbPool {
             created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
             FOREIGN KEY(session_id) REFERENCES chat_sessions(id) ON DELETE CASCADE
         )"#,
+        r#"CREATE TABLE IF NOT EXISTS chat_folders (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            name TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(user_id, name),
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS app_settings (
+            key TEXT PRIMARY KEY,
+            value TEXT NOT NULL,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS session_shares (
+            id TEXT PRIMARY KEY,
+            session_id TEXT NOT NULL,
+            token TEXT NOT NULL UNIQUE,
+            created_by TEXT NOT NULL,
+            expires_at TIMESTAMP,
+            revoked BOOLEAN NOT NULL DEFAULT FALSE,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(session_id) REFERENCES chat_sessions(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS personas (
+            id TEXT PRIMARY KEY,
+            slug TEXT NOT NULL UNIQUE,
+            name TEXT NOT NULL,
+            description TEXT,
+            prompt_template TEXT NOT NULL,
+            retrieval_filter TEXT,
+            is_active BOOLEAN NOT NULL DEFAULT TRUE,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS challenges (
+            id TEXT PRIMARY KEY,
+            title TEXT NOT NULL,
+            category TEXT NOT NULL,
+            difficulty TEXT NOT NULL DEFAULT 'easy',
+            description TEXT NOT NULL,
+            points INTEGER NOT NULL DEFAULT 100,
+            flag_hash TEXT NOT NULL,
+            is_published BOOLEAN NOT NULL DEFAULT FALSE,
+            created_by TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS challenge_hints (
+            id TEXT PRIMARY KEY,
+            challenge_id TEXT NOT NULL,
+            position INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            UNIQUE(challenge_id, position),
+            FOREIGN KEY(challenge_id) REFERENCES challenges(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS challenge_attachments (
+            id TEXT PRIMARY KEY,
+            challenge_id TEXT NOT NULL,
+            filename TEXT NOT NULL,
+            storage_path TEXT NOT NULL,
+            size_bytes BIGINT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(challenge_id) REFERENCES challenges(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS challenge_hint_unlocks (
+            user_id TEXT NOT NULL,
+            hint_id TEXT NOT NULL,
+            challenge_id TEXT NOT NULL,
+            unlocked_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY(user_id, hint_id),
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE,
+            FOREIGN KEY(hint_id) REFERENCES challenge_hints(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS challenge_submissions (
+            id TEXT PRIMARY KEY,
+            challenge_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            correct BOOLEAN NOT NULL,
+            submitted_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(challenge_id) REFERENCES challenges(id) ON DELETE CASCADE,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE INDEX IF NOT EXISTS challenge_submissions_rate_idx ON challenge_submissions (user_id, challenge_id, submitted_at)"#,
+        r#"CREATE TABLE IF NOT EXISTS challenge_solves (
+            id TEXT PRIMARY KEY,
+            challenge_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            points_awarded INTEGER NOT NULL,
+            hints_used INTEGER NOT NULL DEFAULT 0,
+            assist_level INTEGER,
+            solved_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            UNIQUE(challenge_id, user_id),
+            FOREIGN KEY(challenge_id) REFERENCES challenges(id) ON DELETE CASCADE,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS chat_attachments (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            session_id TEXT,
+            message_id TEXT,
+            filename TEXT NOT NULL,
+            file_type TEXT NOT NULL,
+            size_bytes BIGINT NOT NULL,
+            sha256 TEXT NOT NULL,
+            storage_path TEXT NOT NULL,
+            report TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE,
+            FOREIGN KEY(session_id) REFERENCES chat_sessions(id) ON DELETE CASCADE,
+            FOREIGN KEY(message_id) REFERENCES chat_messages(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS code_executions (
+            id TEXT PRIMARY KEY,
+            user_id TEXT NOT NULL,
+            session_id TEXT,
+            message_id TEXT,
+            trigger TEXT NOT NULL,
+            language TEXT NOT NULL,
+            code TEXT NOT NULL,
+            stdout TEXT NOT NULL DEFAULT '',
+            stderr TEXT NOT NULL DEFAULT '',
+            exit_code INTEGER,
+            timed_out BOOLEAN NOT NULL DEFAULT FALSE,
+            duration_ms BIGINT NOT NULL DEFAULT 0,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE,
+            FOREIGN KEY(session_id) REFERENCES chat_sessions(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS import_jobs (
+            id TEXT PRIMARY KEY,
+            uploaded_by TEXT,
+            filename TEXT NOT NULL,
+            storage_path TEXT NOT NULL,
+            status TEXT NOT NULL DEFAULT 'queued',
+            total_records BIGINT,
+            processed_records BIGINT NOT NULL DEFAULT 0,
+            imported_count BIGINT NOT NULL DEFAULT 0,
+            error_count BIGINT NOT NULL DEFAULT 0,
+            checkpoint_offset BIGINT NOT NULL DEFAULT 0,
+            checkpoint_record BIGINT NOT NULL DEFAULT 0,
+            error TEXT,
+            cancel_requested BOOLEAN NOT NULL DEFAULT FALSE,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            started_at TIMESTAMP,
+            finished_at TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(uploaded_by) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS document_versions (
+            document_id TEXT NOT NULL,
+            version INTEGER NOT NULL,
+            content TEXT NOT NULL,
+            metadata TEXT,
+            action TEXT NOT NULL,
+            restored_from INTEGER,
+            author_id TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (document_id, version),
+            FOREIGN KEY(document_id) REFERENCES documents(id) ON DELETE CASCADE,
+            FOREIGN KEY(author_id) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS near_duplicate_dismissals (
+            first_id TEXT NOT NULL,
+            second_id TEXT NOT NULL,
+            dismissed_by TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (first_id, second_id),
+            FOREIGN KEY(first_id) REFERENCES documents(id) ON DELETE CASCADE,
+            FOREIGN KEY(second_id) REFERENCES documents(id) ON DELETE CASCADE,
+            FOREIGN KEY(dismissed_by) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS import_adapters (
+            id TEXT PRIMARY KEY,
+            slug TEXT NOT NULL UNIQUE,
+            name TEXT NOT NULL,
+            description TEXT,
+            config TEXT NOT NULL,
+            created_by TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(created_by) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS import_errors (
+            job_id TEXT NOT NULL,
+            record_index BIGINT NOT NULL,
+            byte_offset BIGINT NOT NULL,
+            reason TEXT NOT NULL,
+            snippet TEXT NOT NULL,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY(job_id, record_index),
+            FOREIGN KEY(job_id) REFERENCES import_jobs(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS kb_collections (
+            id TEXT PRIMARY KEY,
+            slug TEXT NOT NULL UNIQUE,
+            name TEXT NOT NULL,
+            description TEXT,
+            visibility TEXT NOT NULL DEFAULT 'public',
+            roles TEXT[] NOT NULL DEFAULT '{}',
+            created_by TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(created_by) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
+        r#"INSERT INTO kb_collections (id, slug, name, description, visibility)
+           VALUES ('general', 'general', 'General', 'Default collection, readable by everyone.', 'public')
+           ON CONFLICT (id) DO NOTHING"#,
+        r#"CREATE TABLE IF NOT EXISTS user_groups (
+            id TEXT PRIMARY KEY,
+            name TEXT NOT NULL UNIQUE,
+            description TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS user_group_members (
+            group_id TEXT NOT NULL,
+            user_id TEXT NOT NULL,
+            added_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY(group_id, user_id),
+            FOREIGN KEY(group_id) REFERENCES user_groups(id) ON DELETE CASCADE,
+            FOREIGN KEY(user_id) REFERENCES users(id) ON DELETE CASCADE
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS kb_collection_grants (
+            collection_id TEXT NOT NULL,
+            principal_type TEXT NOT NULL,
+            principal_id TEXT NOT NULL,
+            created_by TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY(collection_id, principal_type, principal_id),
+            FOREIGN KEY(collection_id) REFERENCES kb_collections(id) ON DELETE CASCADE,
+            FOREIGN KEY(created_by) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
+        r#"CREATE INDEX IF NOT EXISTS user_group_members_user_idx ON user_group_members (user_id)"#,
+        r#"CREATE TABLE IF NOT EXISTS kb_state (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            generation BIGINT NOT NULL DEFAULT 0,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )"#,
+        r#"INSERT INTO kb_state (id) VALUES (1) ON CONFLICT (id) DO NOTHING"#,
+        response_cache_table.as_str(),
+        r#"CREATE TABLE IF NOT EXISTS embedding_state (
+            id INTEGER PRIMARY KEY CHECK (id = 1),
+            model TEXT NOT NULL,
+            dim INTEGER NOT NULL,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+        )"#,
+        r#"CREATE TABLE IF NOT EXISTS reindex_jobs (
+            id TEXT PRIMARY KEY,
+            source_model TEXT NOT NULL,
+            target_model TEXT NOT NULL,
+            target_dim INTEGER NOT NULL,
+            status TEXT NOT NULL DEFAULT 'queued',
+            total_documents BIGINT,
+            embedded_documents BIGINT NOT NULL DEFAULT 0,
+            error TEXT,
+            cancel_requested BOOLEAN NOT NULL DEFAULT FALSE,
+            requested_by TEXT,
+            created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            started_at TIMESTAMP,
+            finished_at TIMESTAMP,
+            updated_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+            FOREIGN KEY(requested_by) REFERENCES users(id) ON DELETE SET NULL
+        )"#,
         r#"CREATE INDEX IF NOT EXISTS documents_embedding_idx ON documents USING hnsw (embedding vector_cosine_ops)"#,
+        r#"CREATE INDEX IF NOT EXISTS response_cache_embedding_idx ON response_cache USING hnsw (embedding vector_cosine_ops)"#,
+        r#"CREATE INDEX IF NOT EXISTS chat_messages_content_fts_idx ON chat_messages USING gin (to_tsvector('english', content))"#,
+        r#"CREATE INDEX IF NOT EXISTS chat_sessions_user_updated_idx ON chat_sessions (user_id, updated_at DESC)"#,
     ];
 
     for query in queries {
@@ -115,5 +402,121 @@ pub async fn init_db() -> DbPool {
         .await
         .ok();
 
+    sqlx::query("ALTER TABLE chat_sessions ADD COLUMN IF NOT EXISTS model TEXT")
+        .execute(&pool)
+        .await
+        .ok();
+
+    sqlx::query("ALTER TABLE chat_messages ADD COLUMN IF NOT EXISTS sources TEXT")
+        .execute(&pool)
+        .await
+        .ok();
+
+    chat_session_columns(&pool).await;
+    document_columns(&pool).await;
+    import_job_columns(&pool).await;
+    retrieval_columns(&pool).await;
+
+    // Staff-only collection. On first creation, the seeded incident-response procedure moves
+    // into it; until then every user could retrieve it.
+    let internal = sqlx::query(
+        r#"INSERT INTO kb_collections (id, slug, name, description, visibility, roles)
+           VALUES ('internal', 'internal', 'Internal', 'Internal procedures, readable by staff.', 'roles', ARRAY['admin', 'editor'])
+           ON CONFLICT (id) DO NOTHING"#,
+    )
+    .execute(&pool)
+    .await;
+    if matches!(internal, Ok(r) if r.rows_affected() == 1) {
+        sqlx::query("UPDATE documents SET collection_id = 'internal' WHERE collection_id = 'general' AND content LIKE 'Incident Response Plan: %'")
+            .execute(&pool)
+            .await
+            .ok();
+    }
+
+    // Content hashes for rows written before deduplication; the normalization matches `rag::content_hash`.
+    // Existing copies are kept but marked, so the unique index only covers one original per hash.
+    let dedup = [
+        r#"UPDATE documents SET content_hash = encode(sha256(convert_to(btrim(regexp_replace(content, '[ \t\n\r\f]+', ' ', 'g'), ' '), 'UTF8')), 'hex')
+           WHERE content_hash IS NULL"#,
+        r#"UPDATE documents d SET duplicate_of = o.keep
+           FROM (SELECT content_hash, MIN(id) AS keep FROM documents WHERE duplicate_of IS NULL GROUP BY content_hash HAVING COUNT(*) > 1) o
+           WHERE d.content_hash = o.content_hash AND d.duplicate_of IS NULL AND d.id <> o.keep"#,
+        r#"DROP INDEX IF EXISTS documents_content_md5_idx"#,
+        // Documents in the trash do not block new uploads of the same content or key
+        r#"DROP INDEX IF EXISTS documents_content_hash_key"#,
+        r#"DROP INDEX IF EXISTS documents_external_id_key"#,
+        r#"CREATE UNIQUE INDEX IF NOT EXISTS documents_live_content_hash_key ON documents (content_hash) WHERE duplicate_of IS NULL AND deleted_at IS NULL"#,
+        r#"CREATE UNIQUE INDEX IF NOT EXISTS documents_live_external_id_key ON documents (external_id) WHERE external_id IS NOT NULL AND deleted_at IS NULL"#,
+    ];
+    for query in dedup {
+        if let Err(e) = sqlx::query(query).execute(&pool).await {
+            eprintln!("Document deduplication migration failed: {}", e);
+        }
+    }
+
     pool
 }
+
+/// Runs additive migrations; each may already have been applied.
+async fn add_columns(pool: &DbPool, queries: &[&str]) {
+    for query in queries {
+        sqlx::query(query)
+            .execute(pool)
+            .await
+            .ok();
+    }
+}
+
+/// Organisation, persona, hint mode and challenge state of chat sessions.
+async fn chat_session_columns(pool: &DbPool) {
+    add_columns(pool, &[
+        "ALTER TABLE chat_sessions ADD COLUMN IF NOT EXISTS pinned BOOLEAN NOT NULL DEFAULT FALSE",
+        "ALTER TABLE chat_sessions ADD COLUMN IF NOT EXISTS archived BOOLEAN NOT NULL DEFAULT FALSE",
+        "ALTER TABLE chat_sessions ADD COLUMN IF NOT EXISTS title_customized BOOLEAN NOT NULL DEFAULT FALSE",
+        "ALTER TABLE chat_sessions ADD COLUMN IF NOT EXISTS folder_id TEXT REFERENCES chat_folders(id) ON DELETE SET NULL",
+        "ALTER TABLE chat_sessions ADD COLUMN IF NOT EXISTS persona_id TEXT REFERENCES personas(id) ON DELETE SET NULL",
+        "ALTER TABLE chat_sessions ADD COLUMN IF NOT EXISTS hint_mode BOOLEAN NOT NULL DEFAULT FALSE",
+        "ALTER TABLE chat_sessions ADD COLUMN IF NOT EXISTS hint_level INTEGER NOT NULL DEFAULT 0",
+        "ALTER TABLE chat_sessions ADD COLUMN IF NOT EXISTS challenge_id TEXT REFERENCES challenges(id) ON DELETE SET NULL",
+    ])
+    .await;
+}
+
+/// Challenge scoping, deduplication, trash, collections and embedding model of documents.
+async fn document_columns(pool: &DbPool) {
+    add_columns(pool, &[
+        "ALTER TABLE documents ADD COLUMN IF NOT EXISTS challenge_id TEXT REFERENCES challenges(id) ON DELETE SET NULL",
+        "ALTER TABLE documents ADD COLUMN IF NOT EXISTS content_hash TEXT",
+        "ALTER TABLE documents ADD COLUMN IF NOT EXISTS external_id TEXT",
+        "ALTER TABLE documents ADD COLUMN IF NOT EXISTS duplicate_of TEXT",
+        "ALTER TABLE documents ADD COLUMN IF NOT EXISTS deleted_at TIMESTAMP",
+        "ALTER TABLE documents ADD COLUMN IF NOT EXISTS deleted_by TEXT REFERENCES users(id) ON DELETE SET NULL",
+        "ALTER TABLE documents ADD COLUMN IF NOT EXISTS collection_id TEXT NOT NULL DEFAULT 'general' REFERENCES kb_collections(id)",
+        "CREATE INDEX IF NOT EXISTS documents_collection_idx ON documents (collection_id)",
+        "ALTER TABLE documents ADD COLUMN IF NOT EXISTS embedding_model TEXT",
+    ])
+    .await;
+}
+
+/// Dry runs, source formats, adapters, conflict policy and target collection of import jobs.
+async fn import_job_columns(pool: &DbPool) {
+    add_columns(pool, &[
+        "ALTER TABLE import_jobs ADD COLUMN IF NOT EXISTS dry_run BOOLEAN NOT NULL DEFAULT FALSE",
+        "ALTER TABLE import_jobs ADD COLUMN IF NOT EXISTS format TEXT NOT NULL DEFAULT 'json'",
+        "ALTER TABLE import_jobs ADD COLUMN IF NOT EXISTS adapter TEXT",
+        "ALTER TABLE import_jobs ADD COLUMN IF NOT EXISTS on_conflict TEXT NOT NULL DEFAULT 'skip'",
+        "ALTER TABLE import_jobs ADD COLUMN IF NOT EXISTS updated_count BIGINT NOT NULL DEFAULT 0",
+        "ALTER TABLE import_jobs ADD COLUMN IF NOT EXISTS collection_id TEXT REFERENCES kb_collections(id) ON DELETE SET NULL",
+    ])
+    .await;
+}
+
+/// Per-persona and per-model retrieval settings, and the collection scope of cached answers.
+async fn retrieval_columns(pool: &DbPool) {
+    add_columns(pool, &[
+        "ALTER TABLE personas ADD COLUMN IF NOT EXISTS retrieval_settings TEXT",
+        "ALTER TABLE ai_models ADD COLUMN IF NOT EXISTS retrieval_settings TEXT",
+        "ALTER TABLE response_cache ADD COLUMN IF NOT EXISTS access_key TEXT",
+    ])
+    .await;
+}
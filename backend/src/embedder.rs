@@ -0,0 +1,290 @@
+use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
+use serde::Serialize;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, RwLock};
+use tokio::sync::{mpsc, oneshot};
+
+/// Model that embedded every document before the model became configurable.
+pub const LEGACY_MODEL: EmbeddingModel = EmbeddingModel::AllMiniLML6V2;
+
+/// A fastembed text model and the vector dimension it produces.
+#[derive(Clone, Debug)]
+pub struct ModelSpec {
+    /// fastembed model code, e.g. `BAAI/bge-small-en-v1.5`. Stored on documents.
+    pub name: String,
+    pub model: EmbeddingModel,
+    pub dim: usize,
+}
+
+#[derive(Serialize)]
+pub struct ModelSummary {
+    pub name: String,
+    pub dim: usize,
+    pub description: String,
+}
+
+impl ModelSpec {
+    /// Looks a model up by fastembed model code or variant name, ignoring case.
+    pub fn resolve(name: &str) -> Result<Self, String> {
+        TextEmbedding::list_supported_models()
+            .into_iter()
+            .find(|info| info.model_code.eq_ignore_ascii_case(name) || format!("{:?}", info.model).eq_ignore_ascii_case(name))
+            .map(|info| Self { name: info.model_code, model: info.model, dim: info.dim })
+            .ok_or_else(|| format!("Unknown embedding model '{}'", name))
+    }
+
+    pub fn from_model(model: &EmbeddingModel) -> Self {
+        let info = TextEmbedding::get_model_info(model).expect("fastembed lists its own models");
+        Self { name: info.model_code.clone(), model: info.model.clone(), dim: info.dim }
+    }
+
+    /// The model named by `EMBEDDING_MODEL`, defaulting to the legacy model.
+    pub fn from_env() -> Result<Self, String> {
+        match std::env::var("EMBEDDING_MODEL") {
+            Ok(name) if !name.trim().is_empty() => Self::resolve(name.trim()),
+            _ => Ok(Self::from_model(&LEGACY_MODEL)),
+        }
+    }
+
+    /// E5, BGE and Nomic models are trained with different prefixes for queries and passages.
+    fn prefixes(&self) -> (&'static str, &'static str) {
+        let name = self.name.to_lowercase();
+        if name.contains("e5-") {
+            ("query: ", "passage: ")
+        } else if name.contains("nomic-embed") {
+            ("search_query: ", "search_document: ")
+        } else if name.contains("bge-") && name.contains("-en") {
+            ("Represent this sentence for searching relevant passages: ", "")
+        } else {
+            ("", "")
+        }
+    }
+}
+
+pub fn supported_models() -> Vec<ModelSummary> {
+    TextEmbedding::list_supported_models()
+        .into_iter()
+        .map(|info| ModelSummary { name: info.model_code, dim: info.dim, description: info.description })
+        .collect()
+}
+
+type EmbedResult = Result<Vec<Vec<f32>>, String>;
+
+struct Job {
+    texts: Vec<String>,
+    reply: oneshot::Sender<EmbedResult>,
+}
+
+#[derive(Default)]
+struct Counters {
+    queued: AtomicUsize,
+    in_flight: AtomicUsize,
+    batches: AtomicU64,
+    texts: AtomicU64,
+    failures: AtomicU64,
+}
+
+#[derive(Serialize)]
+pub struct EmbedderStats {
+    pub model: String,
+    pub dim: usize,
+    pub workers: usize,
+    pub queue_capacity: usize,
+    pub max_batch: usize,
+    pub queued: usize,
+    pub in_flight: usize,
+    pub batches: u64,
+    pub texts: u64,
+    pub failures: u64,
+}
+
+/// The embedding pool serving requests. The re-index job swaps in a pool for a new model at cutover;
+/// callers that store vectors take `current()` once so the vectors and the recorded model agree.
+pub struct Embedder {
+    active: RwLock<Arc<ModelPool>>,
+}
+
+impl Embedder {
+    pub fn new(pool: ModelPool) -> Self {
+        Self { active: RwLock::new(Arc::new(pool)) }
+    }
+
+    pub fn current(&self) -> Arc<ModelPool> {
+        self.active.read().unwrap().clone()
+    }
+
+    /// Replaces the active pool. The old pool's workers exit once its last request completes.
+    pub fn swap(&self, pool: ModelPool) {
+        *self.active.write().unwrap() = Arc::new(pool);
+    }
+
+    pub fn model(&self) -> ModelSpec {
+        self.current().spec.clone()
+    }
+
+    pub async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+        self.current().embed(texts).await
+    }
+
+    pub async fn embed_query(&self, text: &str) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        self.current().embed_query(text).await
+    }
+
+    pub fn is_saturated(&self) -> bool {
+        self.current().is_saturated()
+    }
+
+    pub fn stats(&self) -> EmbedderStats {
+        self.current().stats()
+    }
+}
+
+/// Pool of embedding model instances running on dedicated OS threads.
+/// Callers enqueue jobs on a bounded queue; each worker drains several queued jobs
+/// into a single `embed` call so concurrent chat queries and imports share batches.
+pub struct ModelPool {
+    pub spec: ModelSpec,
+    tx: mpsc::Sender<Job>,
+    counters: Arc<Counters>,
+    workers: usize,
+    queue_capacity: usize,
+    max_batch: usize,
+}
+
+impl ModelPool {
+    /// Sizes come from `EMBED_WORKERS`, `EMBED_QUEUE_CAPACITY` and `EMBED_MAX_BATCH`.
+    pub fn from_env(spec: ModelSpec) -> Self {
+        Self::new(spec, env_size("EMBED_WORKERS", 2), env_size("EMBED_QUEUE_CAPACITY", 256), env_size("EMBED_MAX_BATCH", 64))
+    }
+
+    pub fn new(spec: ModelSpec, workers: usize, queue_capacity: usize, max_batch: usize) -> Self {
+        let (tx, rx) = mpsc::channel::<Job>(queue_capacity);
+        let rx = Arc::new(Mutex::new(rx));
+        let counters = Arc::new(Counters::default());
+
+        for i in 0..workers {
+            let options = InitOptions::new(spec.model.clone())
+                .with_show_download_progress(i == 0);
+            let model = TextEmbedding::try_new(options)
+                .unwrap_or_else(|e| panic!("Failed to load embedding model {}: {}", spec.name, e));
+            let rx = rx.clone();
+            let counters = counters.clone();
+            std::thread::Builder::new()
+                .name(format!("embed-worker-{}", i))
+                .spawn(move || worker_loop(model, rx, counters, max_batch))
+                .expect("Failed to spawn embedding worker");
+        }
+
+        Self { spec, tx, counters, workers, queue_capacity, max_batch }
+    }
+
+    /// Embeds `texts` as passages (documents) in one job. Waits for queue space when the pool is saturated.
+    pub async fn embed(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+        let prefix = self.spec.prefixes().1;
+        let texts = if prefix.is_empty() { texts } else { texts.into_iter().map(|t| format!("{}{}", prefix, t)).collect() };
+        self.submit(texts).await
+    }
+
+    /// Embeds a search query, which some models expect to be marked differently from passages.
+    pub async fn embed_query(&self, text: &str) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        let mut embeddings = self.submit(vec![format!("{}{}", self.spec.prefixes().0, text)]).await?;
+        embeddings.pop().ok_or_else(|| "empty embedding result".into())
+    }
+
+    async fn submit(&self, texts: Vec<String>) -> Result<Vec<Vec<f32>>, Box<dyn std::error::Error>> {
+        if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+        let (reply, rx) = oneshot::channel();
+        self.counters.queued.fetch_add(1, Ordering::Relaxed);
+        if self.tx.send(Job { texts, reply }).await.is_err() {
+            self.counters.queued.fetch_sub(1, Ordering::Relaxed);
+            return Err("embedding workers stopped".into());
+        }
+        Ok(rx.await.map_err(|_| "embedding worker dropped the request")??)
+    }
+
+    pub async fn embed_one(&self, text: &str) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        let mut embeddings = self.embed(vec![text.to_string()]).await?;
+        embeddings.pop().ok_or_else(|| "empty embedding result".into())
+    }
+
+    /// Jobs waiting for a worker; imports use this to throttle themselves.
+    pub fn queue_depth(&self) -> usize {
+        self.counters.queued.load(Ordering::Relaxed)
+    }
+
+    /// True when most of the queue is in use, so bulk work should yield to interactive requests.
+    pub fn is_saturated(&self) -> bool {
+        self.queue_depth() * 4 >= self.queue_capacity * 3
+    }
+
+    pub fn stats(&self) -> EmbedderStats {
+        EmbedderStats {
+            model: self.spec.name.clone(),
+            dim: self.spec.dim,
+            workers: self.workers,
+            queue_capacity: self.queue_capacity,
+            max_batch: self.max_batch,
+            queued: self.queue_depth(),
+            in_flight: self.counters.in_flight.load(Ordering::Relaxed),
+            batches: self.counters.batches.load(Ordering::Relaxed),
+            texts: self.counters.texts.load(Ordering::Relaxed),
+            failures: self.counters.failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+fn env_size(key: &str, default: usize) -> usize {
+    std::env::var(key).ok().and_then(|v| v.parse().ok()).filter(|v| *v > 0).unwrap_or(default)
+}
+
+fn worker_loop(mut model: TextEmbedding, rx: Arc<Mutex<mpsc::Receiver<Job>>>, counters: Arc<Counters>, max_batch: usize) {
+    loop {
+        // Only one idle worker waits on the queue; the others wait for the lock
+        let mut jobs = {
+            let mut rx = rx.lock().unwrap();
+            let first = match rx.blocking_recv() {
+                Some(job) => job,
+                None => return,
+            };
+            let mut size = first.texts.len();
+            let mut jobs = vec![first];
+            while size < max_batch {
+                match rx.try_recv() {
+                    Ok(job) => {
+                        size += job.texts.len();
+                        jobs.push(job);
+                    }
+                    Err(_) => break,
+                }
+            }
+            jobs
+        };
+        counters.queued.fetch_sub(jobs.len(), Ordering::Relaxed);
+        counters.in_flight.fetch_add(jobs.len(), Ordering::Relaxed);
+
+        let count = jobs.len();
+        let sizes: Vec<usize> = jobs.iter().map(|j| j.texts.len()).collect();
+        let texts: Vec<String> = jobs.iter_mut().flat_map(|j| std::mem::take(&mut j.texts)).collect();
+        let total = texts.len();
+        match model.embed(texts, Some(max_batch)) {
+            Ok(mut embeddings) => {
+                for (job, size) in jobs.into_iter().zip(sizes) {
+                    let own: Vec<Vec<f32>> = embeddings.drain(..size).collect();
+                    let _ = job.reply.send(Ok(own));
+                }
+            }
+            Err(e) => {
+                counters.failures.fetch_add(1, Ordering::Relaxed);
+                for job in jobs {
+                    let _ = job.reply.send(Err(e.to_string()));
+                }
+            }
+        }
+
+        counters.in_flight.fetch_sub(count, Ordering::Relaxed);
+        counters.batches.fetch_add(1, Ordering::Relaxed);
+        counters.texts.fetch_add(total as u64, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,251 @@
+use crate::models::{ChatMessage, ChatSession, ExportedMessage, SessionExport};
+use std::io::Write;
+
+pub const EXPORT_VERSION: u32 = 1;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum ExportFormat {
+    Markdown,
+    Json,
+    Html,
+}
+
+impl ExportFormat {
+    pub fn parse(value: Option<&str>) -> Option<Self> {
+        match value.unwrap_or("md") {
+            "md" | "markdown" => Some(Self::Markdown),
+            "json" => Some(Self::Json),
+            "html" => Some(Self::Html),
+            _ => None,
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Markdown => "md",
+            Self::Json => "json",
+            Self::Html => "html",
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            Self::Markdown => "text/markdown; charset=utf-8",
+            Self::Json => "application/json",
+            Self::Html => "text/html; charset=utf-8",
+        }
+    }
+}
+
+pub fn to_export(session: &ChatSession, messages: &[ChatMessage]) -> SessionExport {
+    SessionExport {
+        version: EXPORT_VERSION,
+        title: session.title.clone(),
+        model: session.model.clone(),
+        created_at: session.created_at,
+        messages: messages
+            .iter()
+            .map(|m| ExportedMessage {
+                role: m.role.clone(),
+                content: m.content.clone(),
+                created_at: m.created_at,
+                sources: m.sources.as_ref().and_then(|s| serde_json::from_str(s).ok()),
+            })
+            .collect(),
+    }
+}
+
+pub fn render(format: ExportFormat, export: &SessionExport) -> String {
+    match format {
+        ExportFormat::Markdown => render_markdown(export),
+        ExportFormat::Json => serde_json::to_string_pretty(export).unwrap_or_default(),
+        ExportFormat::Html => render_html(export),
+    }
+}
+
+/// File name safe slug of the session title, used for downloads and zip entries.
+pub fn file_stem(session: &ChatSession) -> String {
+    let title = session.title.as_deref().unwrap_or("session");
+    let slug: String = title
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '-' })
+        .collect();
+    let slug = slug.split('-').filter(|p| !p.is_empty()).collect::<Vec<_>>().join("-");
+    let short_id: String = session.id.chars().take(8).collect();
+    if slug.is_empty() {
+        short_id
+    } else {
+        format!("{}-{}", slug, short_id)
+    }
+}
+
+fn role_label(role: &str) -> &str {
+    match role {
+        "user" => "User",
+        "assistant" => "Krypton",
+        "system" => "System",
+        other => other,
+    }
+}
+
+fn format_timestamp(ts: Option<chrono::NaiveDateTime>) -> String {
+    ts.map(|t| t.format("%Y-%m-%d %H:%M:%S UTC").to_string()).unwrap_or_default()
+}
+
+fn source_lines(sources: &Option<serde_json::Value>) -> Vec<String> {
+    let Some(serde_json::Value::Array(items)) = sources else {
+        return Vec::new();
+    };
+    items
+        .iter()
+        .map(|s| {
+            let id = s["id"].as_str().unwrap_or("unknown");
+            let score = s["score"].as_f64().unwrap_or(0.0);
+            match s["preview"].as_str() {
+                Some(preview) => format!("`{}` (score {:.2}): {}", id, score, preview),
+                None => format!("`{}` (score {:.2})", id, score),
+            }
+        })
+        .collect()
+}
+
+fn render_markdown(export: &SessionExport) -> String {
+    let mut out = String::new();
+    out.push_str(&format!("# {}\n\n", export.title.as_deref().unwrap_or("Krypton Session")));
+    if let Some(model) = &export.model {
+        out.push_str(&format!("- **Model**: {}\n", model));
+    }
+    out.push_str(&format!("- **Created**: {}\n\n---\n\n", format_timestamp(export.created_at)));
+
+    for msg in &export.messages {
+        out.push_str(&format!("### {} — {}\n\n", role_label(&msg.role), format_timestamp(msg.created_at)));
+        // Content is already markdown, so code blocks carry over unchanged
+        out.push_str(msg.content.trim_end());
+        out.push_str("\n\n");
+        let sources = source_lines(&msg.sources);
+        if !sources.is_empty() {
+            out.push_str("**Sources**\n\n");
+            for line in sources {
+                out.push_str(&format!("- {}\n", line));
+            }
+            out.push('\n');
+        }
+    }
+    out
+}
+
+pub fn escape_html(input: &str) -> String {
+    input
+        .replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&#39;")
+}
+
+fn flush_text(buffer: &mut Vec<&str>, html: &mut String) {
+    let text = buffer.join("\n");
+    for para in text.split("\n\n").filter(|p| !p.trim().is_empty()) {
+        html.push_str(&format!("<p>{}</p>\n", escape_html(para.trim()).replace('\n', "<br>")));
+    }
+    buffer.clear();
+}
+
+/// Renders fenced code blocks as `<pre><code>` and everything else as escaped paragraphs.
+fn content_to_html(content: &str) -> String {
+    let mut html = String::new();
+    let mut in_code = false;
+    let mut buffer: Vec<&str> = Vec::new();
+
+    for line in content.lines() {
+        if let Some(lang) = line.trim_start().strip_prefix("```") {
+            if in_code {
+                html.push_str(&format!("{}</code></pre>\n", escape_html(&buffer.join("\n"))));
+                buffer.clear();
+            } else {
+                flush_text(&mut buffer, &mut html);
+                let lang = lang.trim();
+                if lang.is_empty() {
+                    html.push_str("<pre><code>");
+                } else {
+                    html.push_str(&format!("<pre><code class=\"language-{}\">", escape_html(lang)));
+                }
+            }
+            in_code = !in_code;
+        } else {
+            buffer.push(line);
+        }
+    }
+
+    if in_code {
+        html.push_str(&format!("{}</code></pre>\n", escape_html(&buffer.join("\n"))));
+    } else {
+        flush_text(&mut buffer, &mut html);
+    }
+    html
+}
+
+fn render_html(export: &SessionExport) -> String {
+    let title = escape_html(export.title.as_deref().unwrap_or("Krypton Session"));
+    let mut body = String::new();
+
+    for msg in &export.messages {
+        body.push_str(&format!(
+            "<section class=\"msg {}\">\n<h3>{} <small>{}</small></h3>\n{}",
+            escape_html(&msg.role),
+            escape_html(role_label(&msg.role)),
+            format_timestamp(msg.created_at),
+            content_to_html(&msg.content)
+        ));
+        let sources = source_lines(&msg.sources);
+        if !sources.is_empty() {
+            body.push_str("<details><summary>Sources</summary><ul>\n");
+            for line in sources {
+                body.push_str(&format!("<li>{}</li>\n", escape_html(&line)));
+            }
+            body.push_str("</ul></details>\n");
+        }
+        body.push_str("</section>\n");
+    }
+
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+  <meta charset="utf-8">
+  <title>{}</title>
+  <style>
+    body {{ font-family: Arial, sans-serif; max-width: 860px; margin: 40px auto; color: #222; }}
+    .msg {{ border-left: 3px solid #ccc; padding: 0 16px; margin-bottom: 24px; }}
+    .msg.assistant {{ border-color: #00aa00; }}
+    small {{ color: #888; font-weight: normal; }}
+    pre {{ background: #111; color: #eee; padding: 12px; overflow-x: auto; }}
+  </style>
+</head>
+<body>
+<h1>{}</h1>
+<p>Model: {} &middot; Created: {}</p>
+{}</body>
+</html>
+"#,
+        title,
+        title,
+        escape_html(export.model.as_deref().unwrap_or("unknown")),
+        format_timestamp(export.created_at),
+        body
+    )
+}
+
+/// Bundles pre-rendered sessions into an in-memory zip archive.
+pub fn build_zip(files: Vec<(String, String)>) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut zip = zip::ZipWriter::new(std::io::Cursor::new(Vec::new()));
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated);
+
+    for (name, content) in files {
+        zip.start_file(name, options)?;
+        zip.write_all(content.as_bytes())?;
+    }
+
+    Ok(zip.finish()?.into_inner())
+}
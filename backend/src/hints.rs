@@ -0,0 +1,156 @@
+use regex::Regex;
+
+/// Progressive hint ladder for sessions in hint mode. The user escalates explicitly,
+/// one level at a time; flags are only revealed at `Solution`.
+#[derive(Clone, Copy, PartialEq, PartialOrd, Debug)]
+pub enum HintLevel {
+    Nudge = 0,
+    Direction = 1,
+    Technique = 2,
+    Solution = 3,
+}
+
+impl HintLevel {
+    pub fn from_i32(level: i32) -> Self {
+        match level {
+            i32::MIN..=0 => Self::Nudge,
+            1 => Self::Direction,
+            2 => Self::Technique,
+            _ => Self::Solution,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Nudge => "nudge",
+            Self::Direction => "direction",
+            Self::Technique => "technique",
+            Self::Solution => "solution",
+        }
+    }
+
+    pub fn next(&self) -> Self {
+        Self::from_i32(*self as i32 + 1)
+    }
+
+    pub fn prompt_instructions(&self) -> &'static str {
+        match self {
+            Self::Nudge => "The user is in HINT MODE at level 1/4 (nudge). Give a single short nudge that points at what to look at next. Do not name the vulnerability class, the tool to use or any part of the solution. Never reveal flags.",
+            Self::Direction => "The user is in HINT MODE at level 2/4 (direction). You may name the general area or vulnerability class to investigate, but do not explain how to exploit it and do not give commands, payloads or code. Never reveal flags.",
+            Self::Technique => "The user is in HINT MODE at level 3/4 (technique). Explain the technique and the tools to apply, with generic examples, but do not give the exact payload, the final steps or the flag.",
+            Self::Solution => "The user has unlocked the full solution. You may give the complete walkthrough, including the flag if it is known.",
+        }
+    }
+}
+
+/// Default flag shapes, used until an admin overrides the `flag_patterns` setting.
+/// Any word prefix is included, so formats such as `picoCTF{..}` and `DUCTF{..}` match whole.
+pub const DEFAULT_FLAG_PATTERNS: &str = r#"["(?i)\\w*(?:ctf|flag|krypton|htb|thm)\\{[^}\\n]{1,200}\\}"]"#;
+
+/// Compiles the JSON array of regexes stored in the `flag_patterns` setting.
+/// Invalid patterns are logged and skipped so a typo can't disable redaction entirely.
+pub fn compile_patterns(raw: &str) -> Vec<Regex> {
+    let patterns: Vec<String> = serde_json::from_str(raw).unwrap_or_else(|_| {
+        eprintln!("Invalid flag_patterns setting, falling back to defaults");
+        serde_json::from_str(DEFAULT_FLAG_PATTERNS).unwrap_or_default()
+    });
+
+    patterns
+        .iter()
+        .filter_map(|p| match Regex::new(p) {
+            Ok(re) => Some(re),
+            Err(e) => {
+                eprintln!("Skipping invalid flag pattern {}: {}", p, e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Strict check for a new `flag_patterns` value: a non-empty JSON array of non-empty, valid regexes.
+/// An empty array would turn hint-mode redaction off entirely.
+pub fn validate_patterns(raw: &str) -> Result<(), String> {
+    let patterns: Vec<String> = serde_json::from_str(raw).map_err(|_| "flag_patterns must be a JSON array of strings".to_string())?;
+    if patterns.is_empty() {
+        return Err("flag_patterns must contain at least one pattern".to_string());
+    }
+    for pattern in &patterns {
+        if pattern.is_empty() {
+            return Err("flag patterns must not be empty".to_string());
+        }
+        Regex::new(pattern).map_err(|e| format!("invalid flag pattern {}: {}", pattern, e))?;
+    }
+    Ok(())
+}
+
+/// Replaces every flag-shaped string with a placeholder. Returns the redacted text
+/// and the number of replacements made.
+pub fn redact_flags(text: &str, patterns: &[Regex]) -> (String, usize) {
+    let mut redacted = text.to_string();
+    let mut count = 0;
+    for re in patterns {
+        let matches = re.find_iter(&redacted).count();
+        if matches > 0 {
+            count += matches;
+            redacted = re.replace_all(&redacted, "[REDACTED FLAG]").into_owned();
+        }
+    }
+    (redacted, count)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn redact(text: &str) -> (String, usize) {
+        redact_flags(text, &compile_patterns(DEFAULT_FLAG_PATTERNS))
+    }
+
+    #[test]
+    fn default_patterns_compile() {
+        assert_eq!(compile_patterns(DEFAULT_FLAG_PATTERNS).len(), 1);
+    }
+
+    #[test]
+    fn validate_rejects_bad_patterns() {
+        assert!(validate_patterns(DEFAULT_FLAG_PATTERNS).is_ok());
+        assert!(validate_patterns(r#"["flag("]"#).is_err());
+        assert!(validate_patterns(r#"[""]"#).is_err());
+        assert!(validate_patterns("[]").is_err());
+        assert!(validate_patterns("flag{.*}").is_err());
+    }
+
+    #[test]
+    fn redacts_common_flag_formats() {
+        for flag in ["flag{s3cr3t}", "CTF{abc}", "picoCTF{b4s3_64}", "HTB{r00t3d}", "DUCTF{x_y}", "THM{1}", "krypton{k}"] {
+            let (redacted, count) = redact(&format!("The answer is {} here.", flag));
+            assert_eq!(count, 1, "{} was not redacted", flag);
+            assert_eq!(redacted, "The answer is [REDACTED FLAG] here.");
+        }
+    }
+
+    #[test]
+    fn leaves_ordinary_braces_alone() {
+        let text = "struct Foo{ a: i32 } and format!(\"{}\", x)";
+        assert_eq!(redact(text), (text.to_string(), 0));
+    }
+
+    #[test]
+    fn counts_every_match() {
+        let (redacted, count) = redact("flag{one} then flag{two}");
+        assert_eq!(count, 2);
+        assert_eq!(redacted, "[REDACTED FLAG] then [REDACTED FLAG]");
+    }
+
+    #[test]
+    fn invalid_setting_falls_back_to_defaults() {
+        assert_eq!(compile_patterns("not json").len(), 1);
+    }
+
+    #[test]
+    fn invalid_patterns_are_skipped() {
+        let patterns = compile_patterns(r#"["(unclosed", "secret\\{[^}]+\\}"]"#);
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(redact_flags("secret{x}", &patterns).1, 1);
+    }
+}
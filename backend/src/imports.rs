@@ -0,0 +1,463 @@
+use crate::adapters::{self, Categorizer};
+use crate::loaders::{self, SourceFormat};
+use crate::models::{AdapterConfig, CreateDocumentRequest, ImportJob};
+use crate::rag::{content_hash, ConflictPolicy, RagSystem};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Seek, SeekFrom};
+use std::sync::Arc;
+
+/// Records read from the file between two checkpoints.
+const CHUNK_RECORDS: usize = 512;
+/// Raw text kept per rejected record in the error report.
+const SNIPPET_CHARS: usize = 500;
+
+/// One record read from an import file, or the reason it could not be parsed.
+pub struct RawRecord {
+    pub index: i64,
+    pub offset: u64,
+    /// On parse errors, the parser message and the raw text found at `offset`.
+    pub value: Result<Value, (String, String)>,
+}
+
+/// A record that was not (or, in a dry run, would not be) imported.
+pub struct Rejection {
+    pub index: i64,
+    pub offset: u64,
+    pub reason: String,
+    pub snippet: String,
+}
+
+fn truncate(text: &str) -> String {
+    text.chars().take(SNIPPET_CHARS).collect()
+}
+
+/// Streams records from a JSON array or a JSON Lines / concatenated-objects file
+/// without loading it into memory, tracking the byte offset of every record.
+pub struct RecordReader {
+    reader: BufReader<File>,
+    is_array: bool,
+    array_opened: bool,
+    next_index: i64,
+    done: bool,
+}
+
+impl RecordReader {
+    /// Opens `path` positioned at `offset`, where `index` is the number of records before it.
+    pub fn open(path: &str, offset: u64, index: i64) -> std::io::Result<RecordReader> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let is_array = loop {
+            let buf = reader.fill_buf()?;
+            match buf.iter().position(|b| !b.is_ascii_whitespace()) {
+                Some(i) => break buf[i] == b'[',
+                None if buf.is_empty() => break false,
+                None => {
+                    let len = buf.len();
+                    reader.consume(len);
+                }
+            }
+        };
+        reader.seek(SeekFrom::Start(offset))?;
+        Ok(RecordReader { reader, is_array, array_opened: offset > 0, next_index: index, done: false })
+    }
+
+    /// Byte offset of the next unread record.
+    pub fn position(&mut self) -> u64 {
+        self.reader.stream_position().unwrap_or(0)
+    }
+
+    pub fn next_index(&self) -> i64 {
+        self.next_index
+    }
+
+    /// Skips whitespace and separators; returns false at the end of the records.
+    fn seek_next_record(&mut self) -> std::io::Result<bool> {
+        loop {
+            let buf = self.reader.fill_buf()?;
+            let Some(&byte) = buf.first() else { return Ok(false) };
+            match byte {
+                b' ' | b'\t' | b'\r' | b'\n' | b',' => self.reader.consume(1),
+                b'[' if self.is_array && !self.array_opened => {
+                    self.array_opened = true;
+                    self.reader.consume(1);
+                }
+                b']' if self.is_array => return Ok(false),
+                _ => return Ok(true),
+            }
+        }
+    }
+
+    pub fn next_record(&mut self) -> Option<RawRecord> {
+        if self.done {
+            return None;
+        }
+        match self.seek_next_record() {
+            Ok(true) => {}
+            Ok(false) => {
+                self.done = true;
+                return None;
+            }
+            Err(e) => {
+                self.done = true;
+                return Some(RawRecord { index: self.next_index, offset: self.position(), value: Err((e.to_string(), String::new())) });
+            }
+        }
+
+        let offset = self.position();
+        let index = self.next_index;
+        self.next_index += 1;
+
+        let mut de = serde_json::Deserializer::from_reader(&mut self.reader);
+        match Value::deserialize(&mut de) {
+            Ok(value) => Some(RawRecord { index, offset, value: Ok(value) }),
+            Err(e) => {
+                let snippet = self.raw_snippet(offset);
+                Some(RawRecord { index, offset, value: Err((e.to_string(), snippet)) })
+            }
+        }
+    }
+
+    /// Re-reads the raw text of a malformed record and moves past it. JSON Lines input resumes at
+    /// the next line; inside an array there is no reliable boundary, so reading stops.
+    fn raw_snippet(&mut self, offset: u64) -> String {
+        if self.reader.seek(SeekFrom::Start(offset)).is_err() {
+            self.done = true;
+            return String::new();
+        }
+        let mut raw = Vec::new();
+        if self.is_array {
+            self.done = true;
+            let _ = (&mut self.reader).take(SNIPPET_CHARS as u64 * 4).read_to_end(&mut raw);
+        } else {
+            let _ = self.reader.read_until(b'\n', &mut raw);
+        }
+        truncate(String::from_utf8_lossy(&raw).trim_end())
+    }
+
+    pub fn next_chunk(&mut self, max: usize) -> Vec<RawRecord> {
+        let mut records = Vec::with_capacity(max);
+        while records.len() < max {
+            match self.next_record() {
+                Some(r) => records.push(r),
+                None => break,
+            }
+        }
+        records
+    }
+}
+
+/// Re-queues jobs interrupted by a restart.
+pub async fn resume_pending(rag: Arc<RagSystem>) {
+    let pending: Vec<(String,)> = sqlx::query_as("SELECT id FROM import_jobs WHERE status IN ('queued', 'running') ORDER BY created_at ASC")
+        .fetch_all(&rag.pool)
+        .await
+        .unwrap_or_default();
+
+    for (id,) in pending {
+        println!("Resuming import job {}", id);
+        tokio::spawn(run_job(rag.clone(), id));
+    }
+}
+
+/// Runs an import job to completion, cancellation or failure, recording the outcome on the job row.
+pub async fn run_job(rag: Arc<RagSystem>, job_id: String) {
+    let job = match sqlx::query_as::<_, ImportJob>("SELECT * FROM import_jobs WHERE id = $1")
+        .bind(&job_id)
+        .fetch_optional(&rag.pool)
+        .await
+    {
+        Ok(Some(job)) => job,
+        _ => return,
+    };
+    if !matches!(job.status.as_str(), "queued" | "running") {
+        return;
+    }
+
+    let records_path = match prepare_records(&job).await {
+        Ok(path) => path,
+        Err(e) => {
+            eprintln!("Import job {} failed: {}", job_id, e);
+            let _ = sqlx::query("UPDATE import_jobs SET status = 'failed', error = $2, finished_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+                .bind(&job_id)
+                .bind(e)
+                .execute(&rag.pool)
+                .await;
+            return;
+        }
+    };
+
+    // Streamed files have no record count up front, so the index decision uses the file size
+    let rebuild_bytes: u64 = std::env::var("IMPORT_INDEX_REBUILD_BYTES")
+        .ok()
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(200 * 1024 * 1024);
+    let file_size = std::fs::metadata(&records_path).map(|m| m.len()).unwrap_or(0);
+    let rebuild_index = !job.dry_run && rebuild_bytes > 0 && file_size >= rebuild_bytes && rag.drop_vector_index().await.is_ok();
+
+    let outcome = process(&rag, &job, &records_path).await.map_err(|e| e.to_string());
+
+    if rebuild_index {
+        if let Err(e) = rag.rebuild_vector_index().await {
+            eprintln!("Failed to rebuild vector index after import {}: {}", job_id, e);
+        }
+    }
+
+    match outcome {
+        Ok(true) => {
+            println!("Import job {} completed", job_id);
+            let _ = sqlx::query(
+                "UPDATE import_jobs SET status = 'completed', total_records = processed_records, finished_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = $1",
+            )
+            .bind(&job_id)
+            .execute(&rag.pool)
+            .await;
+            remove_files(&job, &records_path);
+        }
+        Ok(false) => {
+            println!("Import job {} cancelled", job_id);
+            let _ = sqlx::query("UPDATE import_jobs SET status = 'cancelled', finished_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+                .bind(&job_id)
+                .execute(&rag.pool)
+                .await;
+            remove_files(&job, &records_path);
+        }
+        Err(e) => {
+            // The file is kept so the job can be resumed from its checkpoint
+            eprintln!("Import job {} failed: {}", job_id, e);
+            let _ = sqlx::query("UPDATE import_jobs SET status = 'failed', error = $2, finished_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+                .bind(&job_id)
+                .bind(e)
+                .execute(&rag.pool)
+                .await;
+        }
+    }
+}
+
+/// Returns the JSON records file for a job. Documents and archives are extracted and chunked
+/// into `<upload>.records.jsonl` once; the file is written under a temporary name and renamed,
+/// so a resumed job never reads a partial conversion.
+async fn prepare_records(job: &ImportJob) -> Result<String, String> {
+    let format = SourceFormat::parse(&job.format).ok_or_else(|| format!("unknown import format '{}'", job.format))?;
+    if format == SourceFormat::Json {
+        return Ok(job.storage_path.clone());
+    }
+    let output = format!("{}.records.jsonl", job.storage_path);
+    if std::path::Path::new(&output).exists() {
+        return Ok(output);
+    }
+
+    let (input, filename, target) = (job.storage_path.clone(), job.filename.clone(), output.clone());
+    tokio::task::spawn_blocking(move || {
+        let partial = format!("{}.partial", target);
+        loaders::convert_to_records(&input, &filename, format, &partial)?;
+        std::fs::rename(&partial, &target)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+    .map_err(|e| format!("failed to extract {}: {}", format.name(), e))?;
+    Ok(output)
+}
+
+fn remove_files(job: &ImportJob, records_path: &str) {
+    let _ = std::fs::remove_file(&job.storage_path);
+    if records_path != job.storage_path {
+        let _ = std::fs::remove_file(records_path);
+    }
+}
+
+/// Imports records chunk by chunk from the job's checkpoint. Returns false when cancelled.
+async fn process(rag: &RagSystem, job: &ImportJob, path: &str) -> Result<bool, Box<dyn std::error::Error>> {
+    if job.cancel_requested {
+        return Ok(false);
+    }
+    sqlx::query("UPDATE import_jobs SET status = 'running', started_at = COALESCE(started_at, CURRENT_TIMESTAMP), updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+        .bind(&job.id)
+        .execute(&rag.pool)
+        .await?;
+
+    let path = path.to_string();
+    let (offset, index) = (job.checkpoint_offset as u64, job.checkpoint_record);
+    let mut reader = tokio::task::spawn_blocking(move || RecordReader::open(&path, offset, index)).await??;
+
+    let adapter: AdapterConfig = match &job.adapter {
+        Some(config) => serde_json::from_str(config)?,
+        None => AdapterConfig::default(),
+    };
+    let categorizer = match adapter.categorize.clone() {
+        Some(rules) => Some(Categorizer::new(rag, rules).await?),
+        None => None,
+    };
+
+    let batch_size = std::env::var("IMPORT_BATCH_SIZE").ok().and_then(|v| v.parse().ok()).unwrap_or(64);
+    let max_chars = max_content_chars();
+    let mut processed = job.processed_records;
+    let mut imported = job.imported_count;
+    let mut updated = job.updated_count;
+    let mut errors = job.error_count;
+    let policy = ConflictPolicy::parse(Some(&job.on_conflict)).unwrap_or_default();
+    // Records may name their own collection; unknown ones are rejected rather than failing the batch insert
+    let collections: HashSet<String> = sqlx::query_as::<_, (String,)>("SELECT id FROM kb_collections")
+        .fetch_all(&rag.pool)
+        .await?
+        .into_iter()
+        .map(|(id,)| id)
+        .collect();
+    // Hashes of contents seen earlier in this file; after a resume the database check covers them
+    let mut seen: HashSet<String> = HashSet::new();
+
+    loop {
+        let (returned, records, position) = tokio::task::spawn_blocking(move || {
+            let records = reader.next_chunk(CHUNK_RECORDS);
+            let position = reader.position();
+            (reader, records, position)
+        })
+        .await?;
+        reader = returned;
+        if records.is_empty() {
+            return Ok(true);
+        }
+        processed += records.len() as i64;
+
+        let mut rejections = Vec::new();
+        let mut accepted: Vec<(i64, u64, String, CreateDocumentRequest)> = Vec::with_capacity(records.len());
+        for record in records {
+            let value = match record.value {
+                Ok(v) => v,
+                Err((e, snippet)) => {
+                    rejections.push(Rejection { index: record.index, offset: record.offset, reason: format!("invalid JSON: {}", e), snippet });
+                    continue;
+                }
+            };
+            let snippet = truncate(&value.to_string());
+            let mapped = match &adapter.template {
+                Some(template) if value.get("load_error").is_none() => adapters::apply_template(template, value),
+                _ => Ok(value),
+            };
+            let validated = mapped.and_then(|value| validate(value, max_chars)).and_then(|mut doc| {
+                if doc.collection_id.is_none() {
+                    doc.collection_id = job.collection_id.clone();
+                }
+                match &doc.collection_id {
+                    Some(c) if !collections.contains(c) => Err(format!("unknown collection '{}'", c)),
+                    _ => Ok(doc),
+                }
+            });
+            match validated {
+                Ok(doc) if policy == ConflictPolicy::Skip && !seen.insert(content_hash(&doc.content)) => rejections.push(Rejection {
+                    index: record.index,
+                    offset: record.offset,
+                    reason: "duplicate of an earlier record in this file".to_string(),
+                    snippet,
+                }),
+                Ok(doc) => accepted.push((record.index, record.offset, snippet, doc)),
+                Err(reason) => rejections.push(Rejection { index: record.index, offset: record.offset, reason, snippet }),
+            }
+        }
+
+        let mut metas = Vec::with_capacity(accepted.len());
+        let mut docs = Vec::with_capacity(accepted.len());
+        for (index, offset, snippet, doc) in accepted {
+            metas.push((index, offset, snippet));
+            docs.push(doc);
+        }
+
+        if let Some(categorizer) = &categorizer {
+            categorizer.categorize(rag, &mut docs).await?;
+        }
+
+        if job.dry_run {
+            // Report what the conflict policy would do without writing
+            let existing = rag.find_existing(&docs).await?;
+            for (i, found) in existing.into_iter().enumerate() {
+                match (found, policy) {
+                    (None, _) => imported += 1,
+                    (Some(_), ConflictPolicy::Update) => updated += 1,
+                    (Some(found), ConflictPolicy::Duplicate) if !found.by_external_id => imported += 1,
+                    (Some(found), _) => {
+                        let (index, offset, snippet) = metas[i].clone();
+                        rejections.push(Rejection { index, offset, reason: format!("duplicate of existing document {}", found.id), snippet });
+                    }
+                }
+            }
+        } else {
+            let result = rag.add_documents_bulk(docs, policy, job.uploaded_by.as_deref(), batch_size, |_, _| {}).await?;
+            imported += result.inserted as i64;
+            updated += result.updated as i64;
+            for (i, id) in result.skipped {
+                let (index, offset, snippet) = metas[i].clone();
+                rejections.push(Rejection { index, offset, reason: format!("duplicate of existing document {}", id), snippet });
+            }
+            for (i, reason) in result.failures {
+                let (index, offset, snippet) = metas[i].clone();
+                rejections.push(Rejection { index, offset, reason, snippet });
+            }
+        }
+
+        errors += rejections.len() as i64;
+        record_rejections(rag, &job.id, &rejections).await?;
+
+        // Checkpoint after the chunk is written; a crash in between re-imports at most one chunk
+        let (cancel_requested,): (bool,) = sqlx::query_as(
+            "UPDATE import_jobs SET processed_records = $2, imported_count = $3, updated_count = $4, error_count = $5, checkpoint_offset = $6, checkpoint_record = $7, updated_at = CURRENT_TIMESTAMP WHERE id = $1 RETURNING cancel_requested",
+        )
+        .bind(&job.id)
+        .bind(processed)
+        .bind(imported)
+        .bind(updated)
+        .bind(errors)
+        .bind(position as i64)
+        .bind(reader.next_index())
+        .fetch_one(&rag.pool)
+        .await?;
+        if cancel_requested {
+            return Ok(false);
+        }
+    }
+}
+
+fn max_content_chars() -> usize {
+    std::env::var("IMPORT_MAX_CONTENT_CHARS").ok().and_then(|v| v.parse().ok()).unwrap_or(32_000)
+}
+
+/// Schema and size checks applied to every record, in dry runs and real imports alike.
+fn validate(value: Value, max_chars: usize) -> Result<CreateDocumentRequest, String> {
+    if !value.is_object() {
+        return Err("record must be a JSON object".to_string());
+    }
+    // Written by `loaders::convert_to_records` for files it could not extract
+    if let Some(e) = value.get("load_error").and_then(Value::as_str) {
+        return Err(e.to_string());
+    }
+    if value.get("metadata").map(|m| !m.is_object() && !m.is_null()).unwrap_or(false) {
+        return Err("metadata must be an object".to_string());
+    }
+    let doc: CreateDocumentRequest = serde_json::from_value(value).map_err(|e| format!("schema: {}", e))?;
+    if doc.content.trim().is_empty() {
+        return Err("content is empty".to_string());
+    }
+    let chars = doc.content.chars().count();
+    if chars > max_chars {
+        return Err(format!("content is {} characters, limit is {}", chars, max_chars));
+    }
+    Ok(doc)
+}
+
+async fn record_rejections(rag: &RagSystem, job_id: &str, rejections: &[Rejection]) -> Result<(), sqlx::Error> {
+    if rejections.is_empty() {
+        return Ok(());
+    }
+    let mut qb = sqlx::QueryBuilder::<sqlx::Postgres>::new("INSERT INTO import_errors (job_id, record_index, byte_offset, reason, snippet) ");
+    qb.push_values(rejections, |mut row, r| {
+        row.push_bind(job_id)
+            .push_bind(r.index)
+            .push_bind(r.offset as i64)
+            .push_bind(&r.reason)
+            .push_bind(&r.snippet);
+    });
+    // A resumed chunk may report the same records again
+    qb.push(" ON CONFLICT (job_id, record_index) DO NOTHING");
+    qb.build().execute(&rag.pool).await?;
+    Ok(())
+}
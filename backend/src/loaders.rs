@@ -0,0 +1,491 @@
+use crate::models::CreateDocumentRequest;
+use regex::Regex;
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::Path;
+use std::sync::OnceLock;
+
+/// File formats accepted by knowledge base uploads.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum SourceFormat {
+    /// JSON array or JSON Lines of `{content, metadata}` records, streamed by `imports::RecordReader`.
+    Json,
+    Markdown,
+    Pdf,
+    Html,
+    Csv,
+    Text,
+    Zip,
+}
+
+/// Extensions and MIME types mapped to each format. Extensions win over the MIME type,
+/// which browsers often report as `application/octet-stream`.
+const REGISTRY: &[(SourceFormat, &[&str], &[&str])] = &[
+    (SourceFormat::Json, &["json", "jsonl", "ndjson"], &["application/json", "application/x-ndjson", "application/jsonl"]),
+    (SourceFormat::Markdown, &["md", "markdown"], &["text/markdown", "text/x-markdown"]),
+    (SourceFormat::Pdf, &["pdf"], &["application/pdf"]),
+    (SourceFormat::Html, &["html", "htm"], &["text/html", "application/xhtml+xml"]),
+    (SourceFormat::Csv, &["csv"], &["text/csv"]),
+    (SourceFormat::Text, &["txt", "text", "log"], &["text/plain"]),
+    (SourceFormat::Zip, &["zip"], &["application/zip", "application/x-zip-compressed"]),
+];
+
+impl SourceFormat {
+    pub fn detect(filename: &str, mime: Option<&str>) -> Option<Self> {
+        let extension = Path::new(filename).extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase());
+        if let Some(ext) = extension {
+            if let Some((format, _, _)) = REGISTRY.iter().find(|(_, exts, _)| exts.contains(&ext.as_str())) {
+                return Some(*format);
+            }
+        }
+        let mime = mime?.split(';').next()?.trim().to_ascii_lowercase();
+        REGISTRY.iter().find(|(_, _, mimes)| mimes.contains(&mime.as_str())).map(|(format, _, _)| *format)
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "json" => Some(Self::Json),
+            "markdown" => Some(Self::Markdown),
+            "pdf" => Some(Self::Pdf),
+            "html" => Some(Self::Html),
+            "csv" => Some(Self::Csv),
+            "text" => Some(Self::Text),
+            "zip" => Some(Self::Zip),
+            _ => None,
+        }
+    }
+
+    /// Name stored on import jobs and in document metadata.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Json => "json",
+            Self::Markdown => "markdown",
+            Self::Pdf => "pdf",
+            Self::Html => "html",
+            Self::Csv => "csv",
+            Self::Text => "text",
+            Self::Zip => "zip",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        REGISTRY.iter().find(|(f, _, _)| f == self).map(|(_, exts, _)| exts[0]).unwrap_or("bin")
+    }
+
+    /// Comma separated list of accepted extensions for error messages.
+    pub fn accepted() -> String {
+        REGISTRY.iter().flat_map(|(_, exts, _)| exts.iter().map(|e| format!(".{}", e))).collect::<Vec<_>>().join(", ")
+    }
+}
+
+/// A run of text under one heading, page or CSV row.
+#[derive(Default)]
+pub struct Section {
+    /// Heading path, e.g. "Exploitation > Heap spraying".
+    pub heading: Option<String>,
+    pub page: Option<u32>,
+    pub row: Option<u64>,
+    pub text: String,
+}
+
+/// Text extracted from one source file.
+pub struct LoadedDocument {
+    pub source: String,
+    pub format: SourceFormat,
+    pub title: String,
+    pub sections: Vec<Section>,
+}
+
+/// Maximum characters per stored chunk, from `IMPORT_CHUNK_CHARS`.
+pub fn chunk_chars() -> usize {
+    std::env::var("IMPORT_CHUNK_CHARS").ok().and_then(|v| v.parse().ok()).filter(|v| *v >= 200).unwrap_or(1500)
+}
+
+/// Limit on the uncompressed size of an uploaded archive, from `IMPORT_MAX_ARCHIVE_BYTES`.
+fn max_archive_bytes() -> u64 {
+    std::env::var("IMPORT_MAX_ARCHIVE_BYTES").ok().and_then(|v| v.parse().ok()).unwrap_or(512 * 1024 * 1024)
+}
+
+/// Extracts `path` and writes one `{content, metadata}` line per chunk to `output`, so the
+/// result can be imported by the same streaming, checkpointed pipeline as JSON uploads.
+/// Files that cannot be extracted are written as `{"load_error", "metadata"}` lines and end
+/// up in the job's error report.
+pub fn convert_to_records(path: &str, filename: &str, format: SourceFormat, output: &str) -> std::io::Result<usize> {
+    let mut out = BufWriter::new(File::create(output)?);
+    let max_chars = chunk_chars();
+    let mut written = 0;
+
+    let loaded = if format == SourceFormat::Zip {
+        load_archive(path, filename)?
+    } else {
+        let mut bytes = Vec::new();
+        File::open(path)?.read_to_end(&mut bytes)?;
+        vec![(filename.to_string(), load(format, filename, &bytes))]
+    };
+
+    for (source, result) in loaded {
+        let records = match result {
+            Ok(doc) => chunk(&doc, max_chars).into_iter().map(|d| json!({"content": d.content, "metadata": d.metadata})).collect(),
+            Err(e) => vec![json!({"load_error": e, "metadata": {"source": source}})],
+        };
+        for record in records {
+            serde_json::to_writer(&mut out, &record)?;
+            out.write_all(b"\n")?;
+            written += 1;
+        }
+    }
+    out.flush()?;
+    Ok(written)
+}
+
+/// Extracts text from a single (non-archive) file.
+pub fn load(format: SourceFormat, source: &str, bytes: &[u8]) -> Result<LoadedDocument, String> {
+    let stem = Path::new(source).file_stem().and_then(|s| s.to_str()).unwrap_or(source).to_string();
+    let (title, sections) = match format {
+        SourceFormat::Markdown => load_markdown(&decode_text(bytes)),
+        SourceFormat::Html => load_html(&decode_text(bytes)),
+        SourceFormat::Text => (None, vec![Section { text: decode_text(bytes), ..Default::default() }]),
+        SourceFormat::Csv => (None, load_csv(bytes)?),
+        SourceFormat::Pdf => (None, load_pdf(bytes)?),
+        SourceFormat::Json | SourceFormat::Zip => return Err(format!("{} files cannot be loaded as documents", format.name())),
+    };
+    let sections: Vec<Section> = sections.into_iter().filter(|s| !s.text.trim().is_empty()).collect();
+    if sections.is_empty() {
+        return Err("no text could be extracted".to_string());
+    }
+    Ok(LoadedDocument { source: source.to_string(), format, title: title.unwrap_or(stem), sections })
+}
+
+/// Loads every supported file in a zip archive. Entries are named `archive.zip/inner/path`.
+fn load_archive(path: &str, filename: &str) -> std::io::Result<Vec<(String, Result<LoadedDocument, String>)>> {
+    let mut archive = zip::ZipArchive::new(File::open(path)?).map_err(std::io::Error::other)?;
+    let mut budget = max_archive_bytes();
+    let mut loaded = Vec::new();
+
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).map_err(std::io::Error::other)?;
+        if entry.is_dir() {
+            continue;
+        }
+        let name = entry.name().to_string();
+        let hidden = name.split('/').any(|part| part.starts_with('.') || part == "__MACOSX");
+        if hidden {
+            continue;
+        }
+        let source = format!("{}/{}", filename, name);
+        let format = match SourceFormat::detect(&name, None) {
+            Some(SourceFormat::Zip) => {
+                loaded.push((source, Err("nested archives are not supported".to_string())));
+                continue;
+            }
+            Some(SourceFormat::Json) => {
+                loaded.push((source, Err("JSON record files must be uploaded on their own".to_string())));
+                continue;
+            }
+            Some(format) => format,
+            None => {
+                loaded.push((source, Err("unsupported file type".to_string())));
+                continue;
+            }
+        };
+
+        let mut bytes = Vec::new();
+        (&mut entry).take(budget + 1).read_to_end(&mut bytes)?;
+        if bytes.len() as u64 > budget {
+            loaded.push((source, Err("archive exceeds the uncompressed size limit".to_string())));
+            break;
+        }
+        budget -= bytes.len() as u64;
+        let result = load(format, &source, &bytes);
+        loaded.push((source, result));
+    }
+    Ok(loaded)
+}
+
+fn decode_text(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_prefix(b"\xEF\xBB\xBF").unwrap_or(bytes);
+    String::from_utf8_lossy(bytes).replace("\r\n", "\n")
+}
+
+/// Collects lines into sections, tracking the heading path above them.
+#[derive(Default)]
+struct SectionBuilder {
+    headings: Vec<(usize, String)>,
+    current: String,
+    sections: Vec<Section>,
+}
+
+impl SectionBuilder {
+    fn heading(&mut self, level: usize, text: &str) {
+        self.flush();
+        self.headings.retain(|(l, _)| *l < level);
+        self.headings.push((level, text.trim().to_string()));
+    }
+
+    fn line(&mut self, line: &str) {
+        self.current.push_str(line);
+        self.current.push('\n');
+    }
+
+    fn flush(&mut self) {
+        let text = std::mem::take(&mut self.current);
+        if text.trim().is_empty() {
+            return;
+        }
+        let heading = if self.headings.is_empty() {
+            None
+        } else {
+            Some(self.headings.iter().map(|(_, h)| h.as_str()).collect::<Vec<_>>().join(" > "))
+        };
+        self.sections.push(Section { heading, text, ..Default::default() });
+    }
+
+    fn finish(mut self) -> Vec<Section> {
+        self.flush();
+        self.sections
+    }
+}
+
+/// Splits on ATX headings outside fenced code blocks. The first level-1 heading is the title.
+fn load_markdown(text: &str) -> (Option<String>, Vec<Section>) {
+    let mut builder = SectionBuilder::default();
+    let mut title = None;
+    let mut in_fence = false;
+
+    for line in text.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with("```") || trimmed.starts_with("~~~") {
+            in_fence = !in_fence;
+        }
+        if !in_fence {
+            let level = trimmed.chars().take_while(|c| *c == '#').count();
+            if (1..=6).contains(&level) && trimmed[level..].starts_with(' ') {
+                let heading = trimmed[level..].trim().trim_end_matches('#').trim();
+                if level == 1 && title.is_none() {
+                    title = Some(heading.to_string());
+                }
+                builder.heading(level, heading);
+                continue;
+            }
+        }
+        builder.line(line);
+    }
+    (title, builder.finish())
+}
+
+/// Marks headings in converted HTML; a plain `#` would clash with shell comments in `<pre>` blocks.
+const HEADING_MARK: char = '\u{1}';
+
+fn html_patterns() -> &'static [Regex; 6] {
+    static PATTERNS: OnceLock<[Regex; 6]> = OnceLock::new();
+    PATTERNS.get_or_init(|| {
+        [
+            Regex::new(r"(?is)<title[^>]*>(.*?)</title>").unwrap(),
+            Regex::new(r"(?is)<!--.*?-->|<(script|style|noscript|head|svg|template)\b[^>]*>.*?</(script|style|noscript|head|svg|template)>").unwrap(),
+            Regex::new(r"(?is)<h([1-6])\b[^>]*>(.*?)</h[1-6]>").unwrap(),
+            Regex::new(r"(?i)<li\b[^>]*>").unwrap(),
+            Regex::new(r"(?i)</?(p|div|br|tr|section|article|pre|table|ul|ol|blockquote|header|footer|main|nav|dd|dt|hr)\b[^>]*>").unwrap(),
+            Regex::new(r"(?s)<[^>]*>").unwrap(),
+        ]
+    })
+}
+
+/// Strips markup and splits on `<h1>`-`<h6>`. The `<title>` element, or else the first `<h1>`, is the title.
+fn load_html(html: &str) -> (Option<String>, Vec<Section>) {
+    let [title_re, drop_re, heading_re, li_re, block_re, tag_re] = html_patterns();
+
+    let mut title = title_re
+        .captures(html)
+        .map(|c| decode_entities(&tag_re.replace_all(&c[1], "")).trim().to_string())
+        .filter(|t| !t.is_empty());
+
+    let text = drop_re.replace_all(html, "");
+    let text = heading_re.replace_all(&text, |c: &regex::Captures| {
+        let inner = decode_entities(&tag_re.replace_all(&c[2], "")).split_whitespace().collect::<Vec<_>>().join(" ");
+        format!("\n{}{}{}\n", HEADING_MARK, &c[1], inner)
+    });
+    let text = li_re.replace_all(&text, "\n- ");
+    let text = block_re.replace_all(&text, "\n");
+    let text = decode_entities(&tag_re.replace_all(&text, ""));
+
+    let mut builder = SectionBuilder::default();
+    let mut blank = false;
+    for line in text.lines() {
+        if let Some(marked) = line.strip_prefix(HEADING_MARK) {
+            // Documents may contain the marker themselves, so the level digit is not guaranteed
+            let mut chars = marked.chars();
+            let level = chars.next().and_then(|c| c.to_digit(10)).unwrap_or(1) as usize;
+            let heading = chars.as_str();
+            if level == 1 && title.is_none() {
+                title = Some(heading.to_string());
+            }
+            builder.heading(level, heading);
+            blank = false;
+            continue;
+        }
+        let line = line.trim_end();
+        // Markup leaves runs of empty lines; keep at most one as a paragraph break
+        if line.trim().is_empty() {
+            if !blank {
+                builder.line("");
+            }
+            blank = true;
+        } else {
+            builder.line(line);
+            blank = false;
+        }
+    }
+    (title, builder.finish())
+}
+
+fn decode_entities(text: &str) -> String {
+    static ENTITY: OnceLock<Regex> = OnceLock::new();
+    let entity = ENTITY.get_or_init(|| Regex::new(r"&(#[0-9]+|#[xX][0-9a-fA-F]+|[a-zA-Z]+);").unwrap());
+    entity
+        .replace_all(text, |c: &regex::Captures| {
+            let name = &c[1];
+            let decoded = match name {
+                "amp" => Some('&'),
+                "lt" => Some('<'),
+                "gt" => Some('>'),
+                "quot" => Some('"'),
+                "apos" => Some('\''),
+                "nbsp" => Some(' '),
+                _ if name.starts_with("#x") || name.starts_with("#X") => u32::from_str_radix(&name[2..], 16).ok().and_then(char::from_u32),
+                _ if name.starts_with('#') => name[1..].parse().ok().and_then(char::from_u32),
+                _ => None,
+            };
+            decoded.map(String::from).unwrap_or_else(|| c[0].to_string())
+        })
+        .into_owned()
+}
+
+/// One section per row, rendered as `column: value` lines.
+fn load_csv(bytes: &[u8]) -> Result<Vec<Section>, String> {
+    let mut reader = csv::ReaderBuilder::new().flexible(true).from_reader(bytes);
+    let headers = reader.headers().map_err(|e| format!("invalid CSV: {}", e))?.clone();
+    let mut sections = Vec::new();
+
+    for (i, row) in reader.records().enumerate() {
+        let row = row.map_err(|e| format!("invalid CSV: {}", e))?;
+        let text: String = row
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| !value.trim().is_empty())
+            .map(|(col, value)| match headers.get(col) {
+                Some(name) if !name.is_empty() => format!("{}: {}\n", name, value.trim()),
+                _ => format!("{}\n", value.trim()),
+            })
+            .collect();
+        sections.push(Section { row: Some(i as u64 + 1), text, ..Default::default() });
+    }
+    Ok(sections)
+}
+
+/// One section per page.
+fn load_pdf(bytes: &[u8]) -> Result<Vec<Section>, String> {
+    // The PDF parser panics on some malformed files; contain it to this file
+    let pages = std::panic::catch_unwind(|| pdf_extract::extract_text_from_mem_by_pages(bytes))
+        .map_err(|_| "PDF could not be parsed".to_string())?
+        .map_err(|e| format!("PDF text extraction failed: {}", e))?;
+
+    Ok(pages
+        .into_iter()
+        .enumerate()
+        .map(|(i, text)| Section { page: Some(i as u32 + 1), text, ..Default::default() })
+        .collect())
+}
+
+/// Splits each section into paragraph-aligned chunks of at most `max_chars`, prefixed with
+/// the title and heading so the chunk embeds with its context.
+pub fn chunk(doc: &LoadedDocument, max_chars: usize) -> Vec<CreateDocumentRequest> {
+    let mut chunks = Vec::new();
+    for section in &doc.sections {
+        let context = match &section.heading {
+            Some(heading) => format!("{} > {}\n\n", doc.title, heading),
+            None => format!("{}\n\n", doc.title),
+        };
+        let budget = max_chars.saturating_sub(context.chars().count()).max(max_chars / 2);
+
+        for body in pack_paragraphs(&section.text, budget) {
+            let mut metadata = json!({
+                "source": doc.source,
+                "format": doc.format.name(),
+                "title": doc.title,
+                "chunk": chunks.len(),
+            });
+            if let Some(heading) = &section.heading {
+                metadata["section"] = Value::from(heading.as_str());
+            }
+            if let Some(page) = section.page {
+                metadata["page"] = Value::from(page);
+            }
+            if let Some(row) = section.row {
+                metadata["row"] = Value::from(row);
+            }
+            chunks.push(CreateDocumentRequest { content: format!("{}{}", context, body), metadata: Some(metadata), external_id: None, collection_id: None });
+        }
+    }
+    chunks
+}
+
+/// Greedily joins blank-line separated paragraphs up to `max_chars`, splitting oversized ones at whitespace.
+fn pack_paragraphs(text: &str, max_chars: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+
+    let paragraphs = text.split("\n\n").map(str::trim).filter(|p| !p.is_empty());
+    for paragraph in paragraphs.flat_map(|p| split_long(p, max_chars)) {
+        let len = paragraph.chars().count();
+        if current_len > 0 && current_len + 2 + len > max_chars {
+            chunks.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        if current_len > 0 {
+            current.push_str("\n\n");
+            current_len += 2;
+        }
+        current.push_str(&paragraph);
+        current_len += len;
+    }
+    if current_len > 0 {
+        chunks.push(current);
+    }
+    chunks
+}
+
+fn split_long(paragraph: &str, max_chars: usize) -> Vec<String> {
+    let mut parts = Vec::new();
+    let mut rest = paragraph;
+    while rest.chars().count() > max_chars {
+        let limit = rest.char_indices().nth(max_chars).map(|(i, _)| i).unwrap_or(rest.len());
+        let cut = rest[..limit].rfind(char::is_whitespace).filter(|i| *i > 0).unwrap_or(limit);
+        parts.push(rest[..cut].trim_end().to_string());
+        rest = rest[cut..].trim_start();
+    }
+    if !rest.is_empty() {
+        parts.push(rest.to_string());
+    }
+    parts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn html_headings_with_non_ascii_text() {
+        let (title, sections) = load_html("<h1>Überblick</h1><p>Text</p><h2>日本語の見出し</h2><p>本文</p>");
+        assert_eq!(title.as_deref(), Some("Überblick"));
+        assert_eq!(sections.last().and_then(|s| s.heading.as_deref()), Some("Überblick > 日本語の見出し"));
+    }
+
+    #[test]
+    fn html_with_literal_heading_marker_does_not_panic() {
+        let html = format!("<p>{}é stray marker</p><p>after</p>", HEADING_MARK);
+        let (_, sections) = load_html(&html);
+        assert!(sections.iter().any(|s| s.text.contains("after")));
+    }
+}
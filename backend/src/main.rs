@@ -1,13 +1,29 @@
 use actix_cors::Cors;
 use actix_web::{web, App, HttpServer};
 use dotenv::dotenv;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 
+mod adapters;
+mod artifacts;
+mod collections;
 mod db;
+mod embedder;
 mod auth;
+mod export;
+mod hints;
+mod imports;
+mod loaders;
 mod models;
+mod query_rewrite;
 mod rag;
+mod reindex;
+mod reranker;
+mod response_cache;
 mod routes;
+mod scoring;
+mod sandbox;
+mod settings;
+mod tools;
 
 use rag::RagSystem;
 use routes::AppState;
@@ -17,8 +33,11 @@ use crate::models::User;
 async fn main() -> std::io::Result<()> {
     dotenv().ok();
 
+    // Embedding model named by EMBEDDING_MODEL; new databases are sized for it
+    let configured_model = embedder::ModelSpec::from_env().unwrap_or_else(|e| panic!("{}", e));
+
     // Initialize DB
-    let pool = db::init_db().await;
+    let pool = db::init_db(configured_model.dim).await;
 
     // Seed Admin
     let admin_email = "mdjobayerarafat@gmail.com";
@@ -122,6 +141,62 @@ async fn main() -> std::io::Result<()> {
             .ok();
     }
 
+    // Seed Default Personas
+    let personas_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM personas")
+        .fetch_one(&pool)
+        .await
+        .unwrap_or((0,));
+
+    if personas_count.0 == 0 {
+        println!("Seeding Default Personas...");
+        let personas = vec![
+            (
+                "web-ctf-coach",
+                "Web CTF Coach",
+                "Step-by-step guidance for web exploitation challenges.",
+                "You are Krypton, a Web CTF coach. Walk the user through reconnaissance (headers, source, cookies, endpoints) before exploitation. Cover SQLi, XSS, SSRF, IDOR, SSTI and auth flaws, and recommend Burp Suite or OWASP ZAP workflows. Explain why each vulnerability exists.\n\n[CONTEXT BEGIN]\n{context}\n[CONTEXT END]",
+                serde_json::json!({"categories": ["CTF", "Web"]}),
+            ),
+            (
+                "forensics-analyst",
+                "Forensics Analyst",
+                "Artifact-driven analysis of files, memory and network captures.",
+                "You are Krypton, a digital forensics analyst. Start from the artifact: magic bytes, metadata (exiftool), embedded streams, memory images (Volatility) and pcaps (Wireshark, tshark). Keep a clear chain of reasoning and note what each finding proves.\n\n[CONTEXT BEGIN]\n{context}\n[CONTEXT END]",
+                serde_json::json!({"categories": ["CTF", "Forensics", "Procedure"]}),
+            ),
+            (
+                "blue-team-reviewer",
+                "Blue-Team Reviewer",
+                "Defensive review of configurations, logs and incident handling.",
+                "You are Krypton, a blue-team reviewer. Focus on detection, hardening and incident response. For every weakness, give the detection opportunity (logs, SIEM queries) and the remediation. Follow the organisation's procedures where they are available.\n\n[CONTEXT BEGIN]\n{context}\n[CONTEXT END]",
+                serde_json::json!({"categories": ["Policy", "Procedure", "Technical"]}),
+            ),
+            (
+                "exam-mode",
+                "Exam Mode",
+                "Socratic tutoring that never gives direct answers or flags.",
+                "You are Krypton in exam mode. Never give the final answer, the flag or a complete exploit. Respond only with guiding questions, concepts to research and hints about which tool or technique to try next. If the user asks for the answer, politely refuse and give a smaller hint instead.\n\n[CONTEXT BEGIN]\n{context}\n[CONTEXT END]",
+                serde_json::json!({}),
+            ),
+        ];
+
+        for (slug, name, description, template, filter) in personas {
+            sqlx::query(
+                "INSERT INTO personas (id, slug, name, description, prompt_template, retrieval_filter) VALUES ($1, $2, $3, $4, $5, $6)"
+            )
+            .bind(uuid::Uuid::new_v4().to_string())
+            .bind(slug)
+            .bind(name)
+            .bind(description)
+            .bind(template)
+            .bind(filter.to_string())
+            .execute(&pool)
+            .await
+            .expect("Failed to seed persona");
+        }
+        println!("Default Personas Seeded.");
+    }
+
     // Initialize Redis (Optional)
     let redis_url = std::env::var("REDIS_URL").ok();
     let redis_client = if let Some(url) = redis_url {
@@ -140,7 +215,13 @@ async fn main() -> std::io::Result<()> {
 
     // Initialize RAG System (Load embedding model)
     println!("Initializing RAG System (Loading Embedding Model)...");
-    let rag = Arc::new(RagSystem::new(pool.clone(), redis_client));
+    let active_model = reindex::active_model(&pool, &configured_model).await;
+    // Retrieval still works without the reranker, so a model that fails to load is not fatal
+    let reranker = reranker::Reranker::from_env().unwrap_or_else(|e| {
+        eprintln!("Reranking disabled, results are ordered by vector similarity: {}", e);
+        None
+    });
+    let rag = Arc::new(RagSystem::new(pool.clone(), redis_client, active_model, reranker));
     println!("RAG System Initialized.");
 
     // Seed Knowledge Base Documents
@@ -156,24 +237,28 @@ async fn main() -> std::io::Result<()> {
         let demo_docs = vec![
             (
                 "Krypton Security Policies: All employees must use 2FA. Passwords must be at least 12 characters long and include special characters. Regular security audits are conducted quarterly.",
-                serde_json::json!({"category": "Policy", "author": "CISO"})
+                serde_json::json!({"category": "Policy", "author": "CISO"}),
+                "general"
             ),
             (
                 "CTF Challenge #42 Hint: The flag is hidden in the EXIF data of the image. Use 'exiftool' or similar utilities to extract metadata. Look for the 'Comment' field.",
-                serde_json::json!({"category": "CTF", "challenge": "42"})
+                serde_json::json!({"category": "CTF", "challenge": "42"}),
+                "general"
             ),
             (
                 "Incident Response Plan: In case of a confirmed breach, immediately isolate the affected system from the network. Do not power off the machine to preserve RAM artifacts. Contact the security team at security@krypton.local.",
-                serde_json::json!({"category": "Procedure", "priority": "High"})
+                serde_json::json!({"category": "Procedure", "priority": "High"}),
+                "internal"
             ),
              (
                 "Krypton AI Architecture: The system uses a RAG (Retrieval-Augmented Generation) pipeline. Documents are embedded using AllMiniLML6V2 and stored in SQLite. The chat API retrieves relevant chunks before querying the LLM.",
-                serde_json::json!({"category": "Technical", "component": "RAG"})
+                serde_json::json!({"category": "Technical", "component": "RAG"}),
+                "general"
             ),
         ];
 
-        for (content, metadata) in demo_docs {
-            match rag.add_document(content, Some(metadata)).await {
+        for (content, metadata, collection) in demo_docs {
+            match rag.add_document(content, Some(metadata), collection).await {
                 Ok(id) => println!("Seeded document: {}", id),
                 Err(e) => eprintln!("Failed to seed document: {}", e),
             }
@@ -252,11 +337,14 @@ async fn main() -> std::io::Result<()> {
     println!("Demo Chat History Seeded.");
 
     let client = reqwest::Client::new();
-    let import_status = Arc::new(Mutex::new(models::ImportStatus::default()));
+    // Pick up imports interrupted by the last shutdown
+    imports::resume_pending(rag.clone()).await;
+    // Resume or start migrating the corpus to the configured embedding model
+    reindex::resume_or_start(rag.clone(), &configured_model).await;
+
     let app_state = web::Data::new(AppState {
         rag,
         client,
-        import_status,
     });
 
     println!("Starting server at http://0.0.0.0:8080");
@@ -278,13 +366,61 @@ async fn main() -> std::io::Result<()> {
             .service(routes::verify_email)
             .service(routes::verify_email_query)
             .service(routes::upload_data)
+            .service(routes::list_near_duplicates)
+            .service(routes::dismiss_near_duplicate)
             .service(routes::list_documents)
             .service(routes::update_document)
             .service(routes::delete_document)
+            .service(routes::list_document_trash)
+            .service(routes::undelete_document)
+            .service(routes::purge_document)
+            .service(routes::list_document_versions)
+            .service(routes::diff_document_versions)
+            .service(routes::restore_document_version)
+            .service(routes::move_documents)
+            .service(routes::list_collections)
+            .service(routes::create_collection)
+            .service(routes::update_collection)
+            .service(routes::delete_collection)
+            .service(routes::list_collection_grants)
+            .service(routes::add_collection_grant)
+            .service(routes::remove_collection_grant)
+            .service(routes::list_user_groups)
+            .service(routes::create_user_group)
+            .service(routes::delete_user_group)
+            .service(routes::list_group_members)
+            .service(routes::add_group_member)
+            .service(routes::remove_group_member)
             .service(routes::chat)
             .service(routes::list_chat_sessions)
+            .service(routes::search_chat_history)
+            .service(routes::export_all_chat_sessions)
+            .service(routes::import_chat_session)
+            .service(routes::export_chat_session)
             .service(routes::get_chat_session)
+            .service(routes::upload_chat_attachment)
+            .service(routes::list_session_attachments)
+            .service(routes::execute_session_code)
+            .service(routes::list_session_executions)
+            .service(routes::admin_list_executions)
+            .service(routes::admin_cache_stats)
+            .service(routes::admin_flush_caches)
+            .service(routes::admin_embedder_stats)
+            .service(routes::admin_embedding_status)
+            .service(routes::admin_start_reindex)
+            .service(routes::admin_cancel_reindex)
+            .service(routes::update_chat_session)
+            .service(routes::escalate_hint_level)
             .service(routes::delete_chat_session)
+            .service(routes::create_session_share)
+            .service(routes::list_session_shares)
+            .service(routes::revoke_session_share)
+            .service(routes::get_shared_session)
+            .service(routes::fork_shared_session)
+            .service(routes::list_chat_folders)
+            .service(routes::create_chat_folder)
+            .service(routes::rename_chat_folder)
+            .service(routes::delete_chat_folder)
             .service(routes::get_profile)
             .service(routes::list_users)
             .service(routes::update_user_role)
@@ -300,9 +436,41 @@ async fn main() -> std::io::Result<()> {
             .service(routes::add_model)
             .service(routes::update_model)
             .service(routes::delete_model)
+            .service(routes::list_personas)
+            .service(routes::admin_list_personas)
+            .service(routes::add_persona)
+            .service(routes::update_persona)
+            .service(routes::delete_persona)
+            .service(routes::scoreboard)
+            .service(routes::get_my_progress)
+            .service(routes::admin_get_user_progress)
+            .service(routes::list_challenges)
+            .service(routes::submit_flag)
+            .service(routes::list_unlocked_hints)
+            .service(routes::unlock_next_hint)
+            .service(routes::get_challenge)
+            .service(routes::download_challenge_attachment)
+            .service(routes::admin_list_challenges)
+            .service(routes::add_challenge)
+            .service(routes::update_challenge)
+            .service(routes::delete_challenge)
+            .service(routes::set_challenge_hints)
+            .service(routes::link_challenge_documents)
+            .service(routes::upload_challenge_attachment)
+            .service(routes::delete_challenge_attachment)
+            .service(routes::list_settings)
+            .service(routes::update_setting)
             // Knowledge Base
             .service(routes::upload_file)
-            .service(routes::get_import_status)
+            .service(routes::list_import_jobs)
+            .service(routes::get_import_job)
+            .service(routes::download_import_errors)
+            .service(routes::list_import_adapters)
+            .service(routes::create_import_adapter)
+            .service(routes::update_import_adapter)
+            .service(routes::delete_import_adapter)
+            .service(routes::cancel_import_job)
+            .service(routes::resume_import_job)
     })
     .bind(("0.0.0.0", 8080))?
     .run()
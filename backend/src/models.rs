@@ -10,34 +10,350 @@ pub struct Document {
     pub embedding: Vector,
 }
 
-#[derive(Debug, serde::Serialize, Clone)]
-pub struct ImportStatus {
-    pub total_documents: usize,
-    pub processed_documents: usize,
-    pub errors: usize,
-    pub is_processing: bool,
-    pub current_file: String,
-    pub message: String,
+/// Background knowledge base import. `checkpoint_offset` is the byte offset of the next
+/// unread record in the uploaded file, so an interrupted job resumes where it stopped.
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct ImportJob {
+    pub id: String,
+    pub uploaded_by: Option<String>,
+    pub filename: String,
+    #[serde(skip)]
+    pub storage_path: String,
+    /// queued | running | completed | failed | cancelled
+    pub status: String,
+    pub total_records: Option<i64>,
+    pub processed_records: i64,
+    pub imported_count: i64,
+    /// Existing documents overwritten under the `update` conflict policy.
+    pub updated_count: i64,
+    pub error_count: i64,
+    pub checkpoint_offset: i64,
+    pub checkpoint_record: i64,
+    pub error: Option<String>,
+    pub cancel_requested: bool,
+    /// Validation only: `imported_count` is the number of records that would be imported.
+    pub dry_run: bool,
+    /// Uploaded file format (json, markdown, pdf, html, csv, text, zip). Other formats are
+    /// converted to JSON Lines records before import, and offsets refer to that file.
+    pub format: String,
+    /// skip | update | duplicate: what happens to records matching an existing document.
+    pub on_conflict: String,
+    /// JSON encoded `AdapterConfig` captured at upload time, so a resumed job maps records the same way.
+    pub adapter: Option<String>,
+    /// Collection for records that do not name one.
+    pub collection_id: Option<String>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub started_at: Option<chrono::NaiveDateTime>,
+    pub finished_at: Option<chrono::NaiveDateTime>,
+    pub updated_at: Option<chrono::NaiveDateTime>,
 }
 
-impl Default for ImportStatus {
-    fn default() -> Self {
-        Self {
-            total_documents: 0,
-            processed_documents: 0,
-            errors: 0,
-            is_processing: false,
-            current_file: String::new(),
-            message: String::new(),
-        }
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct ImportError {
+    pub record_index: i64,
+    pub byte_offset: i64,
+    pub reason: String,
+    pub snippet: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ImportUploadQuery {
+    pub dry_run: Option<bool>,
+    /// Slug of a built-in or saved dataset adapter.
+    pub adapter: Option<String>,
+    /// skip (default) | update | duplicate
+    pub on_conflict: Option<String>,
+    /// Collection for records that do not name one. Defaults to `general`.
+    pub collection: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UploadDataQuery {
+    /// skip (default) | update | duplicate
+    pub on_conflict: Option<String>,
+    /// Collection for documents that do not name one. Defaults to `general`.
+    pub collection: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct NearDuplicatesQuery {
+    /// Minimum cosine similarity, 0.95 by default.
+    pub threshold: Option<f64>,
+    pub limit: Option<i64>,
+}
+
+/// Two documents whose embeddings are nearly identical, for editor review.
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct NearDuplicatePair {
+    pub first_id: String,
+    pub first_content: String,
+    pub second_id: String,
+    pub second_content: String,
+    pub similarity: f64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct DismissNearDuplicateRequest {
+    pub first_id: String,
+    pub second_id: String,
+}
+
+/// How an import maps source records to documents and assigns categories.
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct AdapterConfig {
+    /// Builds `{content, metadata}` from other record shapes; records are used as-is when absent.
+    pub template: Option<FieldTemplate>,
+    pub categorize: Option<CategoryRules>,
+}
+
+/// `{field}` placeholders are replaced with record values; `{field:100}` truncates to 100 characters.
+/// Nested fields use dots, e.g. `{meta.title}`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FieldTemplate {
+    pub content: String,
+    /// String values are rendered as templates and dropped when empty; other values are copied.
+    #[serde(default)]
+    pub metadata: serde_json::Map<String, serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum CategorizeMode {
+    /// First category with a keyword contained in the content wins.
+    #[default]
+    Keyword,
+    /// Closest category description by embedding similarity, if above `threshold`.
+    Embedding,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CategoryRules {
+    #[serde(default)]
+    pub mode: CategorizeMode,
+    pub categories: Vec<CategoryRule>,
+    /// Category for documents no rule matches.
+    #[serde(default = "default_category")]
+    pub fallback: String,
+    pub threshold: Option<f32>,
+    /// Replace a `metadata.category` the record already has.
+    #[serde(default)]
+    pub overwrite: bool,
+}
+
+fn default_category() -> String {
+    "General".to_string()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CategoryRule {
+    pub name: String,
+    #[serde(default)]
+    pub keywords: Vec<String>,
+    /// Text embedded for similarity matching; defaults to the name and keywords.
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct ImportAdapter {
+    pub id: String,
+    pub slug: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// JSON encoded `AdapterConfig`.
+    pub config: String,
+    pub created_by: Option<String>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+impl ImportAdapter {
+    pub fn config(&self) -> AdapterConfig {
+        serde_json::from_str(&self.config).unwrap_or_default()
     }
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateImportAdapterRequest {
+    pub slug: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub config: AdapterConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateImportAdapterRequest {
+    pub slug: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub config: Option<AdapterConfig>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListImportJobsQuery {
+    pub status: Option<String>,
+    pub limit: Option<i64>,
+}
+
 #[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
 pub struct DocumentSummary {
     pub id: String,
     pub content: String,
     pub metadata: Option<String>,
+    pub challenge_id: Option<String>,
+    pub collection_id: String,
+}
+
+/// Snapshot of a document after a change. Version 1 is the state before the first recorded change.
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct DocumentVersion {
+    pub document_id: String,
+    pub version: i32,
+    pub content: String,
+    pub metadata: Option<String>,
+    /// created | updated | deleted | undeleted | restored
+    pub action: String,
+    /// Version whose content a `restored` entry brought back.
+    pub restored_from: Option<i32>,
+    pub author_id: Option<String>,
+    pub author_name: Option<String>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct DeletedDocument {
+    pub id: String,
+    pub content: String,
+    pub metadata: Option<String>,
+    pub deleted_at: Option<chrono::NaiveDateTime>,
+    pub deleted_by: Option<String>,
+    pub deleted_by_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct VersionDiffQuery {
+    pub from: i32,
+    /// Defaults to the document's current state.
+    pub to: Option<i32>,
+}
+
+/// Background migration of every document's embedding to another model.
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct ReindexJob {
+    pub id: String,
+    pub source_model: String,
+    pub target_model: String,
+    pub target_dim: i32,
+    /// queued | running | completed | failed | cancelled
+    pub status: String,
+    pub total_documents: Option<i64>,
+    pub embedded_documents: i64,
+    pub error: Option<String>,
+    pub cancel_requested: bool,
+    pub requested_by: Option<String>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub started_at: Option<chrono::NaiveDateTime>,
+    pub finished_at: Option<chrono::NaiveDateTime>,
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct StartReindexRequest {
+    /// fastembed model code; defaults to `EMBEDDING_MODEL`.
+    pub model: Option<String>,
+}
+
+// Knowledge base collections
+
+/// public: everyone | subscribers: users with an active subscription | roles: users whose role is
+/// listed | private: grants only. Grants add readers under every policy; admins read everything.
+pub const COLLECTION_VISIBILITIES: &[&str] = &["public", "subscribers", "roles", "private"];
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct Collection {
+    pub id: String,
+    pub slug: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub visibility: String,
+    /// Roles that may read the collection under the `roles` policy.
+    pub roles: Vec<String>,
+    pub created_by: Option<String>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub updated_at: Option<chrono::NaiveDateTime>,
+    /// Live documents in the collection (list endpoint only).
+    #[sqlx(default)]
+    pub document_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateCollectionRequest {
+    pub slug: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub visibility: String,
+    pub roles: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateCollectionRequest {
+    pub slug: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub visibility: Option<String>,
+    pub roles: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct CollectionGrant {
+    pub collection_id: String,
+    /// user | group
+    pub principal_type: String,
+    pub principal_id: String,
+    /// Username or group name.
+    pub principal_name: Option<String>,
+    pub created_by: Option<String>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CollectionGrantRequest {
+    /// user | group
+    pub principal_type: String,
+    pub principal_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct MoveDocumentsRequest {
+    pub document_ids: Vec<String>,
+    pub collection_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct UserGroup {
+    pub id: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    #[sqlx(default)]
+    pub member_count: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateUserGroupRequest {
+    pub name: String,
+    pub description: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GroupMemberRequest {
+    pub user_id: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct GroupMember {
+    pub user_id: String,
+    pub username: String,
+    pub added_at: Option<chrono::NaiveDateTime>,
 }
 
 fn default_vector() -> Vector {
@@ -48,6 +364,12 @@ fn default_vector() -> Vector {
 pub struct CreateDocumentRequest {
     pub content: String,
     pub metadata: Option<serde_json::Value>,
+    /// Caller's stable key for the document; loads with the same key update or skip it.
+    #[serde(default)]
+    pub external_id: Option<String>,
+    /// Defaults to the `general` collection.
+    #[serde(default)]
+    pub collection_id: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -56,6 +378,69 @@ pub struct ChatRequest {
     pub history: Option<Vec<Message>>,
     pub model: Option<String>,
     pub session_id: Option<String>,
+    /// Persona id or slug; sticks to the session once chosen.
+    pub persona: Option<String>,
+    /// Start (or switch the session into) progressive hint mode.
+    pub hint_mode: Option<bool>,
+    /// Attach the session to a challenge; retrieval is then scoped to its documents.
+    pub challenge_id: Option<String>,
+    /// Previously uploaded chat attachments to analyze with this message.
+    pub attachment_ids: Option<Vec<String>>,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct ChatAttachment {
+    pub id: String,
+    pub user_id: String,
+    pub session_id: Option<String>,
+    pub message_id: Option<String>,
+    pub filename: String,
+    pub file_type: String,
+    pub size_bytes: i64,
+    pub sha256: String,
+    /// JSON analysis report produced by `artifacts::analyze`.
+    pub report: String,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AttachmentUploadQuery {
+    pub session_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct CodeExecution {
+    pub id: String,
+    pub user_id: String,
+    pub session_id: Option<String>,
+    pub message_id: Option<String>,
+    /// `user` when run from the UI, `tool` when requested by the model.
+    pub trigger: String,
+    pub language: String,
+    pub code: String,
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub duration_ms: i64,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExecuteCodeRequest {
+    /// Assistant message whose fenced code block should be run.
+    pub message_id: Option<String>,
+    /// Index among the runnable blocks of `message_id` (default 0).
+    pub block: Option<usize>,
+    /// Explicit snippet; used when `message_id` is not given.
+    pub code: Option<String>,
+    pub language: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListExecutionsQuery {
+    pub user_id: Option<String>,
+    pub limit: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone, sqlx::FromRow)]
@@ -77,6 +462,39 @@ pub struct ChatSession {
     pub title: Option<String>,
     pub created_at: Option<chrono::NaiveDateTime>,
     pub updated_at: Option<chrono::NaiveDateTime>,
+    pub model: Option<String>,
+    pub pinned: bool,
+    pub archived: bool,
+    pub title_customized: bool,
+    pub folder_id: Option<String>,
+    pub persona_id: Option<String>,
+    pub hint_mode: bool,
+    pub hint_level: i32,
+    pub challenge_id: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateChatSessionRequest {
+    pub title: Option<String>,
+    pub pinned: Option<bool>,
+    pub archived: Option<bool>,
+    /// Empty string moves the session out of its folder.
+    pub folder_id: Option<String>,
+    /// Hint mode can only be switched on; leaving it means escalating to the solution.
+    pub hint_mode: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct ChatFolder {
+    pub id: String,
+    pub user_id: String,
+    pub name: String,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatFolderRequest {
+    pub name: String,
 }
 
 #[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
@@ -86,6 +504,87 @@ pub struct ChatMessage {
     pub role: String,
     pub content: String,
     pub created_at: Option<chrono::NaiveDateTime>,
+    /// JSON array of the knowledge base chunks cited for an assistant reply.
+    pub sources: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportQuery {
+    pub format: Option<String>,
+}
+
+/// Portable session format used by the JSON export and `POST /api/chat/import`.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SessionExport {
+    pub version: u32,
+    pub title: Option<String>,
+    pub model: Option<String>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub messages: Vec<ExportedMessage>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ExportedMessage {
+    pub role: String,
+    pub content: String,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub sources: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListChatSessionsQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+    pub model: Option<String>,
+    pub from: Option<chrono::NaiveDate>,
+    pub to: Option<chrono::NaiveDate>,
+    pub folder_id: Option<String>,
+    pub pinned: Option<bool>,
+    /// Archived sessions are hidden unless explicitly requested.
+    pub archived: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatSearchQuery {
+    pub q: String,
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct ChatSearchResult {
+    pub session_id: String,
+    pub session_title: Option<String>,
+    pub message_id: String,
+    pub role: String,
+    pub snippet: String,
+    pub rank: f32,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct SessionShare {
+    pub id: String,
+    pub session_id: String,
+    pub token: String,
+    pub created_by: String,
+    pub expires_at: Option<chrono::NaiveDateTime>,
+    pub revoked: bool,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateShareRequest {
+    pub expires_in_hours: Option<i64>,
+}
+
+/// Read-only transcript served to anyone holding a share token.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct SharedTranscript {
+    pub title: Option<String>,
+    pub model: Option<String>,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub messages: Vec<Message>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -178,6 +677,8 @@ pub struct AIModel {
     pub is_active: bool,
     pub created_at: Option<chrono::NaiveDateTime>,
     pub system_prompt: Option<String>,
+    /// JSON encoded `RetrievalSettings`.
+    pub retrieval_settings: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -186,6 +687,7 @@ pub struct CreateAIModelRequest {
     pub display_name: String,
     pub provider: Option<String>,
     pub system_prompt: Option<String>,
+    pub retrieval_settings: Option<RetrievalSettings>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -195,6 +697,222 @@ pub struct UpdateAIModelRequest {
     pub provider: Option<String>,
     pub is_active: Option<bool>,
     pub system_prompt: Option<String>,
+    pub retrieval_settings: Option<RetrievalSettings>,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct Persona {
+    pub id: String,
+    pub slug: String,
+    pub name: String,
+    pub description: Option<String>,
+    /// `{context}` (or legacy `{}`) is replaced with the retrieved knowledge base context.
+    pub prompt_template: String,
+    /// JSON encoded `RetrievalFilter`.
+    pub retrieval_filter: Option<String>,
+    /// JSON encoded `RetrievalSettings`; takes precedence over the chat model's settings.
+    pub retrieval_settings: Option<String>,
+    pub is_active: bool,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+impl Persona {
+    pub fn filter(&self) -> RetrievalFilter {
+        self.retrieval_filter
+            .as_deref()
+            .and_then(|f| serde_json::from_str(f).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn settings(&self) -> RetrievalSettings {
+        RetrievalSettings::parse(self.retrieval_settings.as_deref())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone)]
+pub struct RetrievalFilter {
+    /// Only search documents whose `metadata.category` is one of these.
+    pub categories: Option<Vec<String>>,
+    /// Only search documents linked to this challenge. Set from the session, never stored on personas.
+    #[serde(skip)]
+    pub challenge_id: Option<String>,
+    /// Collections the requesting user may read; `None` means all. Set per request, never stored on personas.
+    #[serde(skip)]
+    pub collection_ids: Option<Vec<String>>,
+}
+
+/// How many chunks reach the prompt. Unset fields fall back to the chat model's settings,
+/// then to `RERANK_CANDIDATES`, `RERANK_TOP_K` and `RERANK_MIN_SCORE`.
+#[derive(Serialize, Deserialize, Debug, Default, Clone, Copy)]
+pub struct RetrievalSettings {
+    /// Chunks fetched by vector similarity and passed to the reranker.
+    pub candidates: Option<usize>,
+    /// Chunks kept after reranking.
+    pub top_k: Option<usize>,
+    /// Reranker relevance (0 to 1) below which chunks are dropped. Compared against cosine
+    /// similarity when reranking is disabled.
+    pub min_score: Option<f32>,
+}
+
+impl RetrievalSettings {
+    pub fn parse(json: Option<&str>) -> Self {
+        json.and_then(|s| serde_json::from_str(s).ok()).unwrap_or_default()
+    }
+
+    /// Fills fields left unset from `fallback`.
+    pub fn or(self, fallback: RetrievalSettings) -> Self {
+        Self {
+            candidates: self.candidates.or(fallback.candidates),
+            top_k: self.top_k.or(fallback.top_k),
+            min_score: self.min_score.or(fallback.min_score),
+        }
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        if matches!(self.candidates, Some(n) if n == 0 || n > 100) {
+            return Err("candidates must be between 1 and 100".to_string());
+        }
+        if matches!(self.top_k, Some(k) if k == 0 || k > 20) {
+            return Err("top_k must be between 1 and 20".to_string());
+        }
+        if let (Some(n), Some(k)) = (self.candidates, self.top_k) {
+            if k > n {
+                return Err("top_k cannot exceed candidates".to_string());
+            }
+        }
+        if matches!(self.min_score, Some(s) if !(0.0..=1.0).contains(&s)) {
+            return Err("min_score must be between 0 and 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreatePersonaRequest {
+    pub slug: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub prompt_template: String,
+    pub retrieval_filter: Option<RetrievalFilter>,
+    pub retrieval_settings: Option<RetrievalSettings>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdatePersonaRequest {
+    pub slug: Option<String>,
+    pub name: Option<String>,
+    pub description: Option<String>,
+    pub prompt_template: Option<String>,
+    pub retrieval_filter: Option<RetrievalFilter>,
+    pub retrieval_settings: Option<RetrievalSettings>,
+    pub is_active: Option<bool>,
+}
+
+// Challenge Models
+
+pub const CHALLENGE_DIFFICULTIES: &[&str] = &["easy", "medium", "hard", "insane"];
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct Challenge {
+    pub id: String,
+    pub title: String,
+    pub category: String,
+    pub difficulty: String,
+    pub description: String,
+    pub points: i32,
+    #[serde(skip)]
+    pub flag_hash: String,
+    pub is_published: bool,
+    pub created_by: String,
+    pub created_at: Option<chrono::NaiveDateTime>,
+    pub updated_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct ChallengeHint {
+    pub id: String,
+    pub challenge_id: String,
+    pub position: i32,
+    pub content: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct ChallengeAttachment {
+    pub id: String,
+    pub challenge_id: String,
+    pub filename: String,
+    #[serde(skip)]
+    pub storage_path: String,
+    pub size_bytes: i64,
+    pub created_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CreateChallengeRequest {
+    pub title: String,
+    pub category: String,
+    pub difficulty: String,
+    pub description: String,
+    pub points: Option<i32>,
+    pub flag: String,
+    pub is_published: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct UpdateChallengeRequest {
+    pub title: Option<String>,
+    pub category: Option<String>,
+    pub difficulty: Option<String>,
+    pub description: Option<String>,
+    pub points: Option<i32>,
+    /// Replaces the stored flag hash when present.
+    pub flag: Option<String>,
+    pub is_published: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChallengeHintsRequest {
+    /// Hints in the order they are unlocked.
+    pub hints: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct LinkDocumentsRequest {
+    pub document_ids: Vec<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ListChallengesQuery {
+    pub category: Option<String>,
+    pub difficulty: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct FlagSubmission {
+    pub flag: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct ScoreboardEntry {
+    pub user_id: String,
+    pub username: String,
+    pub score: i64,
+    pub solves: i64,
+    pub last_solve: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug, sqlx::FromRow)]
+pub struct CategoryProgress {
+    pub category: String,
+    pub total: i64,
+    pub solved: i64,
+    pub points: i64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ScoreboardQuery {
+    pub category: Option<String>,
+    pub limit: Option<i64>,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
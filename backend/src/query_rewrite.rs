@@ -0,0 +1,186 @@
+use serde_json::json;
+use std::time::{Duration, Instant};
+
+/// Query understanding before retrieval, selected by the `query_rewriting` setting.
+/// Every mode other than `Off` first rewrites follow-ups into a standalone question.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    Off,
+    /// Resolve references to earlier turns ("how do I bypass it?").
+    Rewrite,
+    /// Also search a few narrower sub-queries.
+    MultiQuery,
+    /// Also search a hypothetical answer passage (HyDE), which often lands closer to
+    /// the documents than the question does.
+    Hyde,
+}
+
+impl Mode {
+    pub fn parse(value: &str) -> Self {
+        match value {
+            "rewrite" => Self::Rewrite,
+            "multi" => Self::MultiQuery,
+            "hyde" => Self::Hyde,
+            _ => Self::Off,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Off => "off",
+            Self::Rewrite => "rewrite",
+            Self::MultiQuery => "multi",
+            Self::Hyde => "hyde",
+        }
+    }
+}
+
+/// How many of the most recent messages the rewriter sees.
+pub const HISTORY_TURNS: i64 = 6;
+const MAX_SUB_QUERIES: usize = 3;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// What retrieval searches for one message.
+pub struct Expansion {
+    /// The first is the standalone question, which the reranker scores candidates against;
+    /// the rest only widen the candidate pool.
+    pub queries: Vec<String>,
+    /// Hypothetical answer (HyDE). It reads like a document, so it is embedded as a passage.
+    pub passage: Option<String>,
+}
+
+/// Expands `message` with one provider call per mode. Any failure falls back to searching the
+/// message as typed, so a slow or unavailable provider never blocks the answer.
+pub async fn expand(
+    client: &reqwest::Client,
+    api_key: &str,
+    model: &str,
+    mode: Mode,
+    history: &[(String, String)],
+    message: &str,
+) -> Expansion {
+    let original = Expansion { queries: vec![message.to_string()], passage: None };
+    // Opening questions have nothing to resolve, so plain rewriting skips the round trip
+    if api_key.is_empty() || (mode == Mode::Rewrite && history.is_empty()) {
+        return original;
+    }
+    let (instructions, max_tokens) = match mode {
+        Mode::Off => return original,
+        Mode::Rewrite => (REWRITE_PROMPT, 100),
+        Mode::MultiQuery => (MULTI_QUERY_PROMPT, 200),
+        Mode::Hyde => (HYDE_PROMPT, 300),
+    };
+
+    let started = Instant::now();
+    let text = match complete(client, api_key, model, instructions, &transcript(history, message), max_tokens).await {
+        Ok(text) => text,
+        Err(e) => {
+            println!("Query rewrite [{}] failed: {}", mode.name(), e);
+            return original;
+        }
+    };
+
+    // The first line is always the standalone question
+    let mut lines = text.lines().map(str::trim).skip_while(|l| l.is_empty());
+    let mut expansion = original;
+    match lines.next().map(|l| l.trim_matches('"')).filter(|l| !l.is_empty()) {
+        Some(standalone) if !history.is_empty() => expansion.queries[0] = standalone.to_string(),
+        Some(_) => {}
+        None => println!("Query rewrite [{}]: empty rewrite, searching the message as typed", mode.name()),
+    }
+    let standalone = expansion.queries[0].clone();
+    match mode {
+        Mode::MultiQuery => expansion.queries.extend(
+            lines
+                .map(strip_list_marker)
+                .filter(|l| !l.is_empty() && !l.eq_ignore_ascii_case(&standalone))
+                .take(MAX_SUB_QUERIES)
+                .map(str::to_string),
+        ),
+        Mode::Hyde => {
+            let passage = lines.collect::<Vec<_>>().join("\n");
+            let passage = passage.trim();
+            if !passage.is_empty() {
+                expansion.passage = Some(passage.to_string());
+            }
+        }
+        Mode::Off | Mode::Rewrite => {}
+    }
+
+    println!(
+        "Query rewrite [{}] in {} ms: {:?} -> {:?} (passage: {})",
+        mode.name(),
+        started.elapsed().as_millis(),
+        message,
+        expansion.queries,
+        expansion.passage.is_some()
+    );
+    expansion
+}
+
+const REWRITE_PROMPT: &str = "Rewrite the user's latest message as a standalone search query for a cybersecurity knowledge base. Resolve pronouns and references using the conversation. Keep the user's intent and technical terms; do not answer it. Reply with the query only, on one line.";
+const MULTI_QUERY_PROMPT: &str = "On the first line, rewrite the user's latest message as a standalone search query for a cybersecurity knowledge base, resolving pronouns and references using the conversation. Then write up to 3 short, distinct search queries covering different aspects of it, one per line. Keep technical terms; do not answer it. Reply with the queries only.";
+const HYDE_PROMPT: &str = "On the first line, rewrite the user's latest message as a standalone search query for a cybersecurity knowledge base, resolving pronouns and references using the conversation. Then, after a blank line, write a short, factual passage (under 120 words) from a cybersecurity reference that would answer it. Reply with nothing else.";
+
+/// "1. foo", "2) foo", "- foo" and "* foo" become "foo".
+fn strip_list_marker(line: &str) -> &str {
+    let line = line.trim();
+    if let Some(rest) = line.strip_prefix("- ").or_else(|| line.strip_prefix("* ")) {
+        return rest.trim();
+    }
+    let digits = line.len() - line.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    match line[digits..].strip_prefix(". ").or_else(|| line[digits..].strip_prefix(") ")) {
+        Some(rest) if digits > 0 => rest.trim(),
+        _ => line,
+    }
+}
+
+fn transcript(history: &[(String, String)], message: &str) -> String {
+    let mut text = String::new();
+    for (role, content) in history {
+        let excerpt: String = content.chars().take(600).collect();
+        text.push_str(&format!("{}: {}\n\n", role, excerpt));
+    }
+    text.push_str(&format!("Latest message: {}", message));
+    text
+}
+
+async fn complete(
+    client: &reqwest::Client,
+    api_key: &str,
+    model: &str,
+    instructions: &str,
+    input: &str,
+    max_tokens: u32,
+) -> Result<String, String> {
+    let request_body = json!({
+        "model": model,
+        "messages": [
+            {"role": "system", "content": instructions},
+            {"role": "user", "content": input}
+        ],
+        "max_tokens": max_tokens,
+        "temperature": 0
+    });
+
+    let res = client
+        .post("https://openrouter.ai/api/v1/chat/completions")
+        .header("Authorization", format!("Bearer {}", api_key))
+        .header("Content-Type", "application/json")
+        .header("HTTP-Referer", "https://github.com/Krypton-OSS/KryptonSecAI")
+        .header("X-Title", "KryptonSecAI")
+        .timeout(TIMEOUT)
+        .json(&request_body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+
+    if !res.status().is_success() {
+        return Err(format!("provider returned {}", res.status()));
+    }
+    let json_resp: serde_json::Value = res.json().await.map_err(|e| e.to_string())?;
+    json_resp["choices"][0]["message"]["content"]
+        .as_str()
+        .map(|s| s.trim().to_string())
+        .ok_or_else(|| "no content in response".to_string())
+}
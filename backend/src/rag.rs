@@ -1,61 +1,461 @@
-use fastembed::{EmbeddingModel, InitOptions, TextEmbedding};
 use sqlx::Row;
+use crate::collections::DEFAULT_COLLECTION;
 use crate::db::DbPool;
-use crate::models::RAGChunk;
+use crate::models::{CreateDocumentRequest, DeletedDocument, DocumentVersion, RAGChunk, RetrievalFilter, RetrievalSettings};
 use pgvector::Vector;
-use std::sync::Mutex;
+use crate::embedder::{Embedder, ModelPool, ModelSpec};
+use crate::reranker::Reranker;
 use redis::AsyncCommands;
+use sha2::{Digest, Sha256};
 use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
 use std::hash::{Hash, Hasher};
 
+/// What a bulk load does with a document whose `external_id` or normalized content matches
+/// an existing one.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum ConflictPolicy {
+    /// Keep the existing document and report the new one as skipped.
+    #[default]
+    Skip,
+    /// Overwrite the existing document's content and metadata.
+    Update,
+    /// Insert anyway, pointing `duplicate_of` at the existing document. `external_id` stays
+    /// unique, so a document matched by it is still skipped.
+    Duplicate,
+}
+
+impl ConflictPolicy {
+    pub fn parse(value: Option<&str>) -> Option<Self> {
+        match value.unwrap_or("skip") {
+            "skip" => Some(Self::Skip),
+            "update" => Some(Self::Update),
+            "duplicate" => Some(Self::Duplicate),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Skip => "skip",
+            Self::Update => "update",
+            Self::Duplicate => "duplicate",
+        }
+    }
+}
+
+/// Outcome of a bulk load. Indexes refer to the input; `skipped` carries the id of the existing
+/// document and `failures` the reason a document was not written.
+pub struct BulkInsertResult {
+    pub inserted: usize,
+    pub updated: usize,
+    pub skipped: Vec<(usize, String)>,
+    pub failures: Vec<(usize, String)>,
+}
+
+/// SHA-256 of the content with whitespace runs collapsed and trimmed. Must match the
+/// backfill expression in `db::init_db`.
+pub fn content_hash(content: &str) -> String {
+    let normalized = content
+        .split([' ', '\t', '\n', '\r', '\x0C'])
+        .filter(|part| !part.is_empty())
+        .collect::<Vec<_>>()
+        .join(" ");
+    format!("{:x}", Sha256::digest(normalized.as_bytes()))
+}
+
+pub struct ExistingDocument {
+    pub id: String,
+    pub content_hash: String,
+    pub by_external_id: bool,
+}
+
+struct PlannedInsert {
+    index: usize,
+    id: String,
+    hash: String,
+    duplicate_of: Option<String>,
+}
+
+struct PlannedUpdate {
+    index: usize,
+    id: String,
+    hash: String,
+    /// Content changed, so the embedding must be recomputed.
+    reembed: bool,
+}
+
+#[derive(Default)]
+struct BatchPlan {
+    inserts: Vec<PlannedInsert>,
+    updates: Vec<PlannedUpdate>,
+    skipped: Vec<(usize, String)>,
+}
+
+/// Decides, per document of a batch, whether it is inserted, updates an existing document or is skipped.
+fn plan_batch(batch: &[CreateDocumentRequest], existing: Vec<Option<ExistingDocument>>, policy: ConflictPolicy) -> BatchPlan {
+    let mut plan = BatchPlan::default();
+    // Position in `plan.inserts` of documents added earlier in this batch
+    let mut by_hash: HashMap<String, usize> = HashMap::new();
+    let mut by_external: HashMap<String, usize> = HashMap::new();
+
+    for (index, (doc, found)) in batch.iter().zip(existing).enumerate() {
+        let hash = content_hash(&doc.content);
+        let earlier_external = doc.external_id.as_ref().and_then(|e| by_external.get(e)).copied();
+        let earlier = earlier_external.or_else(|| by_hash.get(&hash).copied());
+
+        if let Some(pos) = earlier {
+            let earlier_id = plan.inserts[pos].id.clone();
+            match policy {
+                ConflictPolicy::Duplicate if earlier_external.is_none() => {
+                    plan.inserts.push(PlannedInsert { index, id: uuid::Uuid::new_v4().to_string(), hash, duplicate_of: Some(earlier_id) });
+                }
+                ConflictPolicy::Update => {
+                    // The later document replaces the earlier one before anything is written
+                    let replaced = std::mem::replace(&mut plan.inserts[pos].index, index);
+                    by_hash.retain(|_, p| *p != pos);
+                    by_hash.insert(hash.clone(), pos);
+                    plan.inserts[pos].hash = hash;
+                    plan.skipped.push((replaced, earlier_id));
+                }
+                _ => plan.skipped.push((index, earlier_id)),
+            }
+            continue;
+        }
+
+        match (found, policy) {
+            (None, _) => {
+                let pos = plan.inserts.len();
+                by_hash.insert(hash.clone(), pos);
+                if let Some(e) = &doc.external_id {
+                    by_external.insert(e.clone(), pos);
+                }
+                plan.inserts.push(PlannedInsert { index, id: uuid::Uuid::new_v4().to_string(), hash, duplicate_of: None });
+            }
+            (Some(found), ConflictPolicy::Update) => {
+                let reembed = found.content_hash != hash;
+                plan.updates.push(PlannedUpdate { index, id: found.id, hash, reembed });
+            }
+            (Some(found), ConflictPolicy::Duplicate) if !found.by_external_id => {
+                plan.inserts.push(PlannedInsert { index, id: uuid::Uuid::new_v4().to_string(), hash, duplicate_of: Some(found.id) });
+            }
+            (Some(found), _) => plan.skipped.push((index, found.id)),
+        }
+    }
+    plan
+}
+
 pub struct RagSystem {
-    model: Mutex<TextEmbedding>,
+    pub embedder: Embedder,
+    /// Second retrieval stage; `None` when `RERANK_MODEL=none`.
+    pub reranker: Option<Reranker>,
+    /// Used for settings neither the persona nor the chat model sets.
+    retrieval_defaults: RetrievalSettings,
     pub pool: DbPool,
     pub redis: Option<redis::Client>,
 }
 
 impl RagSystem {
-    pub fn new(pool: DbPool, redis: Option<redis::Client>) -> Self {
-        let options = InitOptions::new(EmbeddingModel::AllMiniLML6V2)
-            .with_show_download_progress(true);
-
-        let model = TextEmbedding::try_new(options)
-            .expect("Failed to load embedding model");
-
-        Self { model: Mutex::new(model), pool, redis }
+    /// `model` must be the model that embedded the stored documents; see `reindex::active_model`.
+    pub fn new(pool: DbPool, redis: Option<redis::Client>, model: ModelSpec, reranker: Option<Reranker>) -> Self {
+        Self { embedder: Embedder::new(ModelPool::from_env(model)), reranker, retrieval_defaults: retrieval_defaults(), pool, redis }
     }
 
-    fn get_cache_key(&self, query: &str) -> String {
+    /// Keys embed the KB version, so a version bump orphans every older entry.
+    fn get_cache_key(&self, query: &str, passage: bool, limit: usize, filter: &RetrievalFilter, kb_version: i64) -> String {
         let mut hasher = DefaultHasher::new();
         query.hash(&mut hasher);
-        format!("rag:search:{}", hasher.finish())
+        passage.hash(&mut hasher);
+        limit.hash(&mut hasher);
+        filter.categories.hash(&mut hasher);
+        filter.challenge_id.hash(&mut hasher);
+        filter.collection_ids.hash(&mut hasher);
+        format!("rag:search:v{}:{}", kb_version, hasher.finish())
     }
 
-    pub async fn add_document(&self, content: &str, metadata: Option<serde_json::Value>) -> Result<String, Box<dyn std::error::Error>> {
-        let documents = vec![content.to_string()];
-        let embeddings = self.model.lock().unwrap().embed(documents, None)?;
-        let embedding = Vector::from(embeddings[0].clone());
+    pub async fn embed_query(&self, text: &str) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        self.embedder.embed_query(text).await
+    }
+
+    /// Current knowledge base version; cached answers from other versions are never served.
+    pub async fn kb_version(&self) -> i64 {
+        sqlx::query_as::<_, (i64,)>("SELECT generation FROM kb_state WHERE id = 1")
+            .fetch_optional(&self.pool)
+            .await
+            .ok()
+            .flatten()
+            .map(|(g,)| g)
+            .unwrap_or(0)
+    }
+
+    /// Invalidates search and response caches after any change to the knowledge base.
+    pub async fn bump_kb_version(&self) -> i64 {
+        let version = sqlx::query_as::<_, (i64,)>(
+            "UPDATE kb_state SET generation = generation + 1, updated_at = CURRENT_TIMESTAMP WHERE id = 1 RETURNING generation",
+        )
+        .fetch_one(&self.pool)
+        .await
+        .map(|(g,)| g)
+        .unwrap_or(0);
+
+        let _ = sqlx::query("DELETE FROM response_cache WHERE kb_version < $1")
+            .bind(version)
+            .execute(&self.pool)
+            .await;
+        version
+    }
+
+    /// Bumps the KB version and drops every cached search result and response.
+    /// Returns the new version and the number of Redis keys removed.
+    pub async fn flush_caches(&self) -> Result<(i64, usize), Box<dyn std::error::Error>> {
+        let version = self.bump_kb_version().await;
+        sqlx::query("DELETE FROM response_cache").execute(&self.pool).await?;
+
+        let mut removed = 0;
+        if let Some(client) = &self.redis {
+            let mut con = client.get_multiplexed_async_connection().await?;
+            let keys: Vec<String> = {
+                let mut iter: redis::AsyncIter<String> = con.scan_match("rag:search:*").await?;
+                let mut keys = Vec::new();
+                while let Some(key) = iter.next_item().await {
+                    keys.push(key);
+                }
+                keys
+            };
+            for batch in keys.chunks(500) {
+                let _: () = con.del(batch).await?;
+                removed += batch.len();
+            }
+        }
+        Ok((version, removed))
+    }
+
+    /// Adds a document unless one with the same normalized content exists, returning the id of either.
+    pub async fn add_document(&self, content: &str, metadata: Option<serde_json::Value>, collection_id: &str) -> Result<String, Box<dyn std::error::Error>> {
+        let hash = content_hash(content);
+        let existing: Option<(String,)> = sqlx::query_as("SELECT id FROM documents WHERE content_hash = $1 AND duplicate_of IS NULL AND deleted_at IS NULL")
+            .bind(&hash)
+            .fetch_optional(&self.pool)
+            .await?;
+        if let Some((id,)) = existing {
+            return Ok(id);
+        }
+
+        let embedder = self.embedder.current();
+        let embedding = Vector::from(embedder.embed_one(content).await?);
 
         let id = uuid::Uuid::new_v4().to_string();
         let metadata_str = metadata.map(|m| m.to_string());
 
         sqlx::query(
-            "INSERT INTO documents (id, content, metadata, embedding) VALUES ($1, $2, $3, $4)",
+            "INSERT INTO documents (id, content, metadata, embedding, content_hash, collection_id, embedding_model) VALUES ($1, $2, $3, $4, $5, $6, $7)",
         )
         .bind(&id)
         .bind(content)
         .bind(metadata_str)
         .bind(embedding)
+        .bind(hash)
+        .bind(collection_id)
+        .bind(&embedder.spec.name)
         .execute(&self.pool)
         .await?;
 
         Ok(id)
     }
 
-    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<RAGChunk>, Box<dyn std::error::Error>> {
+    /// For each document, the existing document it collides with: the one with the same
+    /// `external_id`, otherwise the original with the same normalized content.
+    pub async fn find_existing(&self, docs: &[CreateDocumentRequest]) -> Result<Vec<Option<ExistingDocument>>, sqlx::Error> {
+        let hashes: Vec<String> = docs.iter().map(|d| content_hash(&d.content)).collect();
+        let external_ids: Vec<String> = docs.iter().filter_map(|d| d.external_id.clone()).collect();
+        let rows: Vec<(String, String, Option<String>, Option<String>)> = sqlx::query_as(
+            "SELECT id, content_hash, external_id, duplicate_of FROM documents
+             WHERE deleted_at IS NULL AND (external_id = ANY($1) OR (content_hash = ANY($2) AND duplicate_of IS NULL))",
+        )
+        .bind(&external_ids)
+        .bind(&hashes)
+        .fetch_all(&self.pool)
+        .await?;
+
+        let mut by_external = HashMap::new();
+        let mut by_hash = HashMap::new();
+        for (id, hash, external_id, duplicate_of) in rows {
+            if let Some(external_id) = external_id {
+                by_external.insert(external_id, (id.clone(), hash.clone()));
+            }
+            if duplicate_of.is_none() {
+                by_hash.insert(hash.clone(), (id, hash));
+            }
+        }
+
+        Ok(docs
+            .iter()
+            .zip(&hashes)
+            .map(|(doc, hash)| {
+                if let Some((id, content_hash)) = doc.external_id.as_ref().and_then(|e| by_external.get(e)) {
+                    return Some(ExistingDocument { id: id.clone(), content_hash: content_hash.clone(), by_external_id: true });
+                }
+                by_hash.get(hash).map(|(id, content_hash)| ExistingDocument {
+                    id: id.clone(),
+                    content_hash: content_hash.clone(),
+                    by_external_id: false,
+                })
+            })
+            .collect())
+    }
+
+    /// Inserting into an HNSW index row by row is slow, so very large loads drop it first.
+    pub async fn drop_vector_index(&self) -> Result<(), sqlx::Error> {
+        sqlx::query("DROP INDEX IF EXISTS documents_embedding_idx").execute(&self.pool).await?;
+        Ok(())
+    }
+
+    pub async fn rebuild_vector_index(&self) -> Result<(), sqlx::Error> {
+        println!("Rebuilding vector index...");
+        sqlx::query("CREATE INDEX IF NOT EXISTS documents_embedding_idx ON documents USING hnsw (embedding vector_cosine_ops)")
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    /// Embeds documents in batches of `batch_size` and writes each batch with one multi-row INSERT.
+    /// Collisions with existing documents are resolved by `policy`; within a batch, a later
+    /// collision skips (or, under `Update`, replaces) the earlier document. `on_batch(written, failed)`
+    /// reports running totals after every batch. The HNSW index is left in place; only background
+    /// import jobs drop it for very large loads, since a request future can be cancelled mid-load.
+    pub async fn add_documents_bulk<F: FnMut(usize, usize)>(
+        &self,
+        docs: Vec<CreateDocumentRequest>,
+        policy: ConflictPolicy,
+        author: Option<&str>,
+        batch_size: usize,
+        mut on_batch: F,
+    ) -> Result<BulkInsertResult, Box<dyn std::error::Error>> {
+        // Postgres caps a statement at 65535 bind parameters (9 per row here)
+        let batch_size = batch_size.clamp(1, 1000);
+
+        let mut result = BulkInsertResult { inserted: 0, updated: 0, skipped: Vec::new(), failures: Vec::new() };
+        for (batch_no, batch) in docs.chunks(batch_size).enumerate() {
+            let first = batch_no * batch_size;
+            let existing = match self.find_existing(batch).await {
+                Ok(e) => e,
+                Err(e) => {
+                    let reason = format!("duplicate check failed: {}", e);
+                    result.failures.extend((first..first + batch.len()).map(|i| (i, reason.clone())));
+                    on_batch(result.inserted + result.updated, result.failures.len());
+                    continue;
+                }
+            };
+
+            let plan = plan_batch(batch, existing, policy);
+            result.skipped.extend(plan.skipped.into_iter().map(|(i, id)| (first + i, id)));
+
+            // Bulk loads yield to interactive embedding requests
+            while self.embedder.is_saturated() {
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+
+            let texts: Vec<String> = plan
+                .inserts
+                .iter()
+                .map(|p| p.index)
+                .chain(plan.updates.iter().filter(|u| u.reembed).map(|u| u.index))
+                .map(|i| batch[i].content.clone())
+                .collect();
+            let embedder = self.embedder.current();
+            let mut embeddings = match embedder.embed(texts).await {
+                Ok(e) => e.into_iter(),
+                Err(e) => {
+                    eprintln!("Failed to embed batch: {}", e);
+                    let reason = format!("embedding failed: {}", e);
+                    let indexes = plan.inserts.iter().map(|p| p.index).chain(plan.updates.iter().map(|u| u.index));
+                    result.failures.extend(indexes.map(|i| (first + i, reason.clone())));
+                    on_batch(result.inserted + result.updated, result.failures.len());
+                    continue;
+                }
+            };
+
+            if !plan.inserts.is_empty() {
+                let rows: Vec<(&PlannedInsert, Vec<f32>)> = plan.inserts.iter().zip(embeddings.by_ref()).collect();
+                let mut qb = sqlx::QueryBuilder::<sqlx::Postgres>::new(
+                    "INSERT INTO documents (id, content, metadata, embedding, content_hash, external_id, duplicate_of, collection_id, embedding_model) ",
+                );
+                qb.push_values(&rows, |mut row, (insert, embedding)| {
+                    let doc = &batch[insert.index];
+                    row.push_bind(insert.id.clone())
+                        .push_bind(doc.content.clone())
+                        .push_bind(doc.metadata.as_ref().map(|m| m.to_string()))
+                        .push_bind(Vector::from(embedding.clone()))
+                        .push_bind(insert.hash.clone())
+                        .push_bind(doc.external_id.clone())
+                        .push_bind(insert.duplicate_of.clone())
+                        .push_bind(doc.collection_id.clone().unwrap_or_else(|| DEFAULT_COLLECTION.to_string()))
+                        .push_bind(embedder.spec.name.clone());
+                });
+                // A concurrent load may have written the same content or external_id since the check
+                qb.push(" ON CONFLICT DO NOTHING RETURNING id");
+                match qb.build_query_as::<(String,)>().fetch_all(&self.pool).await {
+                    Ok(written) => {
+                        let written: std::collections::HashSet<String> = written.into_iter().map(|(id,)| id).collect();
+                        result.inserted += written.len();
+                        for insert in plan.inserts.iter().filter(|p| !written.contains(&p.id)) {
+                            result.failures.push((first + insert.index, "conflicts with a document written concurrently".to_string()));
+                        }
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to insert batch: {}", e);
+                        let reason = format!("insert failed: {}", e);
+                        result.failures.extend(plan.inserts.iter().map(|p| (first + p.index, reason.clone())));
+                    }
+                }
+            }
+
+            for update in &plan.updates {
+                let doc = &batch[update.index];
+                let metadata = doc.metadata.as_ref().map(|m| m.to_string());
+                let query = if update.reembed {
+                    let embedding = embeddings.next().unwrap_or_default();
+                    sqlx::query(
+                        "UPDATE documents SET content = $1, metadata = $2, external_id = COALESCE(external_id, $3), content_hash = $4, embedding = $5,
+                         collection_id = COALESCE($6, collection_id), embedding_model = $7 WHERE id = $8",
+                    )
+                    .bind(&doc.content)
+                    .bind(metadata)
+                    .bind(&doc.external_id)
+                    .bind(&update.hash)
+                    .bind(Vector::from(embedding))
+                    .bind(&doc.collection_id)
+                    .bind(&embedder.spec.name)
+                    .bind(&update.id)
+                } else {
+                    sqlx::query("UPDATE documents SET metadata = $1, external_id = COALESCE(external_id, $2), collection_id = COALESCE($3, collection_id) WHERE id = $4")
+                        .bind(metadata)
+                        .bind(&doc.external_id)
+                        .bind(&doc.collection_id)
+                        .bind(&update.id)
+                };
+                match self.versioned_update(&update.id, query, author).await {
+                    Ok(true) => result.updated += 1,
+                    Ok(false) => result.failures.push((first + update.index, "existing document was deleted during the load".to_string())),
+                    Err(e) => result.failures.push((first + update.index, format!("update failed: {}", e))),
+                }
+            }
+            on_batch(result.inserted + result.updated, result.failures.len());
+        }
+
+        if result.inserted + result.updated > 0 {
+            self.bump_kb_version().await;
+        }
+        Ok(result)
+    }
+
+    /// Nearest chunks to `query`. A `passage` is document-like text (such as a hypothetical
+    /// answer) and is embedded the way documents are rather than as a search query.
+    pub async fn search(&self, query: &str, passage: bool, limit: usize, filter: &RetrievalFilter) -> Result<Vec<RAGChunk>, Box<dyn std::error::Error>> {
         // 1. Try Cache
+        let kb_version = self.kb_version().await;
         if let Some(client) = &self.redis {
-            let key = self.get_cache_key(query);
+            let key = self.get_cache_key(query, passage, limit, filter, kb_version);
             if let Ok(mut con) = client.get_multiplexed_async_connection().await {
                 let cached: Option<String> = con.get(&key).await.unwrap_or(None);
                 if let Some(json) = cached {
@@ -66,16 +466,25 @@ impl RagSystem {
             }
         }
 
-        let query_embedding = self.model.lock().unwrap().embed(vec![query.to_string()], None)?;
-        let query_vec = Vector::from(query_embedding[0].clone());
+        let query_vec = if passage { self.embedder.current().embed_one(query).await? } else { self.embed_query(query).await? };
+        let query_vec = Vector::from(query_vec);
 
         // Use pgvector cosine distance operator <=>
         // Distance is 0 to 2. Similarity is roughly 1 - distance (for normalized vectors).
+        // An empty category list means no filtering
         let rows = sqlx::query(
-            "SELECT id, content, 1 - (embedding <=> $1) as score FROM documents ORDER BY embedding <=> $1 LIMIT $2"
+            "SELECT id, content, 1 - (embedding <=> $1) as score FROM documents
+             WHERE deleted_at IS NULL
+               AND ($3::text[] IS NULL OR (metadata::jsonb ->> 'category') = ANY($3))
+               AND ($4::text IS NULL OR challenge_id = $4)
+               AND ($5::text[] IS NULL OR collection_id = ANY($5))
+             ORDER BY embedding <=> $1 LIMIT $2"
         )
         .bind(query_vec)
         .bind(limit as i64)
+        .bind(filter.categories.as_ref().filter(|c| !c.is_empty()))
+        .bind(&filter.challenge_id)
+        .bind(&filter.collection_ids)
         .fetch_all(&self.pool)
         .await?;
 
@@ -91,7 +500,7 @@ impl RagSystem {
 
         // 2. Save to Cache (TTL: 1 hour)
         if let Some(client) = &self.redis {
-            let key = self.get_cache_key(query);
+            let key = self.get_cache_key(query, passage, limit, filter, kb_version);
             if let Ok(json) = serde_json::to_string(&chunks) {
                 if let Ok(mut con) = client.get_multiplexed_async_connection().await {
                     let _: () = con.set_ex(key, json, 3600).await.unwrap_or(());
@@ -102,49 +511,399 @@ impl RagSystem {
         Ok(chunks)
     }
 
-    pub async fn list_documents(&self, limit: i64, offset: i64) -> Result<Vec<crate::models::DocumentSummary>, Box<dyn std::error::Error>> {
+    /// Two-stage retrieval: over-fetches candidates by vector similarity for every query and the
+    /// optional hypothetical answer `passage`, merges them, reranks them with the cross-encoder
+    /// against the first query and keeps the best `top_k` that clear `min_score`. May return nothing.
+    pub async fn retrieve(&self, queries: &[String], passage: Option<&str>, settings: &RetrievalSettings, filter: &RetrievalFilter) -> Result<Vec<RAGChunk>, Box<dyn std::error::Error>> {
+        let Some(primary) = queries.first() else { return Ok(Vec::new()) };
+        let settings = settings.or(self.retrieval_defaults);
+        let top_k = settings.top_k.unwrap_or(3);
+        // Cosine similarity has no comparable cut-off, so without a reranker nothing is dropped by default
+        let min_score = settings.min_score.unwrap_or(if self.reranker.is_some() { 0.1 } else { 0.0 });
+        let fetch = match &self.reranker {
+            Some(_) => settings.candidates.unwrap_or(20).max(top_k),
+            None => top_k,
+        };
+
+        // A chunk found by several queries keeps its best similarity
+        let mut chunks: Vec<RAGChunk> = Vec::new();
+        let searches = queries.iter().map(|q| (q.as_str(), false)).chain(passage.map(|p| (p, true)));
+        for (query, is_passage) in searches {
+            for chunk in self.search(query, is_passage, fetch, filter).await? {
+                match chunks.iter_mut().find(|c| c.id == chunk.id) {
+                    Some(existing) => existing.score = existing.score.max(chunk.score),
+                    None => chunks.push(chunk),
+                }
+            }
+        }
+
+        if let Some(reranker) = &self.reranker {
+            let scores = reranker.score(primary, chunks.iter().map(|c| c.content.clone()).collect()).await?;
+            for (chunk, score) in chunks.iter_mut().zip(scores) {
+                chunk.score = score;
+            }
+        }
+        chunks.sort_by(|a, b| b.score.total_cmp(&a.score));
+        chunks.retain(|c| c.score >= min_score);
+        chunks.truncate(top_k);
+        Ok(chunks)
+    }
+
+    pub async fn list_documents(&self, limit: i64, offset: i64, collection_id: Option<&str>) -> Result<Vec<crate::models::DocumentSummary>, Box<dyn std::error::Error>> {
         let rows = sqlx::query_as::<_, crate::models::DocumentSummary>(
-            "SELECT id, content, metadata FROM documents ORDER BY id DESC LIMIT $1 OFFSET $2"
+            "SELECT id, content, metadata, challenge_id, collection_id FROM documents
+             WHERE deleted_at IS NULL AND ($3::text IS NULL OR collection_id = $3)
+             ORDER BY id DESC LIMIT $1 OFFSET $2"
         )
         .bind(limit)
         .bind(offset)
+        .bind(collection_id)
         .fetch_all(&self.pool)
         .await?;
 
         Ok(rows)
     }
 
-    pub async fn count_documents(&self) -> Result<i64, Box<dyn std::error::Error>> {
-        let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM documents")
+    pub async fn count_documents(&self, collection_id: Option<&str>) -> Result<i64, Box<dyn std::error::Error>> {
+        let count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM documents WHERE deleted_at IS NULL AND ($1::text IS NULL OR collection_id = $1)")
+            .bind(collection_id)
             .fetch_one(&self.pool)
             .await?;
         Ok(count.0)
     }
 
-    pub async fn update_document(&self, id: &str, content: &str, metadata: Option<serde_json::Value>) -> Result<(), Box<dyn std::error::Error>> {
-        let documents = vec![content.to_string()];
-        let embeddings = self.model.lock().unwrap().embed(documents, None)?;
-        let embedding = Vector::from(embeddings[0].clone());
+    /// Moves documents (including trashed ones) into a collection. Returns the number moved.
+    pub async fn move_documents(&self, ids: &[String], collection_id: &str) -> Result<u64, Box<dyn std::error::Error>> {
+        let result = sqlx::query("UPDATE documents SET collection_id = $1 WHERE id = ANY($2) AND collection_id <> $1")
+            .bind(collection_id)
+            .bind(ids)
+            .execute(&self.pool)
+            .await?;
+        if result.rows_affected() > 0 {
+            self.bump_kb_version().await;
+        }
+        Ok(result.rows_affected())
+    }
+
+    pub async fn update_document(&self, id: &str, content: &str, metadata: Option<serde_json::Value>, author: Option<&str>) -> Result<bool, Box<dyn std::error::Error>> {
         let metadata_str = metadata.map(|m| m.to_string());
+        self.rewrite_document(id, content, metadata_str, author, "updated", None).await
+    }
 
+    /// Replaces content and metadata, re-embedding the content, and records the result as a new
+    /// version. Returns false when the document does not exist or is in the trash.
+    async fn rewrite_document(
+        &self,
+        id: &str,
+        content: &str,
+        metadata: Option<String>,
+        author: Option<&str>,
+        action: &str,
+        restored_from: Option<i32>,
+    ) -> Result<bool, Box<dyn std::error::Error>> {
+        let hash = content_hash(content);
+        // Unchanged content is never a clash, even for documents kept as duplicates
+        let clash: Option<(String,)> = sqlx::query_as(
+            "SELECT id FROM documents WHERE content_hash = $1 AND duplicate_of IS NULL AND deleted_at IS NULL AND id <> $2
+               AND NOT EXISTS (SELECT 1 FROM documents s WHERE s.id = $2 AND s.content_hash = $1)",
+        )
+        .bind(&hash)
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some((other,)) = clash {
+            return Err(format!("document {} already has this content", other).into());
+        }
+
+        let embedder = self.embedder.current();
+        let embedding = Vector::from(embedder.embed_one(content).await?);
+
+        let mut tx = self.pool.begin().await?;
+        if !lock_live_document(&mut tx, id).await? {
+            return Ok(false);
+        }
+        record_baseline(&mut tx, id).await?;
         sqlx::query(
-            "UPDATE documents SET content = $1, metadata = $2, embedding = $3 WHERE id = $4"
+            "UPDATE documents SET content = $1, metadata = $2, embedding = $3, embedding_model = $4,
+                 duplicate_of = CASE WHEN content_hash = $5 THEN duplicate_of END, content_hash = $5 WHERE id = $6"
         )
         .bind(content)
-        .bind(metadata_str)
+        .bind(metadata)
         .bind(embedding)
+        .bind(&embedder.spec.name)
+        .bind(hash)
         .bind(id)
-        .execute(&self.pool)
+        .execute(&mut *tx)
         .await?;
+        record_version(&mut tx, id, action, author, restored_from).await?;
+        tx.commit().await?;
 
-        Ok(())
+        self.bump_kb_version().await;
+        Ok(true)
+    }
+
+    /// Runs `update` on a live document inside a transaction that records the change as a version.
+    async fn versioned_update(
+        &self,
+        id: &str,
+        update: sqlx::query::Query<'_, sqlx::Postgres, sqlx::postgres::PgArguments>,
+        author: Option<&str>,
+    ) -> Result<bool, sqlx::Error> {
+        let mut tx = self.pool.begin().await?;
+        if !lock_live_document(&mut tx, id).await? {
+            return Ok(false);
+        }
+        record_baseline(&mut tx, id).await?;
+        update.execute(&mut *tx).await?;
+        record_version(&mut tx, id, "updated", author, None).await?;
+        tx.commit().await?;
+        Ok(true)
     }
 
-    pub async fn delete_document(&self, id: &str) -> Result<(), Box<dyn std::error::Error>> {
-        sqlx::query("DELETE FROM documents WHERE id = $1")
+    /// Moves a document to the trash; it stops being searchable but keeps its history.
+    pub async fn delete_document(&self, id: &str, author: Option<&str>) -> Result<bool, Box<dyn std::error::Error>> {
+        let mut tx = self.pool.begin().await?;
+        if !lock_live_document(&mut tx, id).await? {
+            return Ok(false);
+        }
+        record_baseline(&mut tx, id).await?;
+        sqlx::query("UPDATE documents SET deleted_at = CURRENT_TIMESTAMP, deleted_by = $2 WHERE id = $1")
+            .bind(id)
+            .bind(author)
+            .execute(&mut *tx)
+            .await?;
+        record_version(&mut tx, id, "deleted", author, None).await?;
+        tx.commit().await?;
+
+        self.bump_kb_version().await;
+        Ok(true)
+    }
+
+    /// Takes a document out of the trash. Fails if a live document now has the same content or external_id.
+    pub async fn undelete_document(&self, id: &str, author: Option<&str>) -> Result<bool, Box<dyn std::error::Error>> {
+        let clash: Option<(String,)> = sqlx::query_as(
+            "SELECT o.id FROM documents d JOIN documents o ON o.id <> d.id AND o.deleted_at IS NULL
+             WHERE d.id = $1 AND ((d.duplicate_of IS NULL AND o.duplicate_of IS NULL AND o.content_hash = d.content_hash)
+                                  OR o.external_id = d.external_id)
+             LIMIT 1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?;
+        if let Some((other,)) = clash {
+            return Err(format!("document {} has the same content or external_id", other).into());
+        }
+
+        let mut tx = self.pool.begin().await?;
+        let restored = sqlx::query("UPDATE documents SET deleted_at = NULL, deleted_by = NULL WHERE id = $1 AND deleted_at IS NOT NULL")
+            .bind(id)
+            .execute(&mut *tx)
+            .await?;
+        if restored.rows_affected() == 0 {
+            return Ok(false);
+        }
+        record_version(&mut tx, id, "undeleted", author, None).await?;
+        tx.commit().await?;
+
+        self.bump_kb_version().await;
+        Ok(true)
+    }
+
+    /// Permanently removes a document from the trash, with its history.
+    pub async fn purge_document(&self, id: &str) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM documents WHERE id = $1 AND deleted_at IS NOT NULL")
             .bind(id)
             .execute(&self.pool)
             .await?;
-        Ok(())
+        Ok(result.rows_affected() > 0)
+    }
+
+    pub async fn list_trash(&self, limit: i64, offset: i64) -> Result<Vec<DeletedDocument>, sqlx::Error> {
+        sqlx::query_as::<_, DeletedDocument>(
+            "SELECT d.id, d.content, d.metadata, d.deleted_at, d.deleted_by, u.username AS deleted_by_name
+             FROM documents d LEFT JOIN users u ON u.id = d.deleted_by
+             WHERE d.deleted_at IS NOT NULL ORDER BY d.deleted_at DESC LIMIT $1 OFFSET $2",
+        )
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    /// Versions newest first. A document never changed since it was added has none.
+    pub async fn list_versions(&self, id: &str) -> Result<Vec<DocumentVersion>, sqlx::Error> {
+        sqlx::query_as::<_, DocumentVersion>(
+            "SELECT v.document_id, v.version, v.content, v.metadata, v.action, v.restored_from, v.author_id, u.username AS author_name, v.created_at
+             FROM document_versions v LEFT JOIN users u ON u.id = v.author_id
+             WHERE v.document_id = $1 ORDER BY v.version DESC",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    pub async fn get_version(&self, id: &str, version: i32) -> Result<Option<DocumentVersion>, sqlx::Error> {
+        sqlx::query_as::<_, DocumentVersion>(
+            "SELECT v.document_id, v.version, v.content, v.metadata, v.action, v.restored_from, v.author_id, u.username AS author_name, v.created_at
+             FROM document_versions v LEFT JOIN users u ON u.id = v.author_id
+             WHERE v.document_id = $1 AND v.version = $2",
+        )
+        .bind(id)
+        .bind(version)
+        .fetch_optional(&self.pool)
+        .await
+    }
+
+    /// Current content and metadata of a live or trashed document.
+    pub async fn get_document(&self, id: &str) -> Result<Option<(String, Option<String>)>, sqlx::Error> {
+        sqlx::query_as("SELECT content, metadata FROM documents WHERE id = $1")
+            .bind(id)
+            .fetch_optional(&self.pool)
+            .await
+    }
+
+    /// Brings back the content and metadata of `version` as a new version, re-embedding the content.
+    /// Returns false when the document or version does not exist, or the document is in the trash.
+    pub async fn restore_version(&self, id: &str, version: i32, author: Option<&str>) -> Result<bool, Box<dyn std::error::Error>> {
+        let Some(snapshot) = self.get_version(id, version).await? else {
+            return Ok(false);
+        };
+        self.rewrite_document(id, &snapshot.content, snapshot.metadata, author, "restored", Some(version)).await
+    }
+}
+
+/// Locks a document row for a versioned change; false if it does not exist or is in the trash.
+async fn lock_live_document(conn: &mut sqlx::PgConnection, id: &str) -> Result<bool, sqlx::Error> {
+    let row: Option<(String,)> = sqlx::query_as("SELECT id FROM documents WHERE id = $1 AND deleted_at IS NULL FOR UPDATE")
+        .bind(id)
+        .fetch_optional(&mut *conn)
+        .await?;
+    Ok(row.is_some())
+}
+
+/// Inserts and bulk loads are not versioned, so the first change to a document first records
+/// its existing state as version 1.
+async fn record_baseline(conn: &mut sqlx::PgConnection, id: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO document_versions (document_id, version, content, metadata, action)
+         SELECT d.id, 1, d.content, d.metadata, 'created' FROM documents d
+         WHERE d.id = $1 AND NOT EXISTS (SELECT 1 FROM document_versions v WHERE v.document_id = d.id)",
+    )
+    .bind(id)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Snapshots the document's current state as its next version.
+async fn record_version(conn: &mut sqlx::PgConnection, id: &str, action: &str, author: Option<&str>, restored_from: Option<i32>) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO document_versions (document_id, version, content, metadata, action, restored_from, author_id)
+         SELECT d.id, COALESCE((SELECT MAX(v.version) FROM document_versions v WHERE v.document_id = d.id), 0) + 1,
+                d.content, d.metadata, $2, $3, $4
+         FROM documents d WHERE d.id = $1",
+    )
+    .bind(id)
+    .bind(action)
+    .bind(restored_from)
+    .bind(author)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+/// Server-wide retrieval settings from `RERANK_CANDIDATES`, `RERANK_TOP_K` and `RERANK_MIN_SCORE`.
+fn retrieval_defaults() -> RetrievalSettings {
+    fn env<T: std::str::FromStr>(key: &str) -> Option<T> {
+        std::env::var(key).ok().and_then(|v| v.trim().parse().ok())
+    }
+    let defaults = RetrievalSettings { candidates: env("RERANK_CANDIDATES"), top_k: env("RERANK_TOP_K"), min_score: env("RERANK_MIN_SCORE") };
+    if let Err(e) = defaults.validate() {
+        eprintln!("Ignoring retrieval settings from the environment: {}", e);
+        return RetrievalSettings::default();
+    }
+    defaults
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn doc(content: &str, external_id: Option<&str>) -> CreateDocumentRequest {
+        CreateDocumentRequest { content: content.to_string(), metadata: None, external_id: external_id.map(str::to_string), collection_id: None }
+    }
+
+    fn existing(id: &str, content: &str, by_external_id: bool) -> Option<ExistingDocument> {
+        Some(ExistingDocument { id: id.to_string(), content_hash: content_hash(content), by_external_id })
+    }
+
+    #[test]
+    fn new_documents_are_inserted_under_every_policy() {
+        for policy in [ConflictPolicy::Skip, ConflictPolicy::Update, ConflictPolicy::Duplicate] {
+            let plan = plan_batch(&[doc("a", None), doc("b", Some("x"))], vec![None, None], policy);
+            assert_eq!(plan.inserts.len(), 2, "{:?}", policy);
+            assert!(plan.updates.is_empty() && plan.skipped.is_empty());
+            assert!(plan.inserts.iter().all(|p| p.duplicate_of.is_none()));
+        }
+    }
+
+    #[test]
+    fn content_match_with_stored_document() {
+        let batch = [doc("same", None)];
+
+        let plan = plan_batch(&batch, vec![existing("old", "same", false)], ConflictPolicy::Skip);
+        assert_eq!(plan.skipped, vec![(0, "old".to_string())]);
+        assert!(plan.inserts.is_empty());
+
+        let plan = plan_batch(&batch, vec![existing("old", "same", false)], ConflictPolicy::Update);
+        assert_eq!(plan.updates.len(), 1);
+        assert_eq!(plan.updates[0].id, "old");
+        assert!(!plan.updates[0].reembed);
+
+        let plan = plan_batch(&batch, vec![existing("old", "same", false)], ConflictPolicy::Duplicate);
+        assert_eq!(plan.inserts.len(), 1);
+        assert_eq!(plan.inserts[0].duplicate_of.as_deref(), Some("old"));
+    }
+
+    #[test]
+    fn external_id_match_with_stored_document() {
+        let batch = [doc("new content", Some("x"))];
+
+        let plan = plan_batch(&batch, vec![existing("old", "old content", true)], ConflictPolicy::Skip);
+        assert_eq!(plan.skipped, vec![(0, "old".to_string())]);
+
+        let plan = plan_batch(&batch, vec![existing("old", "old content", true)], ConflictPolicy::Update);
+        assert_eq!(plan.updates.len(), 1);
+        assert!(plan.updates[0].reembed);
+
+        // external_id stays unique, so Duplicate cannot insert it again
+        let plan = plan_batch(&batch, vec![existing("old", "old content", true)], ConflictPolicy::Duplicate);
+        assert!(plan.inserts.is_empty());
+        assert_eq!(plan.skipped, vec![(0, "old".to_string())]);
+    }
+
+    #[test]
+    fn duplicates_within_a_batch() {
+        let batch = [doc("same", None), doc("same", None)];
+
+        let plan = plan_batch(&batch, vec![None, None], ConflictPolicy::Skip);
+        assert_eq!(plan.inserts.len(), 1);
+        assert_eq!(plan.inserts[0].index, 0);
+        assert_eq!(plan.skipped, vec![(1, plan.inserts[0].id.clone())]);
+
+        // The later document wins and the earlier one is reported as skipped
+        let plan = plan_batch(&batch, vec![None, None], ConflictPolicy::Update);
+        assert_eq!(plan.inserts.len(), 1);
+        assert_eq!(plan.inserts[0].index, 1);
+        assert_eq!(plan.skipped, vec![(0, plan.inserts[0].id.clone())]);
+
+        let plan = plan_batch(&batch, vec![None, None], ConflictPolicy::Duplicate);
+        assert_eq!(plan.inserts.len(), 2);
+        assert_eq!(plan.inserts[1].duplicate_of.as_ref(), Some(&plan.inserts[0].id));
+
+        let batch = [doc("one", Some("x")), doc("two", Some("x"))];
+        let plan = plan_batch(&batch, vec![None, None], ConflictPolicy::Duplicate);
+        assert_eq!(plan.inserts.len(), 1);
+        assert_eq!(plan.skipped.len(), 1);
     }
 }
@@ -0,0 +1,412 @@
+use crate::db::DbPool;
+use crate::embedder::{ModelPool, ModelSpec, LEGACY_MODEL};
+use crate::models::ReindexJob;
+use crate::rag::RagSystem;
+use pgvector::Vector;
+use std::sync::Arc;
+
+/// While a job runs, target-model embeddings are written next to the live ones in these
+/// columns, with the content hash they were computed from so edits made during the job are
+/// detected and re-embedded. Their HNSW index is built concurrently, so cutover is a rename.
+const NEXT_COLUMN: &str = "embedding_next";
+const NEXT_INDEX: &str = "documents_embedding_next_idx";
+
+/// Returns the model that embedded the stored documents. On the first start with model tracking,
+/// existing documents are attributed to the legacy model; an empty database adopts `configured`.
+pub async fn active_model(pool: &DbPool, configured: &ModelSpec) -> ModelSpec {
+    let state: Option<(String,)> = sqlx::query_as("SELECT model FROM embedding_state WHERE id = 1")
+        .fetch_optional(pool)
+        .await
+        .expect("Failed to read embedding state");
+    if let Some((name,)) = state {
+        return ModelSpec::resolve(&name).unwrap_or_else(|e| panic!("Stored embedding model is not available: {}", e));
+    }
+
+    let (documents,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM documents")
+        .fetch_one(pool)
+        .await
+        .expect("Failed to count documents");
+    let spec = if documents > 0 {
+        ModelSpec::from_model(&LEGACY_MODEL)
+    } else {
+        // Nothing to migrate, so the columns are resized in place
+        for table in ["documents", "response_cache"] {
+            if column_dim(pool, table, "embedding").await != Some(configured.dim as i32) {
+                let resize = format!("ALTER TABLE {} ALTER COLUMN embedding TYPE vector({})", table, configured.dim);
+                sqlx::query(&resize).execute(pool).await.expect("Failed to resize embedding column");
+            }
+        }
+        configured.clone()
+    };
+
+    sqlx::query("INSERT INTO embedding_state (id, model, dim) VALUES (1, $1, $2) ON CONFLICT (id) DO NOTHING")
+        .bind(&spec.name)
+        .bind(spec.dim as i32)
+        .execute(pool)
+        .await
+        .expect("Failed to record embedding state");
+    sqlx::query("UPDATE documents SET embedding_model = $1 WHERE embedding_model IS NULL")
+        .bind(&spec.name)
+        .execute(pool)
+        .await
+        .ok();
+    println!("Embedding model {} ({} dimensions) recorded as active", spec.name, spec.dim);
+    spec
+}
+
+/// Declared dimension of a vector column, if the column exists.
+async fn column_dim(pool: &DbPool, table: &str, column: &str) -> Option<i32> {
+    sqlx::query_as::<_, (i32,)>("SELECT atttypmod FROM pg_attribute WHERE attrelid = to_regclass($1) AND attname = $2 AND NOT attisdropped")
+        .bind(table)
+        .bind(column)
+        .fetch_optional(pool)
+        .await
+        .ok()
+        .flatten()
+        .map(|(dim,)| dim)
+}
+
+/// Re-queues a job interrupted by a restart, or starts one when `EMBEDDING_MODEL` names a model
+/// other than the active one. A configured model whose last job was cancelled is left alone.
+pub async fn resume_or_start(rag: Arc<RagSystem>, configured: &ModelSpec) {
+    let pending: Option<(String,)> = sqlx::query_as("SELECT id FROM reindex_jobs WHERE status IN ('queued', 'running') ORDER BY created_at LIMIT 1")
+        .fetch_optional(&rag.pool)
+        .await
+        .unwrap_or(None);
+    if let Some((id,)) = pending {
+        println!("Resuming re-index job {}", id);
+        tokio::spawn(run_job(rag, id));
+        return;
+    }
+
+    let active = rag.embedder.model();
+    if configured.name == active.name {
+        return;
+    }
+    let cancelled: Option<(String,)> = sqlx::query_as(
+        "SELECT status FROM reindex_jobs WHERE target_model = $1 ORDER BY created_at DESC LIMIT 1",
+    )
+    .bind(&configured.name)
+    .fetch_optional(&rag.pool)
+    .await
+    .unwrap_or(None);
+    if matches!(cancelled, Some((status,)) if status == "cancelled") {
+        println!("EMBEDDING_MODEL is {} but documents use {}; the last re-index was cancelled", configured.name, active.name);
+        return;
+    }
+
+    println!("EMBEDDING_MODEL is {} but documents use {}; starting re-index", configured.name, active.name);
+    match create_job(&rag, configured, None).await {
+        Ok(id) => {
+            tokio::spawn(run_job(rag, id));
+        }
+        Err(e) => eprintln!("Failed to start re-index job: {}", e),
+    }
+}
+
+/// Queues a job migrating the corpus to `target`. Only one job may be queued or running.
+pub async fn create_job(rag: &RagSystem, target: &ModelSpec, requested_by: Option<&str>) -> Result<String, String> {
+    let active = rag.embedder.model();
+    if target.name == active.name {
+        return Err(format!("{} is already the active embedding model", target.name));
+    }
+
+    let id = uuid::Uuid::new_v4().to_string();
+    let inserted = sqlx::query(
+        "INSERT INTO reindex_jobs (id, source_model, target_model, target_dim, requested_by)
+         SELECT $1, $2, $3, $4, $5 WHERE NOT EXISTS (SELECT 1 FROM reindex_jobs WHERE status IN ('queued', 'running'))",
+    )
+    .bind(&id)
+    .bind(&active.name)
+    .bind(&target.name)
+    .bind(target.dim as i32)
+    .bind(requested_by)
+    .execute(&rag.pool)
+    .await
+    .map_err(|e| e.to_string())?;
+    if inserted.rows_affected() == 0 {
+        return Err("A re-index job is already running".to_string());
+    }
+    Ok(id)
+}
+
+/// Runs a re-index job to cutover, cancellation or failure. A failed job keeps its staged
+/// embeddings, so a new job for the same model only embeds what is missing.
+pub async fn run_job(rag: Arc<RagSystem>, job_id: String) {
+    let job = match sqlx::query_as::<_, ReindexJob>("SELECT * FROM reindex_jobs WHERE id = $1")
+        .bind(&job_id)
+        .fetch_optional(&rag.pool)
+        .await
+    {
+        Ok(Some(job)) => job,
+        _ => return,
+    };
+    if !matches!(job.status.as_str(), "queued" | "running") {
+        return;
+    }
+
+    let outcome = migrate(&rag, &job).await.map_err(|e| e.to_string());
+    match outcome {
+        Ok(true) => {
+            println!("Re-index job {} completed; {} is now the active embedding model", job_id, job.target_model);
+            let _ = sqlx::query("UPDATE reindex_jobs SET status = 'completed', finished_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+                .bind(&job_id)
+                .execute(&rag.pool)
+                .await;
+        }
+        Ok(false) => {
+            println!("Re-index job {} cancelled", job_id);
+            if let Err(e) = drop_next(&rag.pool).await {
+                eprintln!("Failed to drop staged embeddings of re-index job {}: {}", job_id, e);
+            }
+            let _ = sqlx::query("UPDATE reindex_jobs SET status = 'cancelled', finished_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+                .bind(&job_id)
+                .execute(&rag.pool)
+                .await;
+        }
+        Err(e) => {
+            eprintln!("Re-index job {} failed: {}", job_id, e);
+            let _ = sqlx::query("UPDATE reindex_jobs SET status = 'failed', error = $2, finished_at = CURRENT_TIMESTAMP, updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+                .bind(&job_id)
+                .bind(e)
+                .execute(&rag.pool)
+                .await;
+        }
+    }
+}
+
+/// Stages target embeddings, indexes them, cuts over and switches the live embedder.
+/// Returns false when cancelled.
+async fn migrate(rag: &RagSystem, job: &ReindexJob) -> Result<bool, Box<dyn std::error::Error>> {
+    let target = ModelSpec::resolve(&job.target_model)?;
+    // Interrupted after cutover committed: the corpus is already on the target model
+    let (active,): (String,) = sqlx::query_as("SELECT model FROM embedding_state WHERE id = 1").fetch_one(&rag.pool).await?;
+    if active == target.name {
+        reembed_stragglers(rag, &target).await?;
+        return Ok(true);
+    }
+
+    sqlx::query("UPDATE reindex_jobs SET status = 'running', started_at = COALESCE(started_at, CURRENT_TIMESTAMP), updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+        .bind(&job.id)
+        .execute(&rag.pool)
+        .await?;
+    prepare_next(&rag.pool, &target).await?;
+
+    // A separate, smaller pool for staging so chat queries keep the active model's workers
+    let workers = std::env::var("REINDEX_WORKERS").ok().and_then(|v| v.parse().ok()).filter(|v| *v > 0).unwrap_or(1);
+    let spec = target.clone();
+    let embedder = tokio::task::spawn_blocking(move || ModelPool::new(spec, workers, 16, 64)).await?;
+    let batch_size = std::env::var("REINDEX_BATCH_SIZE").ok().and_then(|v| v.parse().ok()).unwrap_or(64i64);
+
+    // Building the index once most rows are filled is much faster than maintaining it per batch
+    if !fill_next(rag, job, &embedder, batch_size).await? {
+        return Ok(false);
+    }
+    build_next_index(&rag.pool).await?;
+    // The live pool is sized like any other, and loaded before cutover so queries never wait on it
+    let spec = target.clone();
+    let live = tokio::task::spawn_blocking(move || ModelPool::from_env(spec)).await?;
+    loop {
+        if !fill_next(rag, job, &embedder, batch_size).await? {
+            return Ok(false);
+        }
+        if cutover(&rag.pool, &target).await? {
+            break;
+        }
+        // Documents changed between the last batch and the lock; embed them and try again
+    }
+
+    drop(embedder);
+    rag.embedder.swap(live);
+    rag.bump_kb_version().await;
+    // Writes that embedded with the old pool between cutover and the swap
+    reembed_stragglers(rag, &target).await?;
+    Ok(true)
+}
+
+/// Adds the staging columns, discarding any staged for a model of another size or name.
+async fn prepare_next(pool: &DbPool, target: &ModelSpec) -> Result<(), sqlx::Error> {
+    if matches!(column_dim(pool, "documents", NEXT_COLUMN).await, Some(dim) if dim != target.dim as i32) {
+        drop_next(pool).await?;
+    }
+    sqlx::query(&format!(
+        "ALTER TABLE documents ADD COLUMN IF NOT EXISTS {col} vector({dim}),
+             ADD COLUMN IF NOT EXISTS {col}_model TEXT,
+             ADD COLUMN IF NOT EXISTS {col}_hash TEXT",
+        col = NEXT_COLUMN,
+        dim = target.dim
+    ))
+    .execute(pool)
+    .await?;
+    // Left over from an earlier job for another model of the same size
+    sqlx::query(&format!(
+        "UPDATE documents SET {col} = NULL, {col}_model = NULL, {col}_hash = NULL WHERE {col}_model IS DISTINCT FROM $1 AND {col} IS NOT NULL",
+        col = NEXT_COLUMN
+    ))
+    .bind(&target.name)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+async fn drop_next(pool: &DbPool) -> Result<(), sqlx::Error> {
+    sqlx::query(&format!("DROP INDEX CONCURRENTLY IF EXISTS {}", NEXT_INDEX)).execute(pool).await?;
+    sqlx::query(&format!(
+        "ALTER TABLE documents DROP COLUMN IF EXISTS {col}, DROP COLUMN IF EXISTS {col}_model, DROP COLUMN IF EXISTS {col}_hash",
+        col = NEXT_COLUMN
+    ))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Builds the staged index without blocking reads or writes. An invalid index left by an
+/// interrupted build is dropped and rebuilt.
+async fn build_next_index(pool: &DbPool) -> Result<(), sqlx::Error> {
+    let valid: Option<(bool,)> = sqlx::query_as("SELECT indisvalid FROM pg_index WHERE indexrelid = to_regclass($1)")
+        .bind(NEXT_INDEX)
+        .fetch_optional(pool)
+        .await?;
+    match valid {
+        Some((true,)) => return Ok(()),
+        Some((false,)) => {
+            sqlx::query(&format!("DROP INDEX CONCURRENTLY IF EXISTS {}", NEXT_INDEX)).execute(pool).await?;
+        }
+        None => {}
+    }
+    sqlx::query(&format!(
+        "CREATE INDEX CONCURRENTLY {} ON documents USING hnsw ({} vector_cosine_ops)",
+        NEXT_INDEX, NEXT_COLUMN
+    ))
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+/// Documents (trashed ones included, so they can be restored) without a current staged
+/// embedding for the model bound as `$1`.
+fn stale_documents(select: &str) -> String {
+    format!(
+        "SELECT {select} FROM documents d
+         WHERE d.{col} IS NULL OR d.{col}_model IS DISTINCT FROM $1 OR d.{col}_hash IS DISTINCT FROM d.content_hash",
+        select = select,
+        col = NEXT_COLUMN
+    )
+}
+
+/// Embeds stale documents batch by batch until none are left. Returns false when cancelled.
+async fn fill_next(rag: &RagSystem, job: &ReindexJob, embedder: &ModelPool, batch_size: i64) -> Result<bool, Box<dyn std::error::Error>> {
+    let batch_query = format!("{} ORDER BY d.id LIMIT $2", stale_documents("d.id, d.content, d.content_hash"));
+    loop {
+        let (cancel,): (bool,) = sqlx::query_as("SELECT cancel_requested FROM reindex_jobs WHERE id = $1")
+            .bind(&job.id)
+            .fetch_one(&rag.pool)
+            .await?;
+        if cancel {
+            return Ok(false);
+        }
+
+        let rows: Vec<(String, String, Option<String>)> = sqlx::query_as(&batch_query)
+            .bind(&embedder.spec.name)
+            .bind(batch_size)
+            .fetch_all(&rag.pool)
+            .await?;
+        if rows.is_empty() {
+            return Ok(true);
+        }
+
+        // Re-indexing yields to interactive embedding requests
+        while rag.embedder.is_saturated() {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        }
+        let embeddings = embedder.embed(rows.iter().map(|(_, content, _)| content.clone()).collect()).await?;
+
+        let mut qb = sqlx::QueryBuilder::<sqlx::Postgres>::new(format!(
+            "UPDATE documents d SET {col} = v.embedding, {col}_model = v.model, {col}_hash = v.content_hash FROM (",
+            col = NEXT_COLUMN
+        ));
+        qb.push_values(rows.iter().zip(embeddings), |mut row, ((id, _, hash), embedding)| {
+            row.push_bind(id.clone())
+                .push_bind(hash.clone())
+                .push_bind(embedder.spec.name.clone())
+                .push_bind(Vector::from(embedding));
+        });
+        qb.push(") AS v(document_id, content_hash, model, embedding) WHERE d.id = v.document_id");
+        qb.build().execute(&rag.pool).await?;
+
+        sqlx::query(&format!(
+            "UPDATE reindex_jobs SET embedded_documents = (SELECT COUNT(*) FROM documents WHERE {}_model = $1),
+                 total_documents = (SELECT COUNT(*) FROM documents), updated_at = CURRENT_TIMESTAMP WHERE id = $2",
+            NEXT_COLUMN
+        ))
+        .bind(&embedder.spec.name)
+        .bind(&job.id)
+        .execute(&rag.pool)
+        .await?;
+    }
+}
+
+/// Promotes the staged embeddings and their index in one short transaction. Returns false,
+/// changing nothing, if documents went stale since the last batch. Writers wait from the stale
+/// check; readers only for the column and index renames and the NOT NULL check, which scans
+/// the table without rewriting it.
+async fn cutover(pool: &DbPool, target: &ModelSpec) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("LOCK TABLE documents IN SHARE ROW EXCLUSIVE MODE").execute(&mut *tx).await?;
+    let (stale,): (i64,) = sqlx::query_as(&stale_documents("COUNT(*)"))
+        .bind(&target.name)
+        .fetch_one(&mut *tx)
+        .await?;
+    if stale > 0 {
+        tx.rollback().await?;
+        return Ok(false);
+    }
+
+    let statements = [
+        // Dropping the old column drops its index too
+        format!("ALTER TABLE documents DROP COLUMN embedding, DROP COLUMN embedding_model, DROP COLUMN {}_hash", NEXT_COLUMN),
+        format!("ALTER TABLE documents RENAME COLUMN {} TO embedding", NEXT_COLUMN),
+        format!("ALTER TABLE documents RENAME COLUMN {}_model TO embedding_model", NEXT_COLUMN),
+        "ALTER TABLE documents ALTER COLUMN embedding SET NOT NULL".to_string(),
+        format!("ALTER INDEX {} RENAME TO documents_embedding_idx", NEXT_INDEX),
+        // Cached answers were matched on question embeddings from the old model
+        "DROP INDEX IF EXISTS response_cache_embedding_idx".to_string(),
+        "DELETE FROM response_cache".to_string(),
+        format!("ALTER TABLE response_cache ALTER COLUMN embedding TYPE vector({})", target.dim),
+        "CREATE INDEX response_cache_embedding_idx ON response_cache USING hnsw (embedding vector_cosine_ops)".to_string(),
+    ];
+    for statement in &statements {
+        sqlx::query(statement).execute(&mut *tx).await?;
+    }
+    sqlx::query("UPDATE embedding_state SET model = $1, dim = $2, updated_at = CURRENT_TIMESTAMP WHERE id = 1")
+        .bind(&target.name)
+        .bind(target.dim as i32)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+/// Re-embeds documents written with another model after cutover. Writes whose vectors have the
+/// old dimension fail outright, so only same-size models can leave rows here.
+async fn reembed_stragglers(rag: &RagSystem, target: &ModelSpec) -> Result<(), Box<dyn std::error::Error>> {
+    loop {
+        let rows: Vec<(String, String)> = sqlx::query_as("SELECT id, content FROM documents WHERE embedding_model IS DISTINCT FROM $1 LIMIT 64")
+            .bind(&target.name)
+            .fetch_all(&rag.pool)
+            .await?;
+        if rows.is_empty() {
+            return Ok(());
+        }
+        let embedder = rag.embedder.current();
+        let embeddings = embedder.embed(rows.iter().map(|(_, content)| content.clone()).collect()).await?;
+        for ((id, _), embedding) in rows.iter().zip(embeddings) {
+            sqlx::query("UPDATE documents SET embedding = $1, embedding_model = $2 WHERE id = $3")
+                .bind(Vector::from(embedding))
+                .bind(&embedder.spec.name)
+                .bind(id)
+                .execute(&rag.pool)
+                .await?;
+        }
+    }
+}
@@ -0,0 +1,73 @@
+use fastembed::{RerankInitOptions, RerankerModel, TextRerank};
+use std::sync::{Arc, Mutex};
+use tokio::sync::Semaphore;
+
+/// Cross-encoder used for the second retrieval stage. It scores each (query, chunk) pair
+/// jointly, which ranks far better than comparing independently embedded vectors.
+///
+/// `RERANK_WORKERS` model instances (default 1) serve requests in parallel; each holds its own
+/// copy of the model in memory, so raise it with care. Requests beyond that wait for a free one.
+pub struct Reranker {
+    pub name: String,
+    models: Arc<Vec<Mutex<TextRerank>>>,
+    free: Arc<Semaphore>,
+}
+
+impl Reranker {
+    /// The model named by `RERANK_MODEL` (fastembed model code or variant name), defaulting to
+    /// BGE-reranker-base. `none` disables reranking, leaving results ordered by vector similarity.
+    pub fn from_env() -> Result<Option<Self>, String> {
+        let name = std::env::var("RERANK_MODEL").unwrap_or_default();
+        let name = name.trim();
+        if name.eq_ignore_ascii_case("none") {
+            return Ok(None);
+        }
+        let info = TextRerank::list_supported_models()
+            .into_iter()
+            .find(|info| match name {
+                "" => info.model == RerankerModel::BGERerankerBase,
+                _ => info.model_code.eq_ignore_ascii_case(name) || format!("{:?}", info.model).eq_ignore_ascii_case(name),
+            })
+            .ok_or_else(|| format!("Unknown reranker model '{}'", name))?;
+        let workers = std::env::var("RERANK_WORKERS").ok().and_then(|v| v.parse().ok()).filter(|v| *v > 0).unwrap_or(1);
+
+        let mut models = Vec::with_capacity(workers);
+        for i in 0..workers {
+            let options = RerankInitOptions::new(info.model.clone()).with_show_download_progress(i == 0);
+            let model = TextRerank::try_new(options).map_err(|e| format!("Failed to load reranker model {}: {}", info.model_code, e))?;
+            models.push(Mutex::new(model));
+        }
+        Ok(Some(Self { name: info.model_code, models: Arc::new(models), free: Arc::new(Semaphore::new(workers)) }))
+    }
+
+    /// Relevance of each document to `query`, in input order. Raw cross-encoder logits are
+    /// squashed to 0..1 so thresholds read the same across models.
+    pub async fn score(&self, query: &str, documents: Vec<String>) -> Result<Vec<f32>, Box<dyn std::error::Error>> {
+        if documents.is_empty() {
+            return Ok(Vec::new());
+        }
+        // A permit guarantees at least one instance is unlocked
+        let permit = self.free.clone().acquire_owned().await?;
+        let models = self.models.clone();
+        let query = query.to_string();
+        let count = documents.len();
+        let results = tokio::task::spawn_blocking(move || {
+            let _permit = permit;
+            let mut model = models
+                .iter()
+                .find_map(|m| m.try_lock().ok())
+                .ok_or("no reranker instance free")?;
+            let docs: Vec<&str> = documents.iter().map(String::as_str).collect();
+            model.rerank(query.as_str(), docs, false, None).map_err(|e| e.to_string())
+        })
+        .await??;
+
+        let mut scores = vec![0.0; count];
+        for result in results {
+            if let Some(score) = scores.get_mut(result.index) {
+                *score = 1.0 / (1.0 + (-result.score).exp());
+            }
+        }
+        Ok(scores)
+    }
+}
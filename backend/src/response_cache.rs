@@ -0,0 +1,144 @@
+use crate::rag::RagSystem;
+use pgvector::Vector;
+use serde::Serialize;
+use sqlx::Row;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+static LOOKUPS: AtomicU64 = AtomicU64::new(0);
+static HITS: AtomicU64 = AtomicU64::new(0);
+static STORES: AtomicU64 = AtomicU64::new(0);
+
+/// Cached answers are only reused within the same model, persona, challenge, readable
+/// collections and KB version.
+pub struct Scope<'a> {
+    pub model: &'a str,
+    pub persona_id: Option<&'a str>,
+    pub challenge_id: Option<&'a str>,
+    /// `collections::access_key` of the requesting user.
+    pub access_key: &'a str,
+}
+
+#[derive(Serialize)]
+pub struct CacheStats {
+    pub enabled: bool,
+    pub kb_version: i64,
+    pub threshold: f64,
+    pub ttl_secs: i64,
+    pub lookups: u64,
+    pub hits: u64,
+    pub misses: u64,
+    pub hit_rate: f64,
+    pub stores: u64,
+    pub entries: i64,
+}
+
+fn enabled() -> bool {
+    std::env::var("SEMANTIC_CACHE").map(|v| v != "off").unwrap_or(true)
+}
+
+/// Minimum cosine similarity between questions for a cached answer to be served.
+fn threshold() -> f64 {
+    std::env::var("SEMANTIC_CACHE_THRESHOLD").ok().and_then(|v| v.parse().ok()).unwrap_or(0.92)
+}
+
+fn ttl_secs() -> i64 {
+    std::env::var("SEMANTIC_CACHE_TTL_SECS").ok().and_then(|v| v.parse().ok()).unwrap_or(86400)
+}
+
+/// Returns the closest cached answer for `question` if it clears the similarity threshold.
+pub async fn lookup(rag: &RagSystem, scope: &Scope<'_>, question: &str) -> Option<String> {
+    if !enabled() {
+        return None;
+    }
+    LOOKUPS.fetch_add(1, Ordering::Relaxed);
+
+    let embedding = Vector::from(rag.embed_query(question).await.ok()?);
+    let kb_version = rag.kb_version().await;
+    let row = sqlx::query(
+        "SELECT id, response, 1 - (embedding <=> $1) AS score FROM response_cache
+         WHERE model = $2 AND persona_id IS NOT DISTINCT FROM $3 AND challenge_id IS NOT DISTINCT FROM $4
+           AND access_key = $7 AND kb_version = $5 AND created_at > CURRENT_TIMESTAMP - make_interval(secs => $6)
+         ORDER BY embedding <=> $1 LIMIT 1",
+    )
+    .bind(embedding)
+    .bind(scope.model)
+    .bind(scope.persona_id)
+    .bind(scope.challenge_id)
+    .bind(kb_version)
+    .bind(ttl_secs() as f64)
+    .bind(scope.access_key)
+    .fetch_optional(&rag.pool)
+    .await
+    .ok()??;
+
+    let score: f64 = row.get("score");
+    if score < threshold() {
+        return None;
+    }
+
+    let id: String = row.get("id");
+    let _ = sqlx::query("UPDATE response_cache SET hits = hits + 1, last_hit_at = CURRENT_TIMESTAMP WHERE id = $1")
+        .bind(&id)
+        .execute(&rag.pool)
+        .await;
+    HITS.fetch_add(1, Ordering::Relaxed);
+    Some(row.get("response"))
+}
+
+/// Stores an answer for a first-turn question and prunes expired or outdated entries.
+pub async fn store(rag: &RagSystem, scope: &Scope<'_>, question: &str, response: &str) {
+    if !enabled() {
+        return;
+    }
+    let embedding = match rag.embed_query(question).await {
+        Ok(e) => Vector::from(e),
+        Err(_) => return,
+    };
+    let kb_version = rag.kb_version().await;
+
+    let inserted = sqlx::query(
+        "INSERT INTO response_cache (id, model, persona_id, challenge_id, kb_version, question, embedding, response, access_key) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+    )
+    .bind(uuid::Uuid::new_v4().to_string())
+    .bind(scope.model)
+    .bind(scope.persona_id)
+    .bind(scope.challenge_id)
+    .bind(kb_version)
+    .bind(question)
+    .bind(embedding)
+    .bind(response)
+    .bind(scope.access_key)
+    .execute(&rag.pool)
+    .await;
+    if inserted.is_ok() {
+        STORES.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let _ = sqlx::query("DELETE FROM response_cache WHERE kb_version <> $1 OR created_at < CURRENT_TIMESTAMP - make_interval(secs => $2)")
+        .bind(kb_version)
+        .bind(ttl_secs() as f64)
+        .execute(&rag.pool)
+        .await;
+}
+
+pub async fn stats(rag: &RagSystem) -> CacheStats {
+    let lookups = LOOKUPS.load(Ordering::Relaxed);
+    let hits = HITS.load(Ordering::Relaxed);
+    let entries: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM response_cache")
+        .fetch_one(&rag.pool)
+        .await
+        .unwrap_or((0,));
+
+    CacheStats {
+        enabled: enabled(),
+        kb_version: rag.kb_version().await,
+        threshold: threshold(),
+        ttl_secs: ttl_secs(),
+        lookups,
+        hits,
+        misses: lookups - hits,
+        hit_rate: if lookups == 0 { 0.0 } else { hits as f64 / lookups as f64 },
+        stores: STORES.load(Ordering::Relaxed),
+        entries: entries.0,
+    }
+}
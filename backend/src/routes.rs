@@ -1,4 +1,4 @@
-use actix_web::{get, post, put, delete, web, HttpResponse, Responder};
+use actix_web::{get, post, put, patch, delete, web, HttpResponse, Responder};
 use actix_multipart::Multipart;
 use futures::{StreamExt, TryStreamExt};
 use std::io::Write;
@@ -6,25 +6,47 @@ use serde_json::json;
 use crate::models::{
     ChatRequest, CreateDocumentRequest, ChatResponse, RegisterRequest, LoginRequest, AuthResponse, User,
     VoucherGenerateRequest, VoucherRedeemRequest, Voucher, AIModel, CreateAIModelRequest, UpdateAIModelRequest,
-    ChatSession, ChatMessage, VerifyEmailRequest, VoucherRequest, ImportStatus
+    ChatSession, ChatMessage, VerifyEmailRequest, VoucherRequest, ImportJob, ImportError, ImportUploadQuery, ListImportJobsQuery,
+    AdapterConfig, ImportAdapter, CreateImportAdapterRequest, UpdateImportAdapterRequest,
+    UploadDataQuery, NearDuplicatesQuery, NearDuplicatePair, DismissNearDuplicateRequest, VersionDiffQuery,
+    Collection, CreateCollectionRequest, UpdateCollectionRequest, CollectionGrant, CollectionGrantRequest,
+    MoveDocumentsRequest, ReindexJob, StartReindexRequest, UserGroup, CreateUserGroupRequest, GroupMemberRequest, GroupMember,
+    ListChatSessionsQuery, ChatSearchQuery, ChatSearchResult, UpdateChatSessionRequest, ChatFolder,
+    ChatFolderRequest, ExportQuery, SessionExport, SessionShare, CreateShareRequest, SharedTranscript, Message,
+    Persona, CreatePersonaRequest, UpdatePersonaRequest, RetrievalSettings, Challenge, ChallengeHint, ChallengeAttachment,
+    CreateChallengeRequest, UpdateChallengeRequest, ChallengeHintsRequest, LinkDocumentsRequest, ListChallengesQuery,
+    CHALLENGE_DIFFICULTIES, FlagSubmission, ScoreboardEntry, CategoryProgress, ScoreboardQuery, ChatAttachment,
+    AttachmentUploadQuery, CodeExecution, ExecuteCodeRequest, ListExecutionsQuery
 };
-use crate::rag::RagSystem;
+use crate::export::{self, ExportFormat};
+use crate::settings;
+use crate::hints::{self, HintLevel};
+use crate::scoring;
+use crate::tools;
+use crate::response_cache;
+use crate::query_rewrite;
+use crate::imports;
+use crate::adapters;
+use crate::collections;
+use crate::embedder::{self, ModelSpec};
+use crate::reindex;
+use crate::loaders::SourceFormat;
+use crate::sandbox::{self, Language};
+use crate::artifacts;
+use sha2::{Digest, Sha256};
+use crate::rag::{ConflictPolicy, RagSystem};
 use crate::auth::{self, AuthenticatedUser};
 use crate::db::DbPool;
-use std::sync::{Arc, Mutex};
+use std::sync::Arc;
 use chrono::{Utc, Duration};
 use uuid::Uuid;
 use sqlx::Row;
 use std::env;
-use redis::AsyncCommands;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
 
 
 pub struct AppState {
     pub rag: Arc<RagSystem>,
     pub client: reqwest::Client,
-    pub import_status: Arc<Mutex<ImportStatus>>,
 }
 
 async fn require_verified(user: &AuthenticatedUser, pool: &DbPool) -> Result<User, HttpResponse> {
@@ -578,6 +600,7 @@ async fn admin_redeem_voucher_for_user(
 async fn upload_data(
     user: AuthenticatedUser,
     state: web::Data<AppState>,
+    query: web::Query<UploadDataQuery>,
     body: web::Json<Vec<CreateDocumentRequest>>,
 ) -> impl Responder {
     if let Err(resp) = require_verified(&user, &state.rag.pool).await {
@@ -586,519 +609,3992 @@ async fn upload_data(
     if user.role != "admin" && user.role != "editor" {
         return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
     }
+    let Some(policy) = ConflictPolicy::parse(query.on_conflict.as_deref()) else {
+        return HttpResponse::BadRequest().json(json!({"error": "on_conflict must be skip, update or duplicate"}));
+    };
+    let collection_id = match check_collection(&state.rag.pool, query.collection.as_deref()).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+    let mut docs = body.into_inner();
+    if collection_id.is_some() {
+        for doc in docs.iter_mut().filter(|d| d.collection_id.is_none()) {
+            doc.collection_id = collection_id.clone();
+        }
+    }
+    let named: std::collections::HashSet<&str> = docs.iter().filter_map(|d| d.collection_id.as_deref()).collect();
+    for id in named {
+        if let Err(resp) = check_collection(&state.rag.pool, Some(id)).await {
+            return resp;
+        }
+    }
 
-    let mut count = 0;
-    for doc in body.iter() {
-        let _ = state.rag.add_document(&doc.content, doc.metadata.clone()).await;
-        count += 1;
+    match state.rag.add_documents_bulk(docs, policy, Some(&user.id), 64, |_, _| {}).await {
+        Ok(result) => HttpResponse::Ok().json(json!({
+            "message": format!("Indexed {} documents", result.inserted),
+            "inserted": result.inserted,
+            "updated": result.updated,
+            "skipped": result.skipped.iter().map(|(i, id)| json!({"index": i, "existing_id": id})).collect::<Vec<_>>(),
+            "failed": result.failures.iter().map(|(i, reason)| json!({"index": i, "reason": reason})).collect::<Vec<_>>(),
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
     }
-    HttpResponse::Ok().json(json!({"message": format!("Indexed {} documents", count)}))
 }
 
-#[derive(serde::Deserialize)]
-pub struct ListDocsQuery {
-    pub page: Option<i64>,
-    pub limit: Option<i64>,
+/// Pairs of documents whose embeddings are nearly identical, most similar first. Each document
+/// is compared with its nearest neighbours only, so the scan stays on the vector index.
+#[get("/api/admin/documents/near-duplicates")]
+pub async fn list_near_duplicates(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    query: web::Query<NearDuplicatesQuery>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let threshold = query.threshold.unwrap_or(0.95).clamp(0.5, 1.0);
+    let limit = query.limit.unwrap_or(50).clamp(1, 500);
+
+    let pairs = sqlx::query_as::<_, NearDuplicatePair>(
+        "SELECT d.id AS first_id, d.content AS first_content, n.id AS second_id, n.content AS second_content, n.similarity
+         FROM documents d
+         CROSS JOIN LATERAL (
+             SELECT o.id, o.content, 1 - (o.embedding <=> d.embedding) AS similarity
+             FROM documents o
+             WHERE o.id <> d.id AND o.deleted_at IS NULL
+             ORDER BY o.embedding <=> d.embedding
+             LIMIT 5
+         ) n
+         WHERE d.deleted_at IS NULL AND d.id < n.id AND n.similarity >= $1
+           AND NOT EXISTS (SELECT 1 FROM near_duplicate_dismissals x WHERE x.first_id = d.id AND x.second_id = n.id)
+         ORDER BY n.similarity DESC
+         LIMIT $2",
+    )
+    .bind(threshold)
+    .bind(limit)
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match pairs {
+        Ok(pairs) => HttpResponse::Ok().json(json!({"threshold": threshold, "pairs": pairs})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
 }
 
-#[get("/api/admin/documents")]
-pub async fn list_documents(
+/// Marks a pair as reviewed and distinct so it no longer appears in the near-duplicate list.
+#[post("/api/admin/documents/near-duplicates/dismiss")]
+pub async fn dismiss_near_duplicate(
     user: AuthenticatedUser,
     state: web::Data<AppState>,
-    query: web::Query<ListDocsQuery>,
+    body: web::Json<DismissNearDuplicateRequest>,
 ) -> impl Responder {
-    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
-        return resp;
-    }
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
     if user.role != "admin" && user.role != "editor" {
         return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
     }
+    // Pairs are listed with the smaller id first
+    let (first, second) = if body.first_id < body.second_id {
+        (&body.first_id, &body.second_id)
+    } else {
+        (&body.second_id, &body.first_id)
+    };
 
-    let page = query.page.unwrap_or(1);
-    let limit = query.limit.unwrap_or(100);
-    let offset = (page - 1) * limit;
+    let result = sqlx::query(
+        "INSERT INTO near_duplicate_dismissals (first_id, second_id, dismissed_by) VALUES ($1, $2, $3) ON CONFLICT (first_id, second_id) DO NOTHING",
+    )
+    .bind(first)
+    .bind(second)
+    .bind(&user.id)
+    .execute(&state.rag.pool)
+    .await;
 
-    match state.rag.list_documents(limit, offset).await {
-        Ok(docs) => {
-            let total = state.rag.count_documents().await.unwrap_or(0);
-            HttpResponse::Ok().json(json!({
-                "documents": docs,
-                "total": total,
-                "page": page,
-                "limit": limit
-            }))
-        },
+    match result {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Pair dismissed"})),
         Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
     }
 }
 
-#[get("/api/admin/import-status")]
-pub async fn get_import_status(
+/// Validates an optional collection id from a request; empty means the default collection.
+async fn check_collection(pool: &DbPool, id: Option<&str>) -> Result<Option<String>, HttpResponse> {
+    let Some(id) = id.filter(|c| !c.is_empty()) else {
+        return Ok(None);
+    };
+    match collections::exists(pool, id).await {
+        Ok(true) => Ok(Some(id.to_string())),
+        Ok(false) => Err(HttpResponse::BadRequest().json(json!({"error": format!("Unknown collection '{}'", id)}))),
+        Err(e) => Err(HttpResponse::InternalServerError().json(json!({"error": e.to_string()}))),
+    }
+}
+
+fn collection_write_error(e: sqlx::Error) -> HttpResponse {
+    match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => HttpResponse::Conflict().json(json!({"error": "A collection with this slug already exists"})),
+        _ => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/admin/collections")]
+pub async fn list_collections(
     user: AuthenticatedUser,
     state: web::Data<AppState>,
 ) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
     if user.role != "admin" && user.role != "editor" {
-        return HttpResponse::Forbidden().json(json!({"error": "Admin/Editor access required"}));
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    let result = sqlx::query_as::<_, Collection>(
+        "SELECT c.*, (SELECT COUNT(*) FROM documents d WHERE d.collection_id = c.id AND d.deleted_at IS NULL) AS document_count
+         FROM kb_collections c ORDER BY c.name",
+    )
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match result {
+        Ok(collections) => HttpResponse::Ok().json(collections),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
     }
-    let status = state.import_status.lock().unwrap();
-    HttpResponse::Ok().json(&*status)
 }
 
-#[put("/api/admin/documents/{id}")]
-pub async fn update_document(
+#[post("/api/admin/collections")]
+pub async fn create_collection(
     user: AuthenticatedUser,
     state: web::Data<AppState>,
-    path: web::Path<String>,
-    body: web::Json<CreateDocumentRequest>,
+    body: web::Json<CreateCollectionRequest>,
 ) -> impl Responder {
-    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
-        return resp;
-    }
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
     if user.role != "admin" && user.role != "editor" {
         return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
     }
+    let roles = body.roles.clone().unwrap_or_default();
+    if let Err(e) = collections::validate_slug(&body.slug).and_then(|_| collections::validate_policy(&body.visibility, &roles)) {
+        return HttpResponse::BadRequest().json(json!({"error": e}));
+    }
+    if body.name.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "name is required"}));
+    }
 
-    let id = path.into_inner();
-    match state.rag.update_document(&id, &body.content, body.metadata.clone()).await {
-        Ok(_) => HttpResponse::Ok().json(json!({"message": "Document updated"})),
-        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    let id = Uuid::new_v4().to_string();
+    let result = sqlx::query(
+        "INSERT INTO kb_collections (id, slug, name, description, visibility, roles, created_by) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(&id)
+    .bind(&body.slug)
+    .bind(body.name.trim())
+    .bind(&body.description)
+    .bind(&body.visibility)
+    .bind(&roles)
+    .bind(&user.id)
+    .execute(&state.rag.pool)
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Created().json(json!({"id": id, "message": "Collection created successfully"})),
+        Err(e) => collection_write_error(e),
     }
 }
 
-#[delete("/api/admin/documents/{id}")]
-pub async fn delete_document(
+#[put("/api/admin/collections/{id}")]
+pub async fn update_collection(
     user: AuthenticatedUser,
     state: web::Data<AppState>,
     path: web::Path<String>,
+    body: web::Json<UpdateCollectionRequest>,
 ) -> impl Responder {
-    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
-        return resp;
-    }
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
     if user.role != "admin" && user.role != "editor" {
         return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
     }
 
     let id = path.into_inner();
-    match state.rag.delete_document(&id).await {
-        Ok(_) => HttpResponse::Ok().json(json!({"message": "Document deleted"})),
+    let existing = sqlx::query_as::<_, Collection>("SELECT * FROM kb_collections WHERE id = $1")
+        .bind(&id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    match existing {
+        Ok(Some(mut collection)) => {
+            if let Some(slug) = &body.slug { collection.slug = slug.clone(); }
+            if let Some(name) = &body.name { collection.name = name.trim().to_string(); }
+            if let Some(desc) = &body.description { collection.description = Some(desc.clone()); }
+            if let Some(visibility) = &body.visibility { collection.visibility = visibility.clone(); }
+            if let Some(roles) = &body.roles { collection.roles = roles.clone(); }
+            if let Err(e) = collections::validate_slug(&collection.slug).and_then(|_| collections::validate_policy(&collection.visibility, &collection.roles)) {
+                return HttpResponse::BadRequest().json(json!({"error": e}));
+            }
+            if collection.name.is_empty() {
+                return HttpResponse::BadRequest().json(json!({"error": "name is required"}));
+            }
+
+            // Search and response caches are keyed by the set of readable collections,
+            // so a policy change takes effect without invalidating them
+            let update_result = sqlx::query(
+                "UPDATE kb_collections SET slug = $1, name = $2, description = $3, visibility = $4, roles = $5, updated_at = CURRENT_TIMESTAMP WHERE id = $6"
+            )
+            .bind(&collection.slug)
+            .bind(&collection.name)
+            .bind(&collection.description)
+            .bind(&collection.visibility)
+            .bind(&collection.roles)
+            .bind(&id)
+            .execute(&state.rag.pool)
+            .await;
+
+            match update_result {
+                Ok(_) => HttpResponse::Ok().json(json!({"message": "Collection updated successfully"})),
+                Err(e) => collection_write_error(e),
+            }
+        },
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "Collection not found"})),
         Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
     }
 }
 
-#[post("/api/chat")]
-async fn chat(
-    user: AuthenticatedUser, // Require authentication
+/// Deletes an empty collection. Documents, including those in the trash, must be moved or purged first.
+#[delete("/api/admin/collections/{id}")]
+pub async fn delete_collection(
+    user: AuthenticatedUser,
     state: web::Data<AppState>,
-    body: web::Json<ChatRequest>,
+    path: web::Path<String>,
 ) -> impl Responder {
-    // Check subscription
-    let user_db = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
-        .bind(&user.id)
-        .fetch_optional(&state.rag.pool)
-        .await;
-
-    let u = match user_db {
-        Ok(Some(u)) => u,
-        _ => return HttpResponse::Unauthorized().json(json!({"error": "User not found"})),
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
     };
-
-    if !u.email_verified && u.role != "admin" {
-        return HttpResponse::Forbidden().json(json!({"error": "Email verification required"}));
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+    let id = path.into_inner();
+    if id == collections::DEFAULT_COLLECTION {
+        return HttpResponse::BadRequest().json(json!({"error": "The default collection cannot be deleted"}));
     }
 
-    if let Some(sub_end) = u.subscription_end {
-        if sub_end < Utc::now().naive_utc() {
-             return HttpResponse::PaymentRequired().json(json!({"error": "Subscription expired. Please redeem a voucher."}));
+    let in_use: Result<(i64, i64), _> = sqlx::query_as(
+        "SELECT (SELECT COUNT(*) FROM documents WHERE collection_id = $1),
+                (SELECT COUNT(*) FROM import_jobs WHERE collection_id = $1 AND status IN ('queued', 'running'))",
+    )
+    .bind(&id)
+    .fetch_one(&state.rag.pool)
+    .await;
+    match in_use {
+        Ok((0, 0)) => {}
+        Ok((0, _)) => return HttpResponse::Conflict().json(json!({"error": "An import into this collection is still running"})),
+        Ok((documents, _)) => {
+            return HttpResponse::Conflict().json(json!({"error": format!("Collection still holds {} documents (including trash)", documents)}))
         }
-    } else {
-         // No subscription
-         return HttpResponse::PaymentRequired().json(json!({"error": "Subscription required. Please redeem a voucher."}));
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
     }
 
-    // Custom Greeting Logic
-    let msg_lower = body.message.trim().to_lowercase();
-    let greeting_patterns = ["hi", "hello", "hey", "greetings", "hi krypton", "hello krypton"];
-    if greeting_patterns.contains(&msg_lower.as_str()) {
-        let name = u.fullname.as_ref().filter(|s| !s.is_empty()).unwrap_or(&u.username);
-        let response_text = format!(
-            "Hello {}! 👋 I'm Krypton, your dedicated cybersecurity mentor and CTF challenge expert. Whether you're tackling a web exploit, reverse-engineering a binary, analyzing forensic artifacts, or deciphering cryptography—I'm here to guide you through it!\n\nHow can I assist you today?\n\n🕵️‍♂️ Stuck on a CTF challenge? Share the details (category, background, task).\n🔍 Need help analyzing a suspicious file/pcap/artifact?\n📚 Want a deep dive into a security concept?\n🚀 Just starting? Let me suggest beginner-friendly CTF platforms!\nProvide a challenge snippet, task description, or your current approach so I can tailor the guidance!\n\nRemember: All assistance is strictly for educational purposes and ethical hacking. 💻✨",
-            name
-        );
-        
-        // Prepare Session
-        let session_id = if let Some(sid) = &body.session_id {
-            sid.clone()
-        } else {
-            let new_sid = Uuid::new_v4().to_string();
-            let title = "Greeting";
-            let _ = sqlx::query("INSERT INTO chat_sessions (id, user_id, title) VALUES ($1, $2, $3)")
-                .bind(&new_sid)
-                .bind(&user.id)
-                .bind(title)
-                .execute(&state.rag.pool)
-                .await;
-            new_sid
-        };
+    match sqlx::query("DELETE FROM kb_collections WHERE id = $1").bind(&id).execute(&state.rag.pool).await {
+        Ok(r) if r.rows_affected() == 0 => HttpResponse::NotFound().json(json!({"error": "Collection not found"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Collection deleted successfully"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
 
-        // Save User Message
-        let user_msg_id = Uuid::new_v4().to_string();
-        let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'user', $3)")
-            .bind(&user_msg_id)
-            .bind(&session_id)
-            .bind(&body.message)
-            .execute(&state.rag.pool)
-            .await;
+#[get("/api/admin/collections/{id}/grants")]
+pub async fn list_collection_grants(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
 
-        // Save AI Response
-        let ai_msg_id = Uuid::new_v4().to_string();
-        let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'assistant', $3)")
-            .bind(&ai_msg_id)
-            .bind(&session_id)
-            .bind(&response_text)
-            .execute(&state.rag.pool)
-            .await;
-            
-        // Update session
-        let _ = sqlx::query("UPDATE chat_sessions SET updated_at = CURRENT_TIMESTAMP WHERE id = $1")
-            .bind(&session_id)
-            .execute(&state.rag.pool)
-            .await;
+    let result = sqlx::query_as::<_, CollectionGrant>(
+        "SELECT g.collection_id, g.principal_type, g.principal_id, COALESCE(u.username, ug.name) AS principal_name, g.created_by, g.created_at
+         FROM kb_collection_grants g
+         LEFT JOIN users u ON g.principal_type = 'user' AND u.id = g.principal_id
+         LEFT JOIN user_groups ug ON g.principal_type = 'group' AND ug.id = g.principal_id
+         WHERE g.collection_id = $1
+         ORDER BY g.created_at",
+    )
+    .bind(path.into_inner())
+    .fetch_all(&state.rag.pool)
+    .await;
 
-        return HttpResponse::Ok().json(ChatResponse { response: response_text, session_id });
+    match result {
+        Ok(grants) => HttpResponse::Ok().json(grants),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
     }
+}
 
-    // Redis Cache Check
-    let model = body.model.clone().unwrap_or_else(|| "deepseek/deepseek-r1-0528:free".to_string());
-    let mut hasher = DefaultHasher::new();
-    body.message.hash(&mut hasher);
-    model.hash(&mut hasher);
-    let cache_key = format!("chat:response:{}", hasher.finish());
-
-    if let Some(client) = &state.rag.redis {
-        if let Ok(mut con) = client.get_multiplexed_async_connection().await {
-            let cached: Option<String> = con.get(&cache_key).await.unwrap_or(None);
-            if let Some(content) = cached {
-                // Return cached response
-                let session_id = if let Some(sid) = &body.session_id {
-                    sid.clone()
-                } else {
-                    let new_sid = Uuid::new_v4().to_string();
-                    let title: String = body.message.chars().take(30).collect();
-                    let _ = sqlx::query("INSERT INTO chat_sessions (id, user_id, title) VALUES ($1, $2, $3)")
-                        .bind(&new_sid)
-                        .bind(&user.id)
-                        .bind(&title)
-                        .execute(&state.rag.pool)
-                        .await;
-                    new_sid
-                };
-
-                let user_msg_id = Uuid::new_v4().to_string();
-                let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'user', $3)")
-                    .bind(&user_msg_id)
-                    .bind(&session_id)
-                    .bind(&body.message)
-                    .execute(&state.rag.pool)
-                    .await;
+#[post("/api/admin/collections/{id}/grants")]
+pub async fn add_collection_grant(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<CollectionGrantRequest>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let id = path.into_inner();
+    match collections::exists(&state.rag.pool, &id).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::NotFound().json(json!({"error": "Collection not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+    let principal_query = match body.principal_type.as_str() {
+        "user" => "SELECT id FROM users WHERE id = $1",
+        "group" => "SELECT id FROM user_groups WHERE id = $1",
+        _ => return HttpResponse::BadRequest().json(json!({"error": "principal_type must be user or group"})),
+    };
+    match sqlx::query(principal_query).bind(&body.principal_id).fetch_optional(&state.rag.pool).await {
+        Ok(Some(_)) => {}
+        Ok(None) => return HttpResponse::BadRequest().json(json!({"error": format!("Unknown {}", body.principal_type)})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
 
-                let ai_msg_id = Uuid::new_v4().to_string();
-                let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'assistant', $3)")
-                    .bind(&ai_msg_id)
-                    .bind(&session_id)
-                    .bind(&content)
-                    .execute(&state.rag.pool)
-                    .await;
-                
-                let _ = sqlx::query("UPDATE chat_sessions SET updated_at = CURRENT_TIMESTAMP WHERE id = $1")
-                    .bind(&session_id)
-                    .execute(&state.rag.pool)
-                    .await;
+    let result = sqlx::query(
+        "INSERT INTO kb_collection_grants (collection_id, principal_type, principal_id, created_by) VALUES ($1, $2, $3, $4)
+         ON CONFLICT (collection_id, principal_type, principal_id) DO NOTHING",
+    )
+    .bind(&id)
+    .bind(&body.principal_type)
+    .bind(&body.principal_id)
+    .bind(&user.id)
+    .execute(&state.rag.pool)
+    .await;
 
-                return HttpResponse::Ok().json(ChatResponse { response: content, session_id });
-            }
-        }
+    match result {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Access granted"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
     }
+}
 
-    // 1. Search RAG
-    let relevant_chunks = match state.rag.search(&body.message, 3).await {
-        Ok(chunks) => chunks,
-        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+#[delete("/api/admin/collections/{id}/grants/{principal_type}/{principal_id}")]
+pub async fn remove_collection_grant(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<(String, String, String)>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
     };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let (id, principal_type, principal_id) = path.into_inner();
 
-    let context = relevant_chunks
-        .iter()
-        .map(|c| c.content.clone())
-        .collect::<Vec<String>>()
-        .join("\n\n");
+    let result = sqlx::query("DELETE FROM kb_collection_grants WHERE collection_id = $1 AND principal_type = $2 AND principal_id = $3")
+        .bind(&id)
+        .bind(&principal_type)
+        .bind(&principal_id)
+        .execute(&state.rag.pool)
+        .await;
 
-    // 2. Prepare Session
-    let session_id = if let Some(sid) = &body.session_id {
-        // Verify session belongs to user
-        let session_check = sqlx::query("SELECT 1 FROM chat_sessions WHERE id = $1 AND user_id = $2")
+    match result {
+        Ok(r) if r.rows_affected() == 0 => HttpResponse::NotFound().json(json!({"error": "Grant not found"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Access revoked"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/admin/documents/move")]
+pub async fn move_documents(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    body: web::Json<MoveDocumentsRequest>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    if let Err(resp) = check_collection(&state.rag.pool, Some(&body.collection_id)).await {
+        return resp;
+    }
+
+    match state.rag.move_documents(&body.document_ids, &body.collection_id).await {
+        Ok(moved) => HttpResponse::Ok().json(json!({"message": format!("Moved {} documents", moved), "moved": moved})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/admin/groups")]
+pub async fn list_user_groups(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    let result = sqlx::query_as::<_, UserGroup>(
+        "SELECT g.*, (SELECT COUNT(*) FROM user_group_members m WHERE m.group_id = g.id) AS member_count FROM user_groups g ORDER BY g.name",
+    )
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match result {
+        Ok(groups) => HttpResponse::Ok().json(groups),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/admin/groups")]
+pub async fn create_user_group(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    body: web::Json<CreateUserGroupRequest>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    if body.name.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "name is required"}));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let result = sqlx::query("INSERT INTO user_groups (id, name, description) VALUES ($1, $2, $3)")
+        .bind(&id)
+        .bind(body.name.trim())
+        .bind(&body.description)
+        .execute(&state.rag.pool)
+        .await;
+
+    match result {
+        Ok(_) => HttpResponse::Created().json(json!({"id": id, "message": "Group created successfully"})),
+        Err(e) if e.as_database_error().map(|d| d.is_unique_violation()).unwrap_or(false) => {
+            HttpResponse::Conflict().json(json!({"error": "A group with this name already exists"}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+/// Deletes a group along with its memberships and collection grants.
+#[delete("/api/admin/groups/{id}")]
+pub async fn delete_user_group(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let id = path.into_inner();
+
+    let result = async {
+        let mut tx = state.rag.pool.begin().await?;
+        sqlx::query("DELETE FROM kb_collection_grants WHERE principal_type = 'group' AND principal_id = $1")
+            .bind(&id)
+            .execute(&mut *tx)
+            .await?;
+        let deleted = sqlx::query("DELETE FROM user_groups WHERE id = $1").bind(&id).execute(&mut *tx).await?;
+        tx.commit().await?;
+        Ok::<_, sqlx::Error>(deleted.rows_affected())
+    }
+    .await;
+
+    match result {
+        Ok(0) => HttpResponse::NotFound().json(json!({"error": "Group not found"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Group deleted successfully"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/admin/groups/{id}/members")]
+pub async fn list_group_members(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    let result = sqlx::query_as::<_, GroupMember>(
+        "SELECT m.user_id, u.username, m.added_at FROM user_group_members m JOIN users u ON u.id = m.user_id
+         WHERE m.group_id = $1 ORDER BY u.username",
+    )
+    .bind(path.into_inner())
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match result {
+        Ok(members) => HttpResponse::Ok().json(members),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/admin/groups/{id}/members")]
+pub async fn add_group_member(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<GroupMemberRequest>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    let result = sqlx::query(
+        "INSERT INTO user_group_members (group_id, user_id) VALUES ($1, $2) ON CONFLICT (group_id, user_id) DO NOTHING",
+    )
+    .bind(path.into_inner())
+    .bind(&body.user_id)
+    .execute(&state.rag.pool)
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Member added"})),
+        Err(e) if e.as_database_error().map(|d| d.is_foreign_key_violation()).unwrap_or(false) => {
+            HttpResponse::NotFound().json(json!({"error": "Group or user not found"}))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[delete("/api/admin/groups/{id}/members/{user_id}")]
+pub async fn remove_group_member(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let (group_id, user_id) = path.into_inner();
+
+    let result = sqlx::query("DELETE FROM user_group_members WHERE group_id = $1 AND user_id = $2")
+        .bind(&group_id)
+        .bind(&user_id)
+        .execute(&state.rag.pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => HttpResponse::NotFound().json(json!({"error": "Member not found"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Member removed"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[derive(serde::Deserialize)]
+pub struct ListDocsQuery {
+    pub page: Option<i64>,
+    pub limit: Option<i64>,
+    pub collection: Option<String>,
+}
+
+#[get("/api/admin/documents")]
+pub async fn list_documents(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    query: web::Query<ListDocsQuery>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    let page = query.page.unwrap_or(1);
+    let limit = query.limit.unwrap_or(100);
+    let offset = (page - 1) * limit;
+
+    let collection = query.collection.as_deref().filter(|c| !c.is_empty());
+    match state.rag.list_documents(limit, offset, collection).await {
+        Ok(docs) => {
+            let total = state.rag.count_documents(collection).await.unwrap_or(0);
+            HttpResponse::Ok().json(json!({
+                "documents": docs,
+                "total": total,
+                "page": page,
+                "limit": limit
+            }))
+        },
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/admin/imports")]
+pub async fn list_import_jobs(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    query: web::Query<ListImportJobsQuery>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin/Editor access required"}));
+    }
+    let jobs = sqlx::query_as::<_, ImportJob>(
+        "SELECT * FROM import_jobs WHERE ($1::text IS NULL OR status = $1) ORDER BY created_at DESC LIMIT $2",
+    )
+    .bind(&query.status)
+    .bind(query.limit.unwrap_or(50).clamp(1, 500))
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match jobs {
+        Ok(jobs) => HttpResponse::Ok().json(jobs),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/admin/imports/{id}")]
+pub async fn get_import_job(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin/Editor access required"}));
+    }
+    let job = sqlx::query_as::<_, ImportJob>("SELECT * FROM import_jobs WHERE id = $1")
+        .bind(path.into_inner())
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    match job {
+        Ok(Some(job)) => HttpResponse::Ok().json(job),
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "Import job not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+/// Built-in and saved dataset adapters selectable on upload.
+#[get("/api/admin/import-adapters")]
+pub async fn list_import_adapters(user: AuthenticatedUser, state: web::Data<AppState>) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin/Editor access required"}));
+    }
+
+    let saved = sqlx::query_as::<_, ImportAdapter>("SELECT * FROM import_adapters ORDER BY name ASC")
+        .fetch_all(&state.rag.pool)
+        .await;
+
+    match saved {
+        Ok(saved) => {
+            let saved: Vec<_> = saved
+                .into_iter()
+                .map(|a| {
+                    let config = a.config();
+                    json!({"id": a.id, "slug": a.slug, "name": a.name, "description": a.description, "config": config, "builtin": false})
+                })
+                .collect();
+            let presets: Vec<_> = adapters::presets()
+                .into_iter()
+                .map(|p| json!({"slug": p.slug, "name": p.name, "description": p.description, "config": p.config, "builtin": true}))
+                .collect();
+            HttpResponse::Ok().json(presets.into_iter().chain(saved).collect::<Vec<_>>())
+        },
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+fn check_adapter(slug: &str, config: &AdapterConfig) -> Result<(), HttpResponse> {
+    if slug.trim().is_empty() {
+        return Err(HttpResponse::BadRequest().json(json!({"error": "slug is required"})));
+    }
+    if adapters::presets().iter().any(|p| p.slug == slug) {
+        return Err(HttpResponse::BadRequest().json(json!({"error": "slug is reserved for a built-in adapter"})));
+    }
+    adapters::validate_config(config).map_err(|e| HttpResponse::BadRequest().json(json!({"error": e})))
+}
+
+#[post("/api/admin/import-adapters")]
+pub async fn create_import_adapter(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    body: web::Json<CreateImportAdapterRequest>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin/Editor access required"}));
+    }
+    if let Err(resp) = check_adapter(&body.slug, &body.config) {
+        return resp;
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let result = sqlx::query(
+        "INSERT INTO import_adapters (id, slug, name, description, config, created_by) VALUES ($1, $2, $3, $4, $5, $6)",
+    )
+    .bind(&id)
+    .bind(&body.slug)
+    .bind(&body.name)
+    .bind(&body.description)
+    .bind(json!(body.config).to_string())
+    .bind(&user.id)
+    .execute(&state.rag.pool)
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Created().json(json!({"id": id, "message": "Adapter created successfully"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[put("/api/admin/import-adapters/{id}")]
+pub async fn update_import_adapter(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<UpdateImportAdapterRequest>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin/Editor access required"}));
+    }
+
+    let id = path.into_inner();
+    let existing = sqlx::query_as::<_, ImportAdapter>("SELECT * FROM import_adapters WHERE id = $1")
+        .bind(&id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    match existing {
+        Ok(Some(mut adapter)) => {
+            let config = body.config.clone().unwrap_or_else(|| adapter.config());
+            if let Some(slug) = &body.slug { adapter.slug = slug.clone(); }
+            if let Some(name) = &body.name { adapter.name = name.clone(); }
+            if let Some(desc) = &body.description { adapter.description = Some(desc.clone()); }
+            if let Err(resp) = check_adapter(&adapter.slug, &config) {
+                return resp;
+            }
+
+            let update_result = sqlx::query(
+                "UPDATE import_adapters SET slug = $1, name = $2, description = $3, config = $4, updated_at = CURRENT_TIMESTAMP WHERE id = $5"
+            )
+            .bind(adapter.slug)
+            .bind(adapter.name)
+            .bind(adapter.description)
+            .bind(json!(config).to_string())
+            .bind(id)
+            .execute(&state.rag.pool)
+            .await;
+
+            match update_result {
+                Ok(_) => HttpResponse::Ok().json(json!({"message": "Adapter updated successfully"})),
+                Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+            }
+        },
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "Adapter not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[delete("/api/admin/import-adapters/{id}")]
+pub async fn delete_import_adapter(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin/Editor access required"}));
+    }
+
+    // Jobs keep their own copy of the config, so deleting an adapter does not affect running imports
+    let result = sqlx::query("DELETE FROM import_adapters WHERE id = $1")
+        .bind(path.into_inner())
+        .execute(&state.rag.pool)
+        .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Adapter deleted successfully"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+/// Rejected records of a job as a JSON Lines download.
+#[get("/api/admin/imports/{id}/errors")]
+pub async fn download_import_errors(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin/Editor access required"}));
+    }
+    let id = path.into_inner();
+    let errors = sqlx::query_as::<_, ImportError>(
+        "SELECT record_index, byte_offset, reason, snippet FROM import_errors WHERE job_id = $1 ORDER BY record_index ASC",
+    )
+    .bind(&id)
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match errors {
+        Ok(errors) => {
+            let body: String = errors
+                .iter()
+                .filter_map(|e| serde_json::to_string(e).ok())
+                .map(|line| line + "\n")
+                .collect();
+            HttpResponse::Ok()
+                .content_type("application/x-ndjson")
+                .insert_header(("Content-Disposition", format!("attachment; filename=\"import-{}-errors.jsonl\"", id)))
+                .body(body)
+        },
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/admin/imports/{id}/cancel")]
+pub async fn cancel_import_job(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin/Editor access required"}));
+    }
+    // The runner stops at its next checkpoint
+    let result = sqlx::query("UPDATE import_jobs SET cancel_requested = TRUE, updated_at = CURRENT_TIMESTAMP WHERE id = $1 AND status IN ('queued', 'running')")
+        .bind(path.into_inner())
+        .execute(&state.rag.pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() > 0 => HttpResponse::Ok().json(json!({"message": "Cancellation requested"})),
+        Ok(_) => HttpResponse::Conflict().json(json!({"error": "Job is not queued or running"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/admin/imports/{id}/resume")]
+pub async fn resume_import_job(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin/Editor access required"}));
+    }
+    let id = path.into_inner();
+    let result = sqlx::query("UPDATE import_jobs SET status = 'queued', error = NULL, finished_at = NULL, updated_at = CURRENT_TIMESTAMP WHERE id = $1 AND status = 'failed'")
+        .bind(&id)
+        .execute(&state.rag.pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() > 0 => {
+            tokio::spawn(imports::run_job(state.rag.clone(), id));
+            HttpResponse::Ok().json(json!({"message": "Import resumed from last checkpoint"}))
+        },
+        Ok(_) => HttpResponse::Conflict().json(json!({"error": "Only failed jobs can be resumed"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[put("/api/admin/documents/{id}")]
+pub async fn update_document(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<CreateDocumentRequest>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    let id = path.into_inner();
+    match state.rag.update_document(&id, &body.content, body.metadata.clone(), Some(&user.id)).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"message": "Document updated"})),
+        Ok(false) => HttpResponse::NotFound().json(json!({"error": "Document not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[delete("/api/admin/documents/{id}")]
+pub async fn delete_document(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    let id = path.into_inner();
+    match state.rag.delete_document(&id, Some(&user.id)).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"message": "Document moved to trash"})),
+        Ok(false) => HttpResponse::NotFound().json(json!({"error": "Document not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/admin/documents/trash")]
+pub async fn list_document_trash(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    query: web::Query<ListDocsQuery>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let offset = (query.page.unwrap_or(1).max(1) - 1) * limit;
+
+    match state.rag.list_trash(limit, offset).await {
+        Ok(documents) => HttpResponse::Ok().json(documents),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/admin/documents/{id}/undelete")]
+pub async fn undelete_document(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    match state.rag.undelete_document(&path.into_inner(), Some(&user.id)).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"message": "Document restored from trash"})),
+        Ok(false) => HttpResponse::NotFound().json(json!({"error": "Document not found in trash"})),
+        Err(e) => HttpResponse::Conflict().json(json!({"error": e.to_string()})),
+    }
+}
+
+/// Permanently deletes a trashed document and its history.
+#[delete("/api/admin/documents/trash/{id}")]
+pub async fn purge_document(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+
+    match state.rag.purge_document(&path.into_inner()).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"message": "Document permanently deleted"})),
+        Ok(false) => HttpResponse::NotFound().json(json!({"error": "Document not found in trash"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/admin/documents/{id}/versions")]
+pub async fn list_document_versions(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    match state.rag.list_versions(&path.into_inner()).await {
+        Ok(versions) => HttpResponse::Ok().json(versions),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+/// Line diff between two versions, or between a version and the current document.
+#[get("/api/admin/documents/{id}/versions/diff")]
+pub async fn diff_document_versions(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<VersionDiffQuery>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let id = path.into_inner();
+
+    let from = match state.rag.get_version(&id, query.from).await {
+        Ok(Some(v)) => (v.content, v.metadata),
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": format!("Version {} not found", query.from)})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+    let to = match query.to {
+        Some(version) => state.rag.get_version(&id, version).await.map(|v| v.map(|v| (v.content, v.metadata))),
+        None => state.rag.get_document(&id).await,
+    };
+    let to = match to {
+        Ok(Some(to)) => to,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Version not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let to_label = query.to.map(|v| format!("version {}", v)).unwrap_or_else(|| "current".to_string());
+    let diff = similar::TextDiff::from_lines(&from.0, &to.0)
+        .unified_diff()
+        .context_radius(3)
+        .header(&format!("version {}", query.from), &to_label)
+        .to_string();
+
+    HttpResponse::Ok().json(json!({
+        "from": query.from,
+        "to": query.to,
+        "diff": diff,
+        "metadata_changed": from.1 != to.1,
+        "from_metadata": from.1,
+        "to_metadata": to.1,
+    }))
+}
+
+/// Makes an earlier version current again; the restore is itself recorded as a new version.
+#[post("/api/admin/documents/{id}/versions/{version}/restore")]
+pub async fn restore_document_version(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<(String, i32)>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let (id, version) = path.into_inner();
+
+    match state.rag.restore_version(&id, version, Some(&user.id)).await {
+        Ok(true) => HttpResponse::Ok().json(json!({"message": format!("Document restored to version {}", version)})),
+        Ok(false) => HttpResponse::NotFound().json(json!({"error": "Document or version not found, or the document is in the trash"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/chat")]
+async fn chat(
+    user: AuthenticatedUser, // Require authentication
+    state: web::Data<AppState>,
+    body: web::Json<ChatRequest>,
+) -> impl Responder {
+    // Check subscription
+    let user_db = sqlx::query_as::<_, User>("SELECT * FROM users WHERE id = $1")
+        .bind(&user.id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    let u = match user_db {
+        Ok(Some(u)) => u,
+        _ => return HttpResponse::Unauthorized().json(json!({"error": "User not found"})),
+    };
+
+    if !u.email_verified && u.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Email verification required"}));
+    }
+
+    if let Some(sub_end) = u.subscription_end {
+        if sub_end < Utc::now().naive_utc() {
+             return HttpResponse::PaymentRequired().json(json!({"error": "Subscription expired. Please redeem a voucher."}));
+        }
+    } else {
+         // No subscription
+         return HttpResponse::PaymentRequired().json(json!({"error": "Subscription required. Please redeem a voucher."}));
+    }
+
+    // Custom Greeting Logic
+    let msg_lower = body.message.trim().to_lowercase();
+    let greeting_patterns = ["hi", "hello", "hey", "greetings", "hi krypton", "hello krypton"];
+    if greeting_patterns.contains(&msg_lower.as_str()) {
+        let name = u.fullname.as_ref().filter(|s| !s.is_empty()).unwrap_or(&u.username);
+        let response_text = format!(
+            "Hello {}! 👋 I'm Krypton, your dedicated cybersecurity mentor and CTF challenge expert. Whether you're tackling a web exploit, reverse-engineering a binary, analyzing forensic artifacts, or deciphering cryptography—I'm here to guide you through it!\n\nHow can I assist you today?\n\n🕵️‍♂️ Stuck on a CTF challenge? Share the details (category, background, task).\n🔍 Need help analyzing a suspicious file/pcap/artifact?\n📚 Want a deep dive into a security concept?\n🚀 Just starting? Let me suggest beginner-friendly CTF platforms!\nProvide a challenge snippet, task description, or your current approach so I can tailor the guidance!\n\nRemember: All assistance is strictly for educational purposes and ethical hacking. 💻✨",
+            name
+        );
+        
+        // Prepare Session
+        let session_id = if let Some(sid) = &body.session_id {
+            sid.clone()
+        } else {
+            let new_sid = Uuid::new_v4().to_string();
+            let title = "Greeting";
+            let _ = sqlx::query("INSERT INTO chat_sessions (id, user_id, title) VALUES ($1, $2, $3)")
+                .bind(&new_sid)
+                .bind(&user.id)
+                .bind(title)
+                .execute(&state.rag.pool)
+                .await;
+            new_sid
+        };
+
+        // Save User Message
+        let user_msg_id = Uuid::new_v4().to_string();
+        let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'user', $3)")
+            .bind(&user_msg_id)
+            .bind(&session_id)
+            .bind(&body.message)
+            .execute(&state.rag.pool)
+            .await;
+
+        // Save AI Response
+        let ai_msg_id = Uuid::new_v4().to_string();
+        let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'assistant', $3)")
+            .bind(&ai_msg_id)
+            .bind(&session_id)
+            .bind(&response_text)
+            .execute(&state.rag.pool)
+            .await;
+            
+        // Update session
+        let _ = sqlx::query("UPDATE chat_sessions SET updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+            .bind(&session_id)
+            .execute(&state.rag.pool)
+            .await;
+
+        return HttpResponse::Ok().json(ChatResponse { response: response_text, session_id });
+    }
+
+    // Resolve persona (explicit choice, else the one stored on the session)
+    let persona = match resolve_persona(&state.rag.pool, body.persona.as_deref(), body.session_id.as_deref(), &user.id).await {
+        Ok(p) => p,
+        Err(resp) => return resp,
+    };
+    let persona_id = persona.as_ref().map(|p| p.id.clone());
+
+    // Hint mode and challenge state of the session (new sessions can opt in via the request)
+    let (stored_hint_mode, stored_hint_level, stored_challenge_id) = match &body.session_id {
+        Some(sid) => sqlx::query_as::<_, (bool, i32, Option<String>)>("SELECT hint_mode, hint_level, challenge_id FROM chat_sessions WHERE id = $1 AND user_id = $2")
+            .bind(sid)
+            .bind(&user.id)
+            .fetch_optional(&state.rag.pool)
+            .await
+            .unwrap_or(None)
+            .unwrap_or((false, 0, None)),
+        None => (false, 0, None),
+    };
+    let hint_mode = stored_hint_mode || body.hint_mode == Some(true);
+    let hint_level = HintLevel::from_i32(stored_hint_level);
+
+    if let Some(cid) = &body.challenge_id {
+        let challenge_check = sqlx::query("SELECT 1 FROM challenges WHERE id = $1 AND is_published = TRUE")
+            .bind(cid)
+            .fetch_optional(&state.rag.pool)
+            .await;
+        if !matches!(challenge_check, Ok(Some(_))) {
+            return HttpResponse::BadRequest().json(json!({"error": "Unknown challenge"}));
+        }
+    }
+    let challenge_id = body.challenge_id.clone().or(stored_challenge_id);
+
+    // Knowledge base collections this user may retrieve from
+    let readable_collections = match collections::readable_by(&state.rag.pool, &u).await {
+        Ok(ids) => ids,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+    let access_key = collections::access_key(readable_collections.as_deref());
+
+    let model = body.model.clone().unwrap_or_else(|| "deepseek/deepseek-r1-0528:free".to_string());
+    let cache_scope = response_cache::Scope {
+        model: &model,
+        persona_id: persona_id.as_deref(),
+        challenge_id: challenge_id.as_deref(),
+        access_key: &access_key,
+    };
+
+    // 1. Prepare Session (cached answers are recorded in it too)
+    let session_id = if let Some(sid) = &body.session_id {
+        // Verify session belongs to user
+        let session_check = sqlx::query("SELECT 1 FROM chat_sessions WHERE id = $1 AND user_id = $2")
+            .bind(sid)
+            .bind(&user.id)
+            .fetch_optional(&state.rag.pool)
+            .await;
+        
+        if let Ok(Some(_)) = session_check {
+            // Switching persona mid-session applies to the rest of the conversation
+            if body.persona.is_some() {
+                let _ = sqlx::query("UPDATE chat_sessions SET persona_id = $1 WHERE id = $2")
+                    .bind(&persona_id)
+                    .bind(sid)
+                    .execute(&state.rag.pool)
+                    .await;
+            }
+            if hint_mode && !stored_hint_mode {
+                let _ = sqlx::query("UPDATE chat_sessions SET hint_mode = TRUE WHERE id = $1")
+                    .bind(sid)
+                    .execute(&state.rag.pool)
+                    .await;
+            }
+            if body.challenge_id.is_some() {
+                let _ = sqlx::query("UPDATE chat_sessions SET challenge_id = $1 WHERE id = $2")
+                    .bind(&challenge_id)
+                    .bind(sid)
+                    .execute(&state.rag.pool)
+                    .await;
+            }
+            sid.clone()
+        } else {
+             // If invalid session ID, return error
+             return HttpResponse::BadRequest().json(json!({"error": "Invalid session ID"}));
+        }
+    } else {
+        // Create new session
+        let new_sid = Uuid::new_v4().to_string();
+        // Use first few words of message as title (truncated)
+        let title: String = body.message.chars().take(30).collect();
+        let _ = sqlx::query("INSERT INTO chat_sessions (id, user_id, title, model, persona_id, hint_mode, challenge_id) VALUES ($1, $2, $3, $4, $5, $6, $7)")
+            .bind(&new_sid)
+            .bind(&user.id)
+            .bind(&title)
+            .bind(&model)
+            .bind(&persona_id)
+            .bind(hint_mode)
+            .bind(&challenge_id)
+            .execute(&state.rag.pool)
+            .await;
+        new_sid
+    };
+
+    // Semantic Cache Check
+    // Follow-up answers depend on the earlier turns, so only opening questions are cached
+    let has_prior_turns = match &body.session_id {
+        Some(sid) => matches!(
+            sqlx::query("SELECT 1 FROM chat_messages m JOIN chat_sessions s ON s.id = m.session_id WHERE m.session_id = $1 AND s.user_id = $2 LIMIT 1")
+                .bind(sid)
+                .bind(&user.id)
+                .fetch_optional(&state.rag.pool)
+                .await,
+            Ok(Some(_))
+        ),
+        None => false,
+    };
+
+    // Hint mode answers depend on the hint level and attachment answers on the files,
+    // so both bypass the response cache
+    let attachment_ids = body.attachment_ids.clone().unwrap_or_default();
+    let cacheable = !hint_mode && attachment_ids.is_empty() && !has_prior_turns;
+    if cacheable {
+        if let Some(content) = response_cache::lookup(&state.rag, &cache_scope, &body.message).await {
+            // Return cached response
+            let user_msg_id = Uuid::new_v4().to_string();
+            let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'user', $3)")
+                .bind(&user_msg_id)
+                .bind(&session_id)
+                .bind(&body.message)
+                .execute(&state.rag.pool)
+                .await;
+
+            let ai_msg_id = Uuid::new_v4().to_string();
+            let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'assistant', $3)")
+                .bind(&ai_msg_id)
+                .bind(&session_id)
+                .bind(&content)
+                .execute(&state.rag.pool)
+                .await;
+            
+            let _ = sqlx::query("UPDATE chat_sessions SET updated_at = CURRENT_TIMESTAMP WHERE id = $1")
+                .bind(&session_id)
+                .execute(&state.rag.pool)
+                .await;
+
+            return HttpResponse::Ok().json(ChatResponse { response: content, session_id });
+        }
+    }
+
+    // 2. Search RAG
+    let mut retrieval_filter = persona.as_ref().map(|p| p.filter()).unwrap_or_default();
+    retrieval_filter.challenge_id = challenge_id.clone();
+    retrieval_filter.collection_ids = readable_collections;
+
+    // Fetch model configuration from DB for its retrieval settings and custom system prompt
+    let model_config = sqlx::query_as::<_, AIModel>("SELECT * FROM ai_models WHERE api_model_name = $1")
+        .bind(&model)
+        .fetch_optional(&state.rag.pool)
+        .await
+        .unwrap_or(None);
+
+    // Persona settings take precedence over the model's
+    let model_settings = RetrievalSettings::parse(model_config.as_ref().and_then(|m| m.retrieval_settings.as_deref()));
+    let retrieval_settings = persona.as_ref().map(|p| p.settings()).unwrap_or_default().or(model_settings);
+
+    // Rewrite follow-ups into standalone queries using the latest turns of this session
+    let rewrite_mode = query_rewrite::Mode::parse(&settings::get(&state.rag.pool, "query_rewriting").await);
+    let expansion = if rewrite_mode == query_rewrite::Mode::Off {
+        query_rewrite::Expansion { queries: vec![body.message.clone()], passage: None }
+    } else {
+        let recent = match &body.session_id {
+            Some(sid) => sqlx::query_as::<_, (String, String)>(
+                "SELECT role, content FROM (
+                    SELECT m.role, m.content, m.created_at FROM chat_messages m JOIN chat_sessions s ON s.id = m.session_id
+                    WHERE m.session_id = $1 AND s.user_id = $2 ORDER BY m.created_at DESC LIMIT $3
+                 ) recent ORDER BY created_at ASC"
+            )
+            .bind(sid)
+            .bind(&user.id)
+            .bind(query_rewrite::HISTORY_TURNS)
+            .fetch_all(&state.rag.pool)
+            .await
+            .unwrap_or_default(),
+            None => Vec::new(),
+        };
+        let rewrite_model = settings::get(&state.rag.pool, "query_rewrite_model").await;
+        let rewrite_model = if rewrite_model.is_empty() { model.clone() } else { rewrite_model };
+        let api_key = std::env::var("OPENROUTER_API_KEY").unwrap_or_default();
+        query_rewrite::expand(&state.client, &api_key, &rewrite_model, rewrite_mode, &recent, &body.message).await
+    };
+    let relevant_chunks = match state.rag.retrieve(&expansion.queries, expansion.passage.as_deref(), &retrieval_settings, &retrieval_filter).await {
+        Ok(chunks) => chunks,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let context = relevant_chunks
+        .iter()
+        .map(|c| c.content.clone())
+        .collect::<Vec<String>>()
+        .join("\n\n");
+
+    // 3. Save User Message
+    let user_msg_id = Uuid::new_v4().to_string();
+    let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'user', $3)")
+        .bind(&user_msg_id)
+        .bind(&session_id)
+        .bind(&body.message)
+        .execute(&state.rag.pool)
+        .await;
+
+    // Link attachments to this message and collect their analysis reports
+    let mut attachment_reports = Vec::new();
+    if !attachment_ids.is_empty() {
+        let linked = sqlx::query_as::<_, (String,)>(
+            "UPDATE chat_attachments SET session_id = $1, message_id = $2 WHERE id = ANY($3) AND user_id = $4 AND message_id IS NULL AND (session_id IS NULL OR session_id = $1) RETURNING report"
+        )
+        .bind(&session_id)
+        .bind(&user_msg_id)
+        .bind(&attachment_ids)
+        .bind(&user.id)
+        .fetch_all(&state.rag.pool)
+        .await
+        .unwrap_or_default();
+        attachment_reports = linked
+            .into_iter()
+            .filter_map(|(report,)| serde_json::from_str::<serde_json::Value>(&report).ok())
+            .collect();
+    }
+
+    // 4. Load History from DB (to ensure context is correct)
+    let db_history = sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_messages WHERE session_id = $1 ORDER BY created_at ASC")
+        .bind(&session_id)
+        .fetch_all(&state.rag.pool)
+        .await
+        .unwrap_or_default();
+
+    // 5. Prepare OpenRouter Request
+    let api_key = std::env::var("OPENROUTER_API_KEY").unwrap_or_default();
+    if api_key.is_empty() {
+        return HttpResponse::InternalServerError().json(json!({"error": "OpenRouter API Key not set"}));
+    }
+
+    let system_prompt = if let Some(p) = &persona {
+        // Persona templates take precedence over model-level prompts
+        let template = &p.prompt_template;
+        if template.contains("{context}") {
+            template.replace("{context}", &context)
+        } else if template.contains("{}") {
+            template.replace("{}", &context)
+        } else {
+            format!("{}\n\n## KNOWLEDGE BASE CONTEXT\nUse the following retrieved context to augment your answers:\n[CONTEXT BEGIN]\n{}\n[CONTEXT END]", template, context)
+        }
+    } else if let Some(Some(custom_prompt)) = model_config.map(|m| m.system_prompt) {
+        // Inject context into custom prompt
+        if custom_prompt.contains("{}") {
+            custom_prompt.replace("{}", &context)
+        } else {
+            format!("{}\n\n## KNOWLEDGE BASE CONTEXT\nUse the following retrieved context to augment your answers:\n[CONTEXT BEGIN]\n{}\n[CONTEXT END]", custom_prompt, context)
+        }
+    } else {
+        // Default Hardcoded Prompt
+        format!(
+        "You are Krypton, an elite Cybersecurity Mentor and CTF Specialist designed to guide users through complex security challenges and ethical hacking scenarios.
+
+        ## CORE OBJECTIVES
+        1. **Solve CTF Challenges**: Provide step-by-step reasoning for Web, Crypto, Pwn, Reverse Engineering, Forensics, and OSINT challenges.
+        2. **Educational Deep Dives**: Explain *why* a vulnerability exists, not just how to exploit it.
+        3. **Ethical Compliance**: STRICTLY refuse requests for illegal acts (black hat hacking, unauthorized access). Pivot immediately to defense and educational concepts.
+
+        ## CTF METHODOLOGY GUIDELINES
+        - **Web**: Analyze headers, source code, cookies, and input fields. Look for SQLi, XSS, IDOR, SSRF. Suggest tools like Burp Suite, OWASP ZAP.
+        - **Cryptography**: Identify encoding (Base64, Hex), hashing (MD5, SHA), or encryption (RSA, AES). Look for key leakage or weak math.
+        - **Forensics**: Analyze file headers (magic bytes), metadata (exiftool), and hidden streams. Suggest Wireshark for pcap analysis.
+        - **Reverse Engineering**: Discuss static analysis (Ghidra/IDA) and dynamic analysis (GDB/x64dbg). Look for buffer overflows and logic gates.
+        - **Pwn**: Check for binary protections (NX, ASLR, Canary). Explain ROP chains and shellcode injection.
+
+        ## KNOWLEDGE BASE CONTEXT
+        Use the following retrieved context to augment your answers. If the context contains the specific flag or solution, use it to guide the user without just giving the answer immediately unless asked.
+        
+        [CONTEXT BEGIN]
+        {}
+        [CONTEXT END]
+
+        ## RESPONSE FORMAT
+        - **Analysis**: Start with a high-level analysis of the problem.
+        - **Tools**: List recommended tools (e.g., `nmap`, `sqlmap`, `python`).
+        - **Steps**: Numbered, actionable steps to solve the challenge.
+        - **Code**: Provide Python scripts or shell commands in code blocks.
+        - **Remediation**: (If applicable) How to patch the vulnerability.
+
+        ## TONE & STYLE
+        - Be technical but clear.
+        - Use markdown extensively (bolding key terms, code blocks).
+        - If the user provides a flag format (e.g., `CTF{{...}}`), help them find the string matching that pattern.
+        - If context is missing, rely on your extensive general cybersecurity knowledge.",
+        context
+        )
+    };
+
+    let system_prompt = if hint_mode {
+        format!("{}\n\n## HINT MODE\n{}", system_prompt, hint_level.prompt_instructions())
+    } else {
+        system_prompt
+    };
+
+    let system_prompt = if attachment_reports.is_empty() {
+        system_prompt
+    } else {
+        format!("{}\n\n{}", system_prompt, artifacts::prompt_section(&attachment_reports, 6000))
+    };
+
+    let mut messages = vec![
+        json!({"role": "system", "content": system_prompt})
+    ];
+
+    for msg in db_history {
+        // Tool rows are an audit trail of earlier turns; the provider only accepts them right after their tool_calls
+        if msg.role == "tool" {
+            continue;
+        }
+        messages.push(json!({"role": msg.role, "content": msg.content}));
+    }
+    // No need to push body.message again as it's in db_history
+
+    // Many free models reject the tools parameter, so function calling is opt-in
+    let mut tool_definitions = Vec::new();
+    if settings::get(&state.rag.pool, "chat_tools").await == "on" {
+        tool_definitions = tools::definitions();
+    }
+    let can_execute = code_execution_allowed(&state.rag.pool, &u).await;
+    if can_execute {
+        tool_definitions.push(sandbox::tool_definition());
+    }
+
+    println!("Sending request to OpenRouter: Model: {}", model);
+
+    // Prompt instructions alone leak flags, so enforce the hint ladder on everything the user can read
+    let redaction = flag_redaction(&state.rag.pool, hint_mode, hint_level).await;
+
+    let mut tool_rounds = 0;
+    let json_resp = loop {
+        let mut request_body = json!({
+            "model": model,
+            "messages": messages,
+            "max_tokens": 80000
+        });
+        // The last round goes out without tools so the model has to answer
+        if !tool_definitions.is_empty() && tool_rounds < tools::MAX_TOOL_ROUNDS {
+            request_body["tools"] = json!(tool_definitions);
+        }
+
+        let json_resp = match call_openrouter(&state.client, &api_key, &request_body).await {
+            Ok(json_resp) => json_resp,
+            Err(resp) => return resp,
+        };
+
+        let message = &json_resp["choices"][0]["message"];
+        let tool_calls = message["tool_calls"].as_array().cloned().unwrap_or_default();
+        if tool_calls.is_empty() || tool_rounds >= tools::MAX_TOOL_ROUNDS {
+            break json_resp;
+        }
+        tool_rounds += 1;
+
+        messages.push(json!({
+            "role": "assistant",
+            "content": message["content"].as_str().unwrap_or_default(),
+            "tool_calls": tool_calls
+        }));
+
+        for call in &tool_calls {
+            let name = call["function"]["name"].as_str().unwrap_or_default();
+            let raw_args = call["function"]["arguments"].as_str().unwrap_or("{}");
+            let result = match serde_json::from_str::<serde_json::Value>(raw_args) {
+                Ok(args) if name == sandbox::TOOL_NAME && can_execute => {
+                    match (args["language"].as_str().and_then(Language::parse), args["code"].as_str()) {
+                        (Some(language), Some(code)) => {
+                            run_and_audit(&state.rag.pool, &user.id, Some(&session_id), None, "tool", language, code)
+                                .await
+                                .map(|r| sandbox::format_result(&r))
+                        }
+                        _ => Err("language must be 'python' or 'shell' and code is required".to_string()),
+                    }
+                }
+                Ok(args) => tools::execute(name, &args),
+                Err(e) => Err(format!("arguments are not valid JSON: {}", e)),
+            };
+            let output = match &result {
+                Ok(output) => output.clone(),
+                Err(e) => format!("Error: {}", e),
+            };
+            // Tool records are shown in the transcript, so a decoded flag must not survive in them
+            let (output, raw_args) = match &redaction {
+                Some(patterns) => (hints::redact_flags(&output, patterns).0, hints::redact_flags(raw_args, patterns).0),
+                None => (output, raw_args.to_string()),
+            };
+
+            messages.push(json!({
+                "role": "tool",
+                "tool_call_id": call["id"],
+                "content": output
+            }));
+
+            let record = json!({
+                "name": name,
+                "arguments": raw_args,
+                "result": output,
+                "ok": result.is_ok()
+            });
+            let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'tool', $3)")
+                .bind(Uuid::new_v4().to_string())
+                .bind(&session_id)
+                .bind(record.to_string())
+                .execute(&state.rag.pool)
+                .await;
+        }
+    };
+
+    let content = json_resp["choices"][0]["message"]["content"]
+        .as_str()
+        .unwrap_or("No response from AI")
+        .to_string();
+
+    let content = if let Some(patterns) = &redaction {
+        let (redacted, count) = hints::redact_flags(&content, patterns);
+        if count > 0 {
+            println!("Redacted {} flag(s) in session {} at hint level {}", count, session_id, hint_level.name());
+        }
+        redacted
+    } else {
+        content
+    };
+    
+    // Cache the answer for semantically similar opening questions
+    if cacheable {
+        response_cache::store(&state.rag, &cache_scope, &body.message, &content).await;
+    }
+
+    // Save AI response along with the chunks it was grounded on
+    // Previews are redacted before truncation so a flag cut short can't slip past the patterns
+    let sources = json!(relevant_chunks
+        .iter()
+        .map(|c| {
+            let preview = match &redaction {
+                Some(patterns) => hints::redact_flags(&c.content, patterns).0,
+                None => c.content.clone(),
+            };
+            json!({
+                "id": c.id,
+                "score": c.score,
+                "preview": preview.chars().take(120).collect::<String>()
+            })
+        })
+        .collect::<Vec<_>>())
+    .to_string();
+    let ai_msg_id = Uuid::new_v4().to_string();
+    let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content, sources) VALUES ($1, $2, 'assistant', $3, $4)")
+        .bind(&ai_msg_id)
+        .bind(&session_id)
+        .bind(&content)
+        .bind(&sources)
+        .execute(&state.rag.pool)
+        .await;
+    
+    // Update session updated_at and the model last used in it
+    let _ = sqlx::query("UPDATE chat_sessions SET updated_at = CURRENT_TIMESTAMP, model = $2 WHERE id = $1")
+        .bind(&session_id)
+        .bind(&model)
+        .execute(&state.rag.pool)
+        .await;
+
+    // Replace the truncated first-message title with an LLM-generated one
+    let auto_title = env::var("CHAT_AUTO_TITLE").map(|v| v == "true").unwrap_or(false);
+    if body.session_id.is_none() && auto_title {
+        let client = state.client.clone();
+        let pool = state.rag.pool.clone();
+        let sid = session_id.clone();
+        let question = body.message.clone();
+        let answer = content.clone();
+        let title_model = model.clone();
+        tokio::spawn(async move {
+            if let Some(title) = generate_session_title(&client, &api_key, &title_model, &question, &answer).await {
+                let _ = sqlx::query("UPDATE chat_sessions SET title = $1 WHERE id = $2 AND title_customized = FALSE")
+                    .bind(title)
+                    .bind(sid)
+                    .execute(&pool)
+                    .await;
+            }
+        });
+    }
+    
+    HttpResponse::Ok().json(ChatResponse { response: content, session_id })
+}
+
+// Chat History Endpoints
+
+#[get("/api/chat/history")]
+async fn list_chat_sessions(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    query: web::Query<ListChatSessionsQuery>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+
+    let mut qb = sqlx::QueryBuilder::<sqlx::Postgres>::new("SELECT * FROM chat_sessions WHERE user_id = ");
+    qb.push_bind(&user.id);
+    if let Some(model) = &query.model {
+        qb.push(" AND model = ").push_bind(model);
+    }
+    if let Some(from) = query.from {
+        qb.push(" AND updated_at::date >= ").push_bind(from);
+    }
+    if let Some(to) = query.to {
+        qb.push(" AND updated_at::date <= ").push_bind(to);
+    }
+    if let Some(folder_id) = &query.folder_id {
+        qb.push(" AND folder_id = ").push_bind(folder_id);
+    }
+    if let Some(pinned) = query.pinned {
+        qb.push(" AND pinned = ").push_bind(pinned);
+    }
+    qb.push(" AND archived = ").push_bind(query.archived.unwrap_or(false));
+    qb.push(" ORDER BY pinned DESC, updated_at DESC");
+
+    // Without paging params the full list is returned, as before
+    if query.page.is_some() || query.limit.is_some() {
+        let page = query.page.unwrap_or(1).max(1);
+        let limit = query.limit.unwrap_or(50).clamp(1, 200);
+        qb.push(" LIMIT ").push_bind(limit);
+        qb.push(" OFFSET ").push_bind((page - 1) * limit);
+    }
+
+    let sessions = qb.build_query_as::<ChatSession>()
+        .fetch_all(&state.rag.pool)
+        .await;
+    
+    match sessions {
+        Ok(s) => HttpResponse::Ok().json(s),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/chat/search")]
+async fn search_chat_history(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    query: web::Query<ChatSearchQuery>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let q = query.q.trim();
+    if q.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "q is required"}));
+    }
+
+    let page = query.page.unwrap_or(1).max(1);
+    let limit = query.limit.unwrap_or(20).clamp(1, 100);
+    let offset = (page - 1) * limit;
+
+    let results = sqlx::query_as::<_, ChatSearchResult>(
+        r#"SELECT m.session_id, s.title AS session_title, m.id AS message_id, m.role, m.created_at,
+            ts_headline('english', m.content, q, $5) AS snippet,
+            ts_rank(to_tsvector('english', m.content), q) AS rank
+        FROM chat_messages m
+        JOIN chat_sessions s ON s.id = m.session_id,
+            websearch_to_tsquery('english', $2) q
+        WHERE s.user_id = $1 AND to_tsvector('english', m.content) @@ q
+        ORDER BY rank DESC, m.created_at DESC
+        LIMIT $3 OFFSET $4"#
+    )
+    .bind(&user.id)
+    .bind(q)
+    .bind(limit)
+    .bind(offset)
+    .bind(format!("StartSel={}, StopSel={}, MaxFragments=2, MaxWords=30, MinWords=10", MATCH_START, MATCH_STOP))
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match results {
+        Ok(mut r) => {
+            for result in &mut r {
+                result.snippet = highlight_snippet(&result.snippet);
+            }
+            HttpResponse::Ok().json(json!({
+                "results": r,
+                "page": page,
+                "limit": limit
+            }))
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// ts_headline wraps matches in these instead of tags. A message that contains them itself
+// only gains stray highlights, never markup of its own
+const MATCH_START: char = '\u{2}';
+const MATCH_STOP: char = '\u{3}';
+
+/// Message content is untrusted, so the snippet is escaped before matches are marked up.
+fn highlight_snippet(snippet: &str) -> String {
+    export::escape_html(snippet).replace(MATCH_START, "<mark>").replace(MATCH_STOP, "</mark>")
+}
+
+#[get("/api/chat/history/{id}")]
+async fn get_chat_session(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let session_id = path.into_inner();
+    
+    // Check ownership
+    let session_check = sqlx::query_as::<_, (bool, i32)>("SELECT hint_mode, hint_level FROM chat_sessions WHERE id = $1 AND user_id = $2")
+        .bind(&session_id)
+        .bind(&user.id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    if let Ok(Some((hint_mode, hint_level))) = session_check {
+        let messages = sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_messages WHERE session_id = $1 ORDER BY created_at ASC")
+            .bind(&session_id)
+            .fetch_all(&state.rag.pool)
+            .await;
+        
+        match messages {
+            Ok(mut m) => {
+                if let Some(patterns) = flag_redaction(&state.rag.pool, hint_mode, HintLevel::from_i32(hint_level)).await {
+                    redact_sources(&mut m, &patterns);
+                }
+                HttpResponse::Ok().json(m)
+            }
+            Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        }
+    } else {
+        HttpResponse::NotFound().json(json!({"error": "Session not found or access denied"}))
+    }
+}
+
+#[get("/api/chat/history/{id}/export")]
+async fn export_chat_session(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    query: web::Query<ExportQuery>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let format = match ExportFormat::parse(query.format.as_deref()) {
+        Some(f) => f,
+        None => return HttpResponse::BadRequest().json(json!({"error": "format must be one of md, json, html"})),
+    };
+    let session_id = path.into_inner();
+
+    let session = sqlx::query_as::<_, ChatSession>("SELECT * FROM chat_sessions WHERE id = $1 AND user_id = $2")
+        .bind(&session_id)
+        .bind(&user.id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    let session = match session {
+        Ok(Some(s)) => s,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Session not found or access denied"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let mut messages = match sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_messages WHERE session_id = $1 ORDER BY created_at ASC")
+        .bind(&session_id)
+        .fetch_all(&state.rag.pool)
+        .await
+    {
+        Ok(m) => m,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+    if let Some(patterns) = flag_redaction(&state.rag.pool, session.hint_mode, HintLevel::from_i32(session.hint_level)).await {
+        redact_sources(&mut messages, &patterns);
+    }
+
+    let body = export::render(format, &export::to_export(&session, &messages));
+    HttpResponse::Ok()
+        .content_type(format.content_type())
+        .insert_header(("Content-Disposition", format!("attachment; filename=\"{}.{}\"", export::file_stem(&session), format.extension())))
+        .body(body)
+}
+
+#[get("/api/chat/export")]
+async fn export_all_chat_sessions(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    query: web::Query<ExportQuery>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let format = match ExportFormat::parse(query.format.as_deref()) {
+        Some(f) => f,
+        None => return HttpResponse::BadRequest().json(json!({"error": "format must be one of md, json, html"})),
+    };
+
+    let sessions = match sqlx::query_as::<_, ChatSession>("SELECT * FROM chat_sessions WHERE user_id = $1 ORDER BY created_at ASC")
+        .bind(&user.id)
+        .fetch_all(&state.rag.pool)
+        .await
+    {
+        Ok(s) => s,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let mut files = Vec::with_capacity(sessions.len());
+    for session in &sessions {
+        let messages = sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_messages WHERE session_id = $1 ORDER BY created_at ASC")
+            .bind(&session.id)
+            .fetch_all(&state.rag.pool)
+            .await
+            .unwrap_or_default();
+        let name = format!("{}.{}", export::file_stem(session), format.extension());
+        files.push((name, export::render(format, &export::to_export(session, &messages))));
+    }
+
+    match web::block(move || export::build_zip(files).map_err(|e| e.to_string())).await {
+        Ok(Ok(archive)) => HttpResponse::Ok()
+            .content_type("application/zip")
+            .insert_header(("Content-Disposition", "attachment; filename=\"krypton-sessions.zip\""))
+            .body(archive),
+        Ok(Err(e)) => HttpResponse::InternalServerError().json(json!({"error": e})),
+        Err(_) => HttpResponse::InternalServerError().json(json!({"error": "Failed to build archive"})),
+    }
+}
+
+#[post("/api/chat/import")]
+async fn import_chat_session(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    body: web::Json<SessionExport>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if body.version > export::EXPORT_VERSION {
+        return HttpResponse::BadRequest().json(json!({"error": "Unsupported export version"}));
+    }
+    if body.messages.iter().any(|m| !matches!(m.role.as_str(), "user" | "assistant" | "tool")) {
+        return HttpResponse::BadRequest().json(json!({"error": "Messages must have role 'user', 'assistant' or 'tool'"}));
+    }
+
+    let mut tx = match state.rag.pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let session_id = Uuid::new_v4().to_string();
+    let title = body.title.clone().unwrap_or_else(|| "Imported session".to_string());
+    if let Err(e) = sqlx::query("INSERT INTO chat_sessions (id, user_id, title, model) VALUES ($1, $2, $3, $4)")
+        .bind(&session_id)
+        .bind(&user.id)
+        .bind(&title)
+        .bind(&body.model)
+        .execute(&mut *tx)
+        .await
+    {
+        return HttpResponse::InternalServerError().json(json!({"error": e.to_string()}));
+    }
+
+    // Keep the original ordering even when timestamps are missing
+    let base_time = Utc::now().naive_utc();
+    for (i, msg) in body.messages.iter().enumerate() {
+        let created_at = msg.created_at.unwrap_or(base_time + Duration::milliseconds(i as i64));
+        if let Err(e) = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content, created_at, sources) VALUES ($1, $2, $3, $4, $5, $6)")
+            .bind(Uuid::new_v4().to_string())
+            .bind(&session_id)
+            .bind(&msg.role)
+            .bind(&msg.content)
+            .bind(created_at)
+            .bind(msg.sources.as_ref().map(|s| s.to_string()))
+            .execute(&mut *tx)
+            .await
+        {
+            return HttpResponse::InternalServerError().json(json!({"error": e.to_string()}));
+        }
+    }
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Created().json(json!({"session_id": session_id, "messages": body.messages.len()})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/chat/attachments")]
+async fn upload_chat_attachment(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    query: web::Query<AttachmentUploadQuery>,
+    mut payload: Multipart,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if let Some(sid) = &query.session_id {
+        let session_check = sqlx::query("SELECT 1 FROM chat_sessions WHERE id = $1 AND user_id = $2")
             .bind(sid)
             .bind(&user.id)
             .fetch_optional(&state.rag.pool)
             .await;
-        
-        if let Ok(Some(_)) = session_check {
-            sid.clone()
-        } else {
-             // If invalid session ID, return error
-             return HttpResponse::BadRequest().json(json!({"error": "Invalid session ID"}));
+        if !matches!(session_check, Ok(Some(_))) {
+            return HttpResponse::NotFound().json(json!({"error": "Session not found or access denied"}));
+        }
+    }
+
+    let max_bytes = env::var("MAX_CHAT_ATTACHMENT_MB").ok().and_then(|v| v.parse::<usize>().ok()).unwrap_or(20) * 1024 * 1024;
+    let dir = upload_dir().join("chat").join(&user.id);
+    let dir_clone = dir.clone();
+    if !matches!(web::block(move || std::fs::create_dir_all(dir_clone)).await, Ok(Ok(()))) {
+        return HttpResponse::InternalServerError().json(json!({"error": "Failed to create upload directory"}));
+    }
+
+    let mut uploaded = Vec::new();
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let filename = field.content_disposition().get_filename().unwrap_or("attachment").to_string();
+        let mut data = Vec::new();
+        while let Some(chunk) = field.next().await {
+            match chunk {
+                Ok(d) => data.extend_from_slice(&d),
+                Err(_) => return HttpResponse::BadRequest().json(json!({"error": "Upload interrupted"})),
+            }
+            if data.len() > max_bytes {
+                return HttpResponse::PayloadTooLarge().json(json!({"error": format!("Attachment exceeds {} bytes", max_bytes)}));
+            }
+        }
+
+        let id = Uuid::new_v4().to_string();
+        let storage_path = dir.join(&id);
+        let path_clone = storage_path.clone();
+        let name_clone = filename.clone();
+        // Hashing and extraction are CPU bound, keep them off the async workers
+        let processed = web::block(move || {
+            let sha256 = format!("{:x}", Sha256::digest(&data));
+            let report = artifacts::analyze(&name_clone, &data);
+            std::fs::write(&path_clone, &data).map(|_| (sha256, report, data.len()))
+        })
+        .await;
+
+        let (sha256, report, size) = match processed {
+            Ok(Ok(p)) => p,
+            _ => return HttpResponse::InternalServerError().json(json!({"error": "Failed to store attachment"})),
+        };
+        let file_type = report["file_type"].as_str().unwrap_or("unknown").to_string();
+
+        let result = sqlx::query(
+            "INSERT INTO chat_attachments (id, user_id, session_id, filename, file_type, size_bytes, sha256, storage_path, report) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)"
+        )
+        .bind(&id)
+        .bind(&user.id)
+        .bind(&query.session_id)
+        .bind(&filename)
+        .bind(&file_type)
+        .bind(size as i64)
+        .bind(&sha256)
+        .bind(storage_path.to_string_lossy().to_string())
+        .bind(report.to_string())
+        .execute(&state.rag.pool)
+        .await;
+        if let Err(e) = result {
+            return HttpResponse::InternalServerError().json(json!({"error": e.to_string()}));
+        }
+        uploaded.push(json!({"id": id, "filename": filename, "sha256": sha256, "size_bytes": size, "report": report}));
+    }
+
+    if uploaded.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "No file found"}));
+    }
+    HttpResponse::Created().json(json!({"attachments": uploaded}))
+}
+
+#[get("/api/chat/history/{id}/attachments")]
+async fn list_session_attachments(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let attachments = sqlx::query_as::<_, ChatAttachment>(
+        "SELECT * FROM chat_attachments WHERE session_id = $1 AND user_id = $2 ORDER BY created_at ASC"
+    )
+    .bind(path.into_inner())
+    .bind(&user.id)
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match attachments {
+        Ok(a) => HttpResponse::Ok().json(a),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/chat/history/{id}/hint")]
+async fn escalate_hint_level(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let session_id = path.into_inner();
+
+    let row = sqlx::query_as::<_, (bool, i32)>("SELECT hint_mode, hint_level FROM chat_sessions WHERE id = $1 AND user_id = $2")
+        .bind(&session_id)
+        .bind(&user.id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    let (hint_mode, level) = match row {
+        Ok(Some((mode, level))) => (mode, HintLevel::from_i32(level)),
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Session not found or access denied"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+    if !hint_mode {
+        return HttpResponse::BadRequest().json(json!({"error": "Session is not in hint mode"}));
+    }
+    if level == HintLevel::Solution {
+        return HttpResponse::BadRequest().json(json!({"error": "Solution already unlocked"}));
+    }
+
+    let next = level.next();
+    let result = sqlx::query("UPDATE chat_sessions SET hint_level = $1 WHERE id = $2")
+        .bind(next as i32)
+        .bind(&session_id)
+        .execute(&state.rag.pool)
+        .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(json!({"hint_level": next as i32, "name": next.name()})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[patch("/api/chat/history/{id}")]
+async fn update_chat_session(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<UpdateChatSessionRequest>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let session_id = path.into_inner();
+
+    let existing = sqlx::query_as::<_, ChatSession>("SELECT * FROM chat_sessions WHERE id = $1 AND user_id = $2")
+        .bind(&session_id)
+        .bind(&user.id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    match existing {
+        Ok(Some(mut session)) => {
+            if let Some(title) = &body.title {
+                let title = title.trim();
+                if title.is_empty() {
+                    return HttpResponse::BadRequest().json(json!({"error": "Title cannot be empty"}));
+                }
+                session.title = Some(title.chars().take(200).collect());
+                session.title_customized = true;
+            }
+            if let Some(hint_mode) = body.hint_mode {
+                if !hint_mode && session.hint_mode {
+                    return HttpResponse::BadRequest().json(json!({"error": "Hint mode cannot be disabled; escalate to the solution level instead"}));
+                }
+                session.hint_mode = hint_mode;
+            }
+            if let Some(pinned) = body.pinned { session.pinned = pinned; }
+            if let Some(archived) = body.archived { session.archived = archived; }
+            if let Some(folder_id) = &body.folder_id {
+                if folder_id.is_empty() {
+                    session.folder_id = None;
+                } else {
+                    // Folders are private to their owner
+                    let folder_check = sqlx::query("SELECT 1 FROM chat_folders WHERE id = $1 AND user_id = $2")
+                        .bind(folder_id)
+                        .bind(&user.id)
+                        .fetch_optional(&state.rag.pool)
+                        .await;
+                    match folder_check {
+                        Ok(Some(_)) => session.folder_id = Some(folder_id.clone()),
+                        Ok(None) => return HttpResponse::BadRequest().json(json!({"error": "Folder not found"})),
+                        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+                    }
+                }
+            }
+
+            let result = sqlx::query(
+                "UPDATE chat_sessions SET title = $1, title_customized = $2, pinned = $3, archived = $4, folder_id = $5, hint_mode = $6 WHERE id = $7"
+            )
+            .bind(&session.title)
+            .bind(session.title_customized)
+            .bind(session.pinned)
+            .bind(session.archived)
+            .bind(&session.folder_id)
+            .bind(session.hint_mode)
+            .bind(&session.id)
+            .execute(&state.rag.pool)
+            .await;
+
+            match result {
+                Ok(_) => HttpResponse::Ok().json(session),
+                Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+            }
+        },
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "Session not found or access denied"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[delete("/api/chat/history/{id}")]
+async fn delete_chat_session(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let session_id = path.into_inner();
+    
+    // Check ownership
+    let session_check = sqlx::query("SELECT 1 FROM chat_sessions WHERE id = $1 AND user_id = $2")
+        .bind(&session_id)
+        .bind(&user.id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    if let Ok(Some(_)) = session_check {
+        // Delete session (messages cascade delete)
+        let result = sqlx::query("DELETE FROM chat_sessions WHERE id = $1")
+            .bind(&session_id)
+            .execute(&state.rag.pool)
+            .await;
+        
+        match result {
+            Ok(_) => HttpResponse::Ok().json(json!({"message": "Session deleted"})),
+            Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        }
+    } else {
+        HttpResponse::NotFound().json(json!({"error": "Session not found or access denied"}))
+    }
+}
+
+async fn resolve_persona(
+    pool: &DbPool,
+    requested: Option<&str>,
+    session_id: Option<&str>,
+    user_id: &str,
+) -> Result<Option<Persona>, HttpResponse> {
+    if let Some(key) = requested {
+        let persona = sqlx::query_as::<_, Persona>("SELECT * FROM personas WHERE (id = $1 OR slug = $1) AND is_active = TRUE")
+            .bind(key)
+            .fetch_optional(pool)
+            .await;
+        return match persona {
+            Ok(Some(p)) => Ok(Some(p)),
+            Ok(None) => Err(HttpResponse::BadRequest().json(json!({"error": "Unknown persona"}))),
+            Err(_) => Err(HttpResponse::InternalServerError().json(json!({"error": "Database error"}))),
+        };
+    }
+
+    let Some(sid) = session_id else {
+        return Ok(None);
+    };
+    let persona = sqlx::query_as::<_, Persona>(
+        "SELECT p.* FROM personas p JOIN chat_sessions s ON s.persona_id = p.id WHERE s.id = $1 AND s.user_id = $2 AND p.is_active = TRUE"
+    )
+    .bind(sid)
+    .bind(user_id)
+    .fetch_optional(pool)
+    .await
+    .unwrap_or(None);
+    Ok(persona)
+}
+
+// Persona Endpoints
+
+#[get("/api/personas")]
+async fn list_personas(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    // Prompt templates stay server-side for regular users
+    let personas = sqlx::query("SELECT id, slug, name, description FROM personas WHERE is_active = TRUE ORDER BY name ASC")
+        .fetch_all(&state.rag.pool)
+        .await;
+
+    match personas {
+        Ok(rows) => {
+            let list: Vec<serde_json::Value> = rows
+                .iter()
+                .map(|row| json!({
+                    "id": row.get::<String, _>("id"),
+                    "slug": row.get::<String, _>("slug"),
+                    "name": row.get::<String, _>("name"),
+                    "description": row.get::<Option<String>, _>("description"),
+                }))
+                .collect();
+            HttpResponse::Ok().json(list)
+        },
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/admin/personas")]
+async fn admin_list_personas(user: AuthenticatedUser, state: web::Data<AppState>) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+
+    let personas = sqlx::query_as::<_, Persona>("SELECT * FROM personas ORDER BY created_at DESC")
+        .fetch_all(&state.rag.pool)
+        .await;
+
+    match personas {
+        Ok(p) => HttpResponse::Ok().json(p),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/admin/personas")]
+async fn add_persona(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    body: web::Json<CreatePersonaRequest>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+
+    if let Err(e) = collections::validate_slug(&body.slug) {
+        return HttpResponse::BadRequest().json(json!({"error": e}));
+    }
+    if let Some(Err(e)) = body.retrieval_settings.as_ref().map(|s| s.validate()) {
+        return HttpResponse::BadRequest().json(json!({"error": e}));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let filter = body.retrieval_filter.as_ref().map(|f| json!(f).to_string());
+    let settings = body.retrieval_settings.as_ref().map(|s| json!(s).to_string());
+
+    let result = sqlx::query(
+        "INSERT INTO personas (id, slug, name, description, prompt_template, retrieval_filter, retrieval_settings) VALUES ($1, $2, $3, $4, $5, $6, $7)",
+    )
+    .bind(&id)
+    .bind(&body.slug)
+    .bind(&body.name)
+    .bind(&body.description)
+    .bind(&body.prompt_template)
+    .bind(filter)
+    .bind(settings)
+    .execute(&state.rag.pool)
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Created().json(json!({"id": id, "message": "Persona added successfully"})),
+        Err(e) => persona_write_error(e),
+    }
+}
+
+#[put("/api/admin/personas/{id}")]
+async fn update_persona(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<UpdatePersonaRequest>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+
+    if let Some(Err(e)) = body.slug.as_deref().map(collections::validate_slug) {
+        return HttpResponse::BadRequest().json(json!({"error": e}));
+    }
+    if let Some(Err(e)) = body.retrieval_settings.as_ref().map(|s| s.validate()) {
+        return HttpResponse::BadRequest().json(json!({"error": e}));
+    }
+
+    let id = path.into_inner();
+    let existing = sqlx::query_as::<_, Persona>("SELECT * FROM personas WHERE id = $1")
+        .bind(&id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    match existing {
+        Ok(Some(mut persona)) => {
+            if let Some(slug) = &body.slug { persona.slug = slug.clone(); }
+            if let Some(name) = &body.name { persona.name = name.clone(); }
+            if let Some(desc) = &body.description { persona.description = Some(desc.clone()); }
+            if let Some(template) = &body.prompt_template { persona.prompt_template = template.clone(); }
+            if let Some(filter) = &body.retrieval_filter { persona.retrieval_filter = Some(json!(filter).to_string()); }
+            if let Some(settings) = &body.retrieval_settings { persona.retrieval_settings = Some(json!(settings).to_string()); }
+            if let Some(active) = body.is_active { persona.is_active = active; }
+
+            let update_result = sqlx::query(
+                "UPDATE personas SET slug = $1, name = $2, description = $3, prompt_template = $4, retrieval_filter = $5, retrieval_settings = $6, is_active = $7 WHERE id = $8"
+            )
+            .bind(persona.slug)
+            .bind(persona.name)
+            .bind(persona.description)
+            .bind(persona.prompt_template)
+            .bind(persona.retrieval_filter)
+            .bind(persona.retrieval_settings)
+            .bind(persona.is_active)
+            .bind(id)
+            .execute(&state.rag.pool)
+            .await;
+
+            match update_result {
+                Ok(_) => HttpResponse::Ok().json(json!({"message": "Persona updated successfully"})),
+                Err(e) => persona_write_error(e),
+            }
+        },
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "Persona not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[delete("/api/admin/personas/{id}")]
+async fn delete_persona(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+
+    let result = sqlx::query("DELETE FROM personas WHERE id = $1")
+        .bind(path.into_inner())
+        .execute(&state.rag.pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => HttpResponse::NotFound().json(json!({"error": "Persona not found"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Persona deleted successfully"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+fn persona_write_error(e: sqlx::Error) -> HttpResponse {
+    match e.as_database_error() {
+        Some(db) if db.is_unique_violation() => HttpResponse::Conflict().json(json!({"error": "A persona with this slug already exists"})),
+        _ => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Challenge Endpoints
+
+fn upload_dir() -> std::path::PathBuf {
+    std::path::PathBuf::from(env::var("UPLOAD_DIR").unwrap_or_else(|_| "uploads".to_string()))
+}
+
+fn validate_challenge_fields(category: &str, difficulty: &str, points: Option<i32>) -> Result<(), HttpResponse> {
+    if category.trim().is_empty() {
+        return Err(HttpResponse::BadRequest().json(json!({"error": "category is required"})));
+    }
+    if !CHALLENGE_DIFFICULTIES.contains(&difficulty) {
+        return Err(HttpResponse::BadRequest().json(json!({"error": format!("difficulty must be one of {}", CHALLENGE_DIFFICULTIES.join(", "))})));
+    }
+    if points.map(|p| p < 0).unwrap_or(false) {
+        return Err(HttpResponse::BadRequest().json(json!({"error": "points must not be negative"})));
+    }
+    Ok(())
+}
+
+#[get("/api/challenges")]
+async fn list_challenges(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    query: web::Query<ListChallengesQuery>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let challenges = sqlx::query_as::<_, Challenge>(
+        "SELECT * FROM challenges WHERE is_published = TRUE AND ($1::text IS NULL OR category = $1) AND ($2::text IS NULL OR difficulty = $2) ORDER BY category ASC, points ASC"
+    )
+    .bind(&query.category)
+    .bind(&query.difficulty)
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match challenges {
+        Ok(c) => HttpResponse::Ok().json(c),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/challenges/{id}")]
+async fn get_challenge(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let id = path.into_inner();
+    let is_editor = user.role == "admin" || user.role == "editor";
+
+    let challenge = sqlx::query_as::<_, Challenge>("SELECT * FROM challenges WHERE id = $1 AND (is_published = TRUE OR $2)")
+        .bind(&id)
+        .bind(is_editor)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    let challenge = match challenge {
+        Ok(Some(c)) => c,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Challenge not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let attachments = sqlx::query_as::<_, ChallengeAttachment>("SELECT * FROM challenge_attachments WHERE challenge_id = $1 ORDER BY created_at ASC")
+        .bind(&id)
+        .fetch_all(&state.rag.pool)
+        .await
+        .unwrap_or_default();
+    let hint_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM challenge_hints WHERE challenge_id = $1")
+        .bind(&id)
+        .fetch_one(&state.rag.pool)
+        .await
+        .unwrap_or((0,));
+
+    let mut body = json!({
+        "challenge": challenge,
+        "attachments": attachments,
+        "hint_count": hint_count.0,
+    });
+    // Editors see hint contents; players unlock them one by one
+    if is_editor {
+        let hints = sqlx::query_as::<_, ChallengeHint>("SELECT * FROM challenge_hints WHERE challenge_id = $1 ORDER BY position ASC")
+            .bind(&id)
+            .fetch_all(&state.rag.pool)
+            .await
+            .unwrap_or_default();
+        body["hints"] = json!(hints);
+    }
+    HttpResponse::Ok().json(body)
+}
+
+#[get("/api/challenges/{id}/attachments/{attachment_id}")]
+async fn download_challenge_attachment(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let (challenge_id, attachment_id) = path.into_inner();
+    let is_editor = user.role == "admin" || user.role == "editor";
+
+    let attachment = sqlx::query_as::<_, ChallengeAttachment>(
+        "SELECT a.* FROM challenge_attachments a JOIN challenges c ON c.id = a.challenge_id WHERE a.id = $1 AND a.challenge_id = $2 AND (c.is_published = TRUE OR $3)"
+    )
+    .bind(&attachment_id)
+    .bind(&challenge_id)
+    .bind(is_editor)
+    .fetch_optional(&state.rag.pool)
+    .await;
+
+    match attachment {
+        Ok(Some(a)) => match web::block(move || std::fs::read(a.storage_path).map(|data| (a.filename, data))).await {
+            Ok(Ok((filename, data))) => HttpResponse::Ok()
+                .content_type("application/octet-stream")
+                .insert_header(("Content-Disposition", format!("attachment; filename=\"{}\"", filename.replace('"', ""))))
+                .body(data),
+            _ => HttpResponse::InternalServerError().json(json!({"error": "Failed to read attachment"})),
+        },
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "Attachment not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+/// Checks a flag and records the solve with decayed points. Krypton assistance is only seen in
+/// sessions attached to the challenge: answers obtained in an ordinary session, without hint
+/// mode or a challenge, are not penalised.
+#[post("/api/challenges/{id}/submit")]
+async fn submit_flag(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<FlagSubmission>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let challenge_id = path.into_inner();
+
+    let challenge = sqlx::query_as::<_, Challenge>("SELECT * FROM challenges WHERE id = $1 AND is_published = TRUE")
+        .bind(&challenge_id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+    let challenge = match challenge {
+        Ok(Some(c)) => c,
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Challenge not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let solved = sqlx::query("SELECT 1 FROM challenge_solves WHERE challenge_id = $1 AND user_id = $2")
+        .bind(&challenge_id)
+        .bind(&user.id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+    if let Ok(Some(_)) = solved {
+        return HttpResponse::BadRequest().json(json!({"error": "Challenge already solved"}));
+    }
+
+    // Rate limit per user and challenge to stop flag brute forcing. The attempt is recorded under
+    // a per-user, per-challenge lock before the flag is checked, so parallel requests can't all
+    // pass the count
+    let limit: i64 = env::var("FLAG_SUBMIT_LIMIT_PER_MINUTE").ok().and_then(|v| v.parse().ok()).unwrap_or(5);
+    let submission_id = Uuid::new_v4().to_string();
+    match record_flag_attempt(&state.rag.pool, &user.id, &challenge_id, &submission_id, limit).await {
+        Ok(true) => {}
+        Ok(false) => return HttpResponse::TooManyRequests().json(json!({"error": "Too many submissions. Try again in a minute."})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+
+    // argon2 verification compares in constant time
+    let flag = body.flag.trim().to_string();
+    let flag_hash = challenge.flag_hash.clone();
+    let correct = web::block(move || auth::verify_password(&flag, &flag_hash)).await.unwrap_or(false);
+
+    if !correct {
+        return HttpResponse::Ok().json(json!({"correct": false, "message": "Incorrect flag"}));
+    }
+    let _ = sqlx::query("UPDATE challenge_submissions SET correct = TRUE WHERE id = $1")
+        .bind(&submission_id)
+        .execute(&state.rag.pool)
+        .await;
+
+    let hints_used: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM challenge_hint_unlocks WHERE user_id = $1 AND challenge_id = $2")
+        .bind(&user.id)
+        .bind(&challenge_id)
+        .fetch_one(&state.rag.pool)
+        .await
+        .unwrap_or((0,));
+    // Sessions without hint mode had full answers available, so they count as the top level
+    let assist_level: (Option<i32>,) = sqlx::query_as(
+        "SELECT MAX(CASE WHEN hint_mode THEN hint_level ELSE 3 END) FROM chat_sessions WHERE user_id = $1 AND challenge_id = $2"
+    )
+    .bind(&user.id)
+    .bind(&challenge_id)
+    .fetch_one(&state.rag.pool)
+    .await
+    .unwrap_or((None,));
+
+    let points = scoring::award_points(challenge.points, hints_used.0, assist_level.0);
+    let result = sqlx::query(
+        "INSERT INTO challenge_solves (id, challenge_id, user_id, points_awarded, hints_used, assist_level) VALUES ($1, $2, $3, $4, $5, $6) ON CONFLICT (challenge_id, user_id) DO NOTHING"
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(&challenge_id)
+    .bind(&user.id)
+    .bind(points)
+    .bind(hints_used.0 as i32)
+    .bind(assist_level.0)
+    .execute(&state.rag.pool)
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Ok().json(json!({
+            "correct": true,
+            "points_awarded": points,
+            "base_points": challenge.points,
+            "hints_used": hints_used.0,
+            "assist_level": assist_level.0
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+/// Records a flag attempt, initially incorrect, unless `limit` attempts were already made in the
+/// last minute. Returns false when rate limited.
+async fn record_flag_attempt(pool: &DbPool, user_id: &str, challenge_id: &str, submission_id: &str, limit: i64) -> Result<bool, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    sqlx::query("SELECT pg_advisory_xact_lock(hashtext($1 || ':' || $2))")
+        .bind(user_id)
+        .bind(challenge_id)
+        .execute(&mut *tx)
+        .await?;
+    let (recent,): (i64,) = sqlx::query_as(
+        "SELECT COUNT(*) FROM challenge_submissions WHERE user_id = $1 AND challenge_id = $2 AND submitted_at > CURRENT_TIMESTAMP - INTERVAL '1 minute'"
+    )
+    .bind(user_id)
+    .bind(challenge_id)
+    .fetch_one(&mut *tx)
+    .await?;
+    if recent >= limit {
+        return Ok(false);
+    }
+    sqlx::query("INSERT INTO challenge_submissions (id, challenge_id, user_id, correct) VALUES ($1, $2, $3, FALSE)")
+        .bind(submission_id)
+        .bind(challenge_id)
+        .bind(user_id)
+        .execute(&mut *tx)
+        .await?;
+    tx.commit().await?;
+    Ok(true)
+}
+
+#[get("/api/challenges/{id}/hints")]
+async fn list_unlocked_hints(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let hints = sqlx::query_as::<_, ChallengeHint>(
+        "SELECT h.* FROM challenge_hints h JOIN challenge_hint_unlocks u ON u.hint_id = h.id WHERE h.challenge_id = $1 AND u.user_id = $2 ORDER BY h.position ASC"
+    )
+    .bind(path.into_inner())
+    .bind(&user.id)
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match hints {
+        Ok(h) => HttpResponse::Ok().json(h),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/challenges/{id}/hints/unlock")]
+async fn unlock_next_hint(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let challenge_id = path.into_inner();
+
+    // Hints unlock strictly in order
+    let next = sqlx::query_as::<_, ChallengeHint>(
+        "SELECT h.* FROM challenge_hints h JOIN challenges c ON c.id = h.challenge_id
+         WHERE h.challenge_id = $1 AND c.is_published = TRUE
+           AND NOT EXISTS (SELECT 1 FROM challenge_hint_unlocks u WHERE u.hint_id = h.id AND u.user_id = $2)
+         ORDER BY h.position ASC LIMIT 1"
+    )
+    .bind(&challenge_id)
+    .bind(&user.id)
+    .fetch_optional(&state.rag.pool)
+    .await;
+
+    match next {
+        Ok(Some(hint)) => {
+            let result = sqlx::query("INSERT INTO challenge_hint_unlocks (user_id, hint_id, challenge_id) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING")
+                .bind(&user.id)
+                .bind(&hint.id)
+                .bind(&challenge_id)
+                .execute(&state.rag.pool)
+                .await;
+            match result {
+                Ok(_) => HttpResponse::Ok().json(hint),
+                Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+            }
+        },
+        Ok(None) => HttpResponse::BadRequest().json(json!({"error": "No more hints available"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/scoreboard")]
+async fn scoreboard(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    query: web::Query<ScoreboardQuery>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let limit = query.limit.unwrap_or(100).clamp(1, 500);
+
+    let entries = sqlx::query_as::<_, ScoreboardEntry>(
+        r#"SELECT u.id AS user_id, u.username, COALESCE(SUM(s.points_awarded), 0)::BIGINT AS score,
+            COUNT(s.id) AS solves, MAX(s.solved_at) AS last_solve
+        FROM challenge_solves s
+        JOIN users u ON u.id = s.user_id
+        JOIN challenges c ON c.id = s.challenge_id
+        WHERE $1::text IS NULL OR c.category = $1
+        GROUP BY u.id, u.username
+        ORDER BY score DESC, last_solve ASC
+        LIMIT $2"#
+    )
+    .bind(&query.category)
+    .bind(limit)
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match entries {
+        Ok(e) => HttpResponse::Ok().json(e),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+async fn user_progress(pool: &DbPool, user_id: &str) -> Result<Vec<CategoryProgress>, sqlx::Error> {
+    sqlx::query_as::<_, CategoryProgress>(
+        r#"SELECT c.category, COUNT(c.id) AS total, COUNT(s.id) AS solved,
+            COALESCE(SUM(s.points_awarded), 0)::BIGINT AS points
+        FROM challenges c
+        LEFT JOIN challenge_solves s ON s.challenge_id = c.id AND s.user_id = $1
+        WHERE c.is_published = TRUE
+        GROUP BY c.category
+        ORDER BY c.category ASC"#
+    )
+    .bind(user_id)
+    .fetch_all(pool)
+    .await
+}
+
+#[get("/api/progress")]
+async fn get_my_progress(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    match user_progress(&state.rag.pool, &user.id).await {
+        Ok(p) => HttpResponse::Ok().json(p),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/admin/users/{id}/progress")]
+async fn admin_get_user_progress(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    match user_progress(&state.rag.pool, &path.into_inner()).await {
+        Ok(p) => HttpResponse::Ok().json(p),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/admin/challenges")]
+async fn admin_list_challenges(user: AuthenticatedUser, state: web::Data<AppState>) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    let challenges = sqlx::query_as::<_, Challenge>("SELECT * FROM challenges ORDER BY created_at DESC")
+        .fetch_all(&state.rag.pool)
+        .await;
+
+    match challenges {
+        Ok(c) => HttpResponse::Ok().json(c),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/admin/challenges")]
+async fn add_challenge(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    body: web::Json<CreateChallengeRequest>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    if let Err(resp) = validate_challenge_fields(&body.category, &body.difficulty, body.points) {
+        return resp;
+    }
+    if body.flag.trim().is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "flag is required"}));
+    }
+
+    // Flags are stored as salted argon2 hashes, never in plain text
+    let flag_hash = match auth::hash_password(body.flag.trim()) {
+        Ok(h) => h,
+        Err(_) => return HttpResponse::InternalServerError().json(json!({"error": "Hashing failed"})),
+    };
+
+    let id = Uuid::new_v4().to_string();
+    let result = sqlx::query(
+        "INSERT INTO challenges (id, title, category, difficulty, description, points, flag_hash, is_published, created_by) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+    )
+    .bind(&id)
+    .bind(&body.title)
+    .bind(&body.category)
+    .bind(&body.difficulty)
+    .bind(&body.description)
+    .bind(body.points.unwrap_or(100))
+    .bind(&flag_hash)
+    .bind(body.is_published.unwrap_or(false))
+    .bind(&user.id)
+    .execute(&state.rag.pool)
+    .await;
+
+    match result {
+        Ok(_) => HttpResponse::Created().json(json!({"id": id, "message": "Challenge created"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[put("/api/admin/challenges/{id}")]
+async fn update_challenge(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<UpdateChallengeRequest>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    let id = path.into_inner();
+    let existing = sqlx::query_as::<_, Challenge>("SELECT * FROM challenges WHERE id = $1")
+        .bind(&id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    match existing {
+        Ok(Some(mut challenge)) => {
+            if let Some(title) = &body.title { challenge.title = title.clone(); }
+            if let Some(category) = &body.category { challenge.category = category.clone(); }
+            if let Some(difficulty) = &body.difficulty { challenge.difficulty = difficulty.clone(); }
+            if let Some(description) = &body.description { challenge.description = description.clone(); }
+            if let Some(points) = body.points { challenge.points = points; }
+            if let Some(published) = body.is_published { challenge.is_published = published; }
+            if let Err(resp) = validate_challenge_fields(&challenge.category, &challenge.difficulty, Some(challenge.points)) {
+                return resp;
+            }
+            if let Some(flag) = &body.flag {
+                if flag.trim().is_empty() {
+                    return HttpResponse::BadRequest().json(json!({"error": "flag cannot be empty"}));
+                }
+                challenge.flag_hash = match auth::hash_password(flag.trim()) {
+                    Ok(h) => h,
+                    Err(_) => return HttpResponse::InternalServerError().json(json!({"error": "Hashing failed"})),
+                };
+            }
+
+            let update_result = sqlx::query(
+                "UPDATE challenges SET title = $1, category = $2, difficulty = $3, description = $4, points = $5, flag_hash = $6, is_published = $7, updated_at = CURRENT_TIMESTAMP WHERE id = $8"
+            )
+            .bind(challenge.title)
+            .bind(challenge.category)
+            .bind(challenge.difficulty)
+            .bind(challenge.description)
+            .bind(challenge.points)
+            .bind(challenge.flag_hash)
+            .bind(challenge.is_published)
+            .bind(id)
+            .execute(&state.rag.pool)
+            .await;
+
+            match update_result {
+                Ok(_) => HttpResponse::Ok().json(json!({"message": "Challenge updated"})),
+                Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+            }
+        },
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "Challenge not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[delete("/api/admin/challenges/{id}")]
+async fn delete_challenge(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+
+    let id = path.into_inner();
+    let result = sqlx::query("DELETE FROM challenges WHERE id = $1")
+        .bind(&id)
+        .execute(&state.rag.pool)
+        .await;
+
+    match result {
+        Ok(_) => {
+            let dir = upload_dir().join("challenges").join(&id);
+            let _ = web::block(move || std::fs::remove_dir_all(dir)).await;
+            HttpResponse::Ok().json(json!({"message": "Challenge deleted"}))
+        },
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[put("/api/admin/challenges/{id}/hints")]
+async fn set_challenge_hints(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<ChallengeHintsRequest>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let challenge_id = path.into_inner();
+
+    let mut tx = match state.rag.pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let exists = sqlx::query("SELECT 1 FROM challenges WHERE id = $1")
+        .bind(&challenge_id)
+        .fetch_optional(&mut *tx)
+        .await;
+    if !matches!(exists, Ok(Some(_))) {
+        return HttpResponse::NotFound().json(json!({"error": "Challenge not found"}));
+    }
+
+    // Hints are updated in place by position so players keep their unlocks (and penalties);
+    // only hints past the new end are deleted, taking their unlocks with them
+    for (position, content) in body.hints.iter().enumerate() {
+        if let Err(e) = sqlx::query(
+            "INSERT INTO challenge_hints (id, challenge_id, position, content) VALUES ($1, $2, $3, $4)
+             ON CONFLICT (challenge_id, position) DO UPDATE SET content = EXCLUDED.content"
+        )
+            .bind(Uuid::new_v4().to_string())
+            .bind(&challenge_id)
+            .bind(position as i32)
+            .bind(content)
+            .execute(&mut *tx)
+            .await
+        {
+            return HttpResponse::InternalServerError().json(json!({"error": e.to_string()}));
+        }
+    }
+
+    if let Err(e) = sqlx::query("DELETE FROM challenge_hints WHERE challenge_id = $1 AND position >= $2")
+        .bind(&challenge_id)
+        .bind(body.hints.len() as i32)
+        .execute(&mut *tx)
+        .await
+    {
+        return HttpResponse::InternalServerError().json(json!({"error": e.to_string()}));
+    }
+
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Hints updated", "count": body.hints.len()})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[put("/api/admin/challenges/{id}/documents")]
+async fn link_challenge_documents(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<LinkDocumentsRequest>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let challenge_id = path.into_inner();
+
+    let mut tx = match state.rag.pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    // Replace the linked set: unlink everything, then link the requested documents
+    let unlink = sqlx::query("UPDATE documents SET challenge_id = NULL WHERE challenge_id = $1")
+        .bind(&challenge_id)
+        .execute(&mut *tx)
+        .await;
+    let link = sqlx::query("UPDATE documents SET challenge_id = $1 WHERE id = ANY($2)")
+        .bind(&challenge_id)
+        .bind(&body.document_ids)
+        .execute(&mut *tx)
+        .await;
+
+    match (unlink, link) {
+        (Ok(_), Ok(r)) => match tx.commit().await {
+            Ok(_) => {
+                state.rag.bump_kb_version().await;
+                HttpResponse::Ok().json(json!({"message": "Documents linked", "linked": r.rows_affected()}))
+            },
+            Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        },
+        (Err(e), _) | (_, Err(e)) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/admin/challenges/{id}/attachments")]
+async fn upload_challenge_attachment(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    mut payload: Multipart,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let challenge_id = path.into_inner();
+
+    let exists = sqlx::query("SELECT 1 FROM challenges WHERE id = $1")
+        .bind(&challenge_id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+    if !matches!(exists, Ok(Some(_))) {
+        return HttpResponse::NotFound().json(json!({"error": "Challenge not found"}));
+    }
+
+    let dir = upload_dir().join("challenges").join(&challenge_id);
+    let dir_clone = dir.clone();
+    if !matches!(web::block(move || std::fs::create_dir_all(dir_clone)).await, Ok(Ok(()))) {
+        return HttpResponse::InternalServerError().json(json!({"error": "Failed to create upload directory"}));
+    }
+
+    let mut uploaded = Vec::new();
+    while let Ok(Some(mut field)) = payload.try_next().await {
+        let filename = field.content_disposition().get_filename().unwrap_or("attachment").to_string();
+        let id = Uuid::new_v4().to_string();
+        let storage_path = dir.join(&id);
+
+        let path_clone = storage_path.clone();
+        let mut f = match web::block(move || std::fs::File::create(path_clone)).await {
+            Ok(Ok(f)) => f,
+            _ => return HttpResponse::InternalServerError().json(json!({"error": "Failed to create file"})),
+        };
+
+        let mut size: i64 = 0;
+        while let Some(chunk) = field.next().await {
+            let data = match chunk {
+                Ok(d) => d,
+                Err(_) => return HttpResponse::BadRequest().json(json!({"error": "Upload interrupted"})),
+            };
+            size += data.len() as i64;
+            let res = web::block(move || f.write_all(&data).map(|_| f)).await;
+            match res {
+                Ok(Ok(file)) => f = file,
+                _ => return HttpResponse::InternalServerError().json(json!({"error": "Failed to write to file"})),
+            }
+        }
+
+        let result = sqlx::query("INSERT INTO challenge_attachments (id, challenge_id, filename, storage_path, size_bytes) VALUES ($1, $2, $3, $4, $5)")
+            .bind(&id)
+            .bind(&challenge_id)
+            .bind(&filename)
+            .bind(storage_path.to_string_lossy().to_string())
+            .bind(size)
+            .execute(&state.rag.pool)
+            .await;
+        if let Err(e) = result {
+            return HttpResponse::InternalServerError().json(json!({"error": e.to_string()}));
+        }
+        uploaded.push(json!({"id": id, "filename": filename, "size_bytes": size}));
+    }
+
+    if uploaded.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "No file found"}));
+    }
+    HttpResponse::Created().json(json!({"attachments": uploaded}))
+}
+
+#[delete("/api/admin/challenges/{id}/attachments/{attachment_id}")]
+async fn delete_challenge_attachment(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<(String, String)>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" && user.role != "editor" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin or Editor access required"}));
+    }
+    let (challenge_id, attachment_id) = path.into_inner();
+
+    let attachment = sqlx::query_as::<_, ChallengeAttachment>("DELETE FROM challenge_attachments WHERE id = $1 AND challenge_id = $2 RETURNING *")
+        .bind(&attachment_id)
+        .bind(&challenge_id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    match attachment {
+        Ok(Some(a)) => {
+            let _ = web::block(move || std::fs::remove_file(a.storage_path)).await;
+            HttpResponse::Ok().json(json!({"message": "Attachment deleted"}))
+        },
+        Ok(None) => HttpResponse::NotFound().json(json!({"error": "Attachment not found"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Cache Endpoints
+
+#[get("/api/admin/cache/stats")]
+async fn admin_cache_stats(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+    HttpResponse::Ok().json(response_cache::stats(&state.rag).await)
+}
+
+#[get("/api/admin/embedder/stats")]
+async fn admin_embedder_stats(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+    HttpResponse::Ok().json(state.rag.embedder.stats())
+}
+
+/// Active and configured embedding models, the models available, the reranker, and the latest re-index job.
+#[get("/api/admin/embeddings")]
+async fn admin_embedding_status(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+    let active = state.rag.embedder.model();
+    let latest = sqlx::query_as::<_, ReindexJob>("SELECT * FROM reindex_jobs ORDER BY created_at DESC LIMIT 1")
+        .fetch_optional(&state.rag.pool)
+        .await;
+
+    match latest {
+        Ok(job) => HttpResponse::Ok().json(json!({
+            "active": {"model": active.name, "dim": active.dim},
+            "configured": ModelSpec::from_env().map(|m| m.name).unwrap_or_else(|e| e),
+            "models": embedder::supported_models(),
+            "reranker": state.rag.reranker.as_ref().map(|r| r.name.clone()),
+            "job": job,
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+/// Starts re-embedding every document with another model. Search keeps using the active
+/// model until the job cuts over.
+#[post("/api/admin/embeddings/reindex")]
+async fn admin_start_reindex(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    body: web::Json<StartReindexRequest>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+    let target = match body.model.as_deref() {
+        Some(name) => ModelSpec::resolve(name),
+        None => ModelSpec::from_env(),
+    };
+    let target = match target {
+        Ok(t) => t,
+        Err(e) => return HttpResponse::BadRequest().json(json!({"error": e})),
+    };
+
+    match reindex::create_job(&state.rag, &target, Some(&user.id)).await {
+        Ok(job_id) => {
+            tokio::spawn(reindex::run_job(state.rag.clone(), job_id.clone()));
+            HttpResponse::Ok().json(json!({"message": format!("Re-indexing with {} started", target.name), "job_id": job_id}))
+        }
+        Err(e) => HttpResponse::Conflict().json(json!({"error": e})),
+    }
+}
+
+#[post("/api/admin/embeddings/reindex/{id}/cancel")]
+async fn admin_cancel_reindex(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    let user = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+    // The job stops before its next batch; a cutover already under way completes
+    let result = sqlx::query("UPDATE reindex_jobs SET cancel_requested = TRUE, updated_at = CURRENT_TIMESTAMP WHERE id = $1 AND status IN ('queued', 'running')")
+        .bind(path.into_inner())
+        .execute(&state.rag.pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() > 0 => HttpResponse::Ok().json(json!({"message": "Cancellation requested"})),
+        Ok(_) => HttpResponse::Conflict().json(json!({"error": "Job is not queued or running"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[post("/api/admin/cache/flush")]
+async fn admin_flush_caches(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+    match state.rag.flush_caches().await {
+        Ok((kb_version, removed)) => HttpResponse::Ok().json(json!({
+            "message": "Caches flushed",
+            "kb_version": kb_version,
+            "search_keys_removed": removed
+        })),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Code Execution Endpoints
+
+async fn code_execution_allowed(pool: &DbPool, user: &User) -> bool {
+    plan_allows(&settings::get(pool, "code_execution_policy").await, user)
+}
+
+/// Runs a snippet in the sandbox and records it in the `code_executions` audit log.
+async fn run_and_audit(
+    pool: &DbPool,
+    user_id: &str,
+    session_id: Option<&str>,
+    message_id: Option<&str>,
+    trigger: &str,
+    language: Language,
+    code: &str,
+) -> Result<sandbox::ExecutionResult, String> {
+    let result = sandbox::run(language, code, &sandbox::Limits::from_env()).await;
+    let (stdout, stderr, exit_code, timed_out, duration_ms) = match &result {
+        Ok(r) => (r.stdout.as_str(), r.stderr.as_str(), r.exit_code, r.timed_out, r.duration_ms),
+        Err(e) => ("", e.as_str(), None, false, 0),
+    };
+    println!("Sandbox run by {} ({}, {}): exit {:?}, timed out {}", user_id, trigger, language.name(), exit_code, timed_out);
+
+    let _ = sqlx::query(
+        "INSERT INTO code_executions (id, user_id, session_id, message_id, trigger, language, code, stdout, stderr, exit_code, timed_out, duration_ms) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9, $10, $11, $12)",
+    )
+    .bind(Uuid::new_v4().to_string())
+    .bind(user_id)
+    .bind(session_id)
+    .bind(message_id)
+    .bind(trigger)
+    .bind(language.name())
+    .bind(code)
+    .bind(stdout)
+    .bind(stderr)
+    .bind(exit_code)
+    .bind(timed_out)
+    .bind(duration_ms)
+    .execute(pool)
+    .await;
+
+    result
+}
+
+#[post("/api/chat/history/{id}/execute")]
+async fn execute_session_code(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<ExecuteCodeRequest>,
+) -> impl Responder {
+    let u = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if !code_execution_allowed(&state.rag.pool, &u).await {
+        return HttpResponse::Forbidden().json(json!({"error": "Code execution is not available on your plan"}));
+    }
+    let session_id = path.into_inner();
+
+    let owned = sqlx::query_as::<_, (bool, i32)>("SELECT hint_mode, hint_level FROM chat_sessions WHERE id = $1 AND user_id = $2")
+        .bind(&session_id)
+        .bind(&user.id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+    let (hint_mode, hint_level) = match owned {
+        Ok(Some((mode, level))) => (mode, HintLevel::from_i32(level)),
+        Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Session not found"})),
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
+
+    let (language, code) = if let Some(message_id) = &body.message_id {
+        let message = sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_messages WHERE id = $1 AND session_id = $2 AND role = 'assistant'")
+            .bind(message_id)
+            .bind(&session_id)
+            .fetch_optional(&state.rag.pool)
+            .await;
+        let message = match message {
+            Ok(Some(m)) => m,
+            Ok(None) => return HttpResponse::NotFound().json(json!({"error": "Message not found"})),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        };
+        match sandbox::extract_code_blocks(&message.content).into_iter().nth(body.block.unwrap_or(0)) {
+            Some(block) => block,
+            None => return HttpResponse::BadRequest().json(json!({"error": "Message has no runnable python or shell block at that index"})),
         }
     } else {
-        // Create new session
-        let new_sid = Uuid::new_v4().to_string();
-        // Use first few words of message as title (truncated)
-        let title: String = body.message.chars().take(30).collect();
-        let _ = sqlx::query("INSERT INTO chat_sessions (id, user_id, title) VALUES ($1, $2, $3)")
-            .bind(&new_sid)
-            .bind(&user.id)
-            .bind(&title)
-            .execute(&state.rag.pool)
-            .await;
-        new_sid
+        let language = match body.language.as_deref().and_then(Language::parse) {
+            Some(l) => l,
+            None => return HttpResponse::BadRequest().json(json!({"error": "language must be 'python' or 'shell'"})),
+        };
+        match &body.code {
+            Some(code) if !code.trim().is_empty() => (language, code.clone()),
+            _ => return HttpResponse::BadRequest().json(json!({"error": "code or message_id is required"})),
+        }
     };
 
-    // 3. Save User Message
-    let user_msg_id = Uuid::new_v4().to_string();
-    let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'user', $3)")
-        .bind(&user_msg_id)
+    let mut result = match run_and_audit(&state.rag.pool, &user.id, Some(&session_id), body.message_id.as_deref(), "user", language, &code).await {
+        Ok(r) => r,
+        Err(e) => return HttpResponse::ServiceUnavailable().json(json!({"error": e})),
+    };
+    // Running the model's code must not reveal a flag the hint level still withholds
+    let mut code = code;
+    if let Some(patterns) = flag_redaction(&state.rag.pool, hint_mode, hint_level).await {
+        result.stdout = hints::redact_flags(&result.stdout, &patterns).0;
+        result.stderr = hints::redact_flags(&result.stderr, &patterns).0;
+        code = hints::redact_flags(&code, &patterns).0;
+    }
+
+    // Attach the output to the transcript the same way model tool calls are recorded
+    let record = json!({
+        "name": sandbox::TOOL_NAME,
+        "arguments": json!({"language": language.name(), "code": code}).to_string(),
+        "result": sandbox::format_result(&result),
+        "ok": result.exit_code == Some(0)
+    });
+    let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'tool', $3)")
+        .bind(Uuid::new_v4().to_string())
         .bind(&session_id)
-        .bind(&body.message)
+        .bind(record.to_string())
         .execute(&state.rag.pool)
         .await;
 
-    // 4. Load History from DB (to ensure context is correct)
-    let db_history = sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_messages WHERE session_id = $1 ORDER BY created_at ASC")
+    HttpResponse::Ok().json(result)
+}
+
+#[get("/api/chat/history/{id}/executions")]
+async fn list_session_executions(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let session_id = path.into_inner();
+    let (hint_mode, hint_level) = sqlx::query_as::<_, (bool, i32)>("SELECT hint_mode, hint_level FROM chat_sessions WHERE id = $1 AND user_id = $2")
         .bind(&session_id)
-        .fetch_all(&state.rag.pool)
+        .bind(&user.id)
+        .fetch_optional(&state.rag.pool)
         .await
-        .unwrap_or_default();
+        .unwrap_or(None)
+        .unwrap_or((false, 0));
+    let executions = sqlx::query_as::<_, CodeExecution>("SELECT * FROM code_executions WHERE session_id = $1 AND user_id = $2 ORDER BY created_at ASC")
+        .bind(&session_id)
+        .bind(&user.id)
+        .fetch_all(&state.rag.pool)
+        .await;
 
-    // 5. Prepare OpenRouter Request
-    let api_key = std::env::var("OPENROUTER_API_KEY").unwrap_or_default();
-    if api_key.is_empty() {
-        return HttpResponse::InternalServerError().json(json!({"error": "OpenRouter API Key not set"}));
+    match executions {
+        Ok(mut executions) => {
+            // The audit log keeps raw output; players below the Solution level get it redacted
+            if let Some(patterns) = flag_redaction(&state.rag.pool, hint_mode, HintLevel::from_i32(hint_level)).await {
+                for e in executions.iter_mut() {
+                    e.code = hints::redact_flags(&e.code, &patterns).0;
+                    e.stdout = hints::redact_flags(&e.stdout, &patterns).0;
+                    e.stderr = hints::redact_flags(&e.stderr, &patterns).0;
+                }
+            }
+            HttpResponse::Ok().json(executions)
+        }
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/admin/executions")]
+async fn admin_list_executions(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    query: web::Query<ListExecutionsQuery>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
     }
+    let executions = sqlx::query_as::<_, CodeExecution>(
+        "SELECT * FROM code_executions WHERE ($1::text IS NULL OR user_id = $1) ORDER BY created_at DESC LIMIT $2",
+    )
+    .bind(&query.user_id)
+    .bind(query.limit.unwrap_or(100).clamp(1, 500))
+    .fetch_all(&state.rag.pool)
+    .await;
 
-    let model_name = body.model.clone().unwrap_or_else(|| "deepseek/deepseek-r1-0528:free".to_string());
+    match executions {
+        Ok(e) => HttpResponse::Ok().json(e),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
 
-    // Fetch model configuration from DB to get custom system prompt
-    let model_config = sqlx::query_as::<_, AIModel>("SELECT * FROM ai_models WHERE api_model_name = $1")
-        .bind(&model_name)
-        .fetch_optional(&state.rag.pool)
-        .await
-        .unwrap_or(None);
+// Session Sharing Endpoints
 
-    let system_prompt = if let Some(Some(custom_prompt)) = model_config.map(|m| m.system_prompt) {
-        // Inject context into custom prompt
-        if custom_prompt.contains("{}") {
-            custom_prompt.replace("{}", &context)
-        } else {
-            format!("{}\n\n## KNOWLEDGE BASE CONTEXT\nUse the following retrieved context to augment your answers:\n[CONTEXT BEGIN]\n{}\n[CONTEXT END]", custom_prompt, context)
+/// Evaluates an off | subscribers | everyone policy setting. `off` disables the feature for
+/// everyone, admins included; otherwise admins are always allowed.
+fn plan_allows(policy: &str, user: &User) -> bool {
+    match policy {
+        "everyone" => true,
+        "subscribers" => user.role == "admin" || user.subscription_end.map(|end| end > Utc::now().naive_utc()).unwrap_or(false),
+        _ => false,
+    }
+}
+
+async fn sharing_allowed(pool: &DbPool, user: &User) -> bool {
+    plan_allows(&settings::get(pool, "sharing_policy").await, user)
+}
+
+/// Resolves a share token to its session and a sanitized transcript (no ids, sources or system messages).
+async fn load_shared_transcript(pool: &DbPool, token: &str) -> Result<SharedTranscript, HttpResponse> {
+    // Links stop resolving exactly when nobody may create them
+    if !matches!(settings::get(pool, "sharing_policy").await.as_str(), "subscribers" | "everyone") {
+        return Err(HttpResponse::NotFound().json(json!({"error": "Shared session not found"})));
+    }
+
+    let share = sqlx::query_as::<_, SessionShare>("SELECT * FROM session_shares WHERE token = $1 AND revoked = FALSE")
+        .bind(token)
+        .fetch_optional(pool)
+        .await;
+
+    let share = match share {
+        Ok(Some(s)) => s,
+        Ok(None) => return Err(HttpResponse::NotFound().json(json!({"error": "Shared session not found"}))),
+        Err(_) => return Err(HttpResponse::InternalServerError().json(json!({"error": "Database error"}))),
+    };
+    if let Some(expires_at) = share.expires_at {
+        if expires_at < Utc::now().naive_utc() {
+            return Err(HttpResponse::Gone().json(json!({"error": "Share link expired"})));
         }
-    } else {
-        // Default Hardcoded Prompt
-        format!(
-        "You are Krypton, an elite Cybersecurity Mentor and CTF Specialist designed to guide users through complex security challenges and ethical hacking scenarios.
+    }
 
-        ## CORE OBJECTIVES
-        1. **Solve CTF Challenges**: Provide step-by-step reasoning for Web, Crypto, Pwn, Reverse Engineering, Forensics, and OSINT challenges.
-        2. **Educational Deep Dives**: Explain *why* a vulnerability exists, not just how to exploit it.
-        3. **Ethical Compliance**: STRICTLY refuse requests for illegal acts (black hat hacking, unauthorized access). Pivot immediately to defense and educational concepts.
+    let session = sqlx::query_as::<_, ChatSession>("SELECT * FROM chat_sessions WHERE id = $1")
+        .bind(&share.session_id)
+        .fetch_optional(pool)
+        .await;
+    let session = match session {
+        Ok(Some(s)) => s,
+        Ok(None) => return Err(HttpResponse::NotFound().json(json!({"error": "Shared session not found"}))),
+        Err(_) => return Err(HttpResponse::InternalServerError().json(json!({"error": "Database error"}))),
+    };
 
-        ## CTF METHODOLOGY GUIDELINES
-        - **Web**: Analyze headers, source code, cookies, and input fields. Look for SQLi, XSS, IDOR, SSRF. Suggest tools like Burp Suite, OWASP ZAP.
-        - **Cryptography**: Identify encoding (Base64, Hex), hashing (MD5, SHA), or encryption (RSA, AES). Look for key leakage or weak math.
-        - **Forensics**: Analyze file headers (magic bytes), metadata (exiftool), and hidden streams. Suggest Wireshark for pcap analysis.
-        - **Reverse Engineering**: Discuss static analysis (Ghidra/IDA) and dynamic analysis (GDB/x64dbg). Look for buffer overflows and logic gates.
-        - **Pwn**: Check for binary protections (NX, ASLR, Canary). Explain ROP chains and shellcode injection.
+    let messages = sqlx::query_as::<_, Message>(
+        "SELECT role, content FROM chat_messages WHERE session_id = $1 AND role IN ('user', 'assistant') ORDER BY created_at ASC"
+    )
+    .bind(&session.id)
+    .fetch_all(pool)
+    .await
+    .unwrap_or_default();
 
-        ## KNOWLEDGE BASE CONTEXT
-        Use the following retrieved context to augment your answers. If the context contains the specific flag or solution, use it to guide the user without just giving the answer immediately unless asked.
-        
-        [CONTEXT BEGIN]
-        {}
-        [CONTEXT END]
+    Ok(SharedTranscript {
+        title: session.title,
+        model: session.model,
+        created_at: session.created_at,
+        messages,
+    })
+}
+
+#[post("/api/chat/history/{id}/share")]
+async fn create_session_share(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<CreateShareRequest>,
+) -> impl Responder {
+    let u = match require_verified(&user, &state.rag.pool).await {
+        Ok(u) => u,
+        Err(resp) => return resp,
+    };
+    if !sharing_allowed(&state.rag.pool, &u).await {
+        return HttpResponse::Forbidden().json(json!({"error": "Session sharing is not enabled for your account"}));
+    }
+    let session_id = path.into_inner();
+
+    let session_check = sqlx::query("SELECT 1 FROM chat_sessions WHERE id = $1 AND user_id = $2")
+        .bind(&session_id)
+        .bind(&user.id)
+        .fetch_optional(&state.rag.pool)
+        .await;
+    if !matches!(session_check, Ok(Some(_))) {
+        return HttpResponse::NotFound().json(json!({"error": "Session not found or access denied"}));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+    let expires_at = body.expires_in_hours
+        .filter(|h| *h > 0)
+        .map(|h| Utc::now().naive_utc() + Duration::hours(h));
+
+    let result = sqlx::query("INSERT INTO session_shares (id, session_id, token, created_by, expires_at) VALUES ($1, $2, $3, $4, $5)")
+        .bind(&id)
+        .bind(&session_id)
+        .bind(&token)
+        .bind(&user.id)
+        .bind(expires_at)
+        .execute(&state.rag.pool)
+        .await;
+
+    match result {
+        Ok(_) => {
+            let frontend_base = env::var("FRONTEND_BASE_URL").unwrap_or_else(|_| "http://localhost:3000".to_string());
+            let share_link = format!("{}/shared/{}", frontend_base.trim_end_matches('/'), token);
+            HttpResponse::Created().json(json!({"id": id, "token": token, "url": share_link, "expires_at": expires_at}))
+        },
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[get("/api/chat/history/{id}/shares")]
+async fn list_session_shares(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let shares = sqlx::query_as::<_, SessionShare>(
+        "SELECT sh.* FROM session_shares sh JOIN chat_sessions s ON s.id = sh.session_id WHERE sh.session_id = $1 AND s.user_id = $2 ORDER BY sh.created_at DESC"
+    )
+    .bind(path.into_inner())
+    .bind(&user.id)
+    .fetch_all(&state.rag.pool)
+    .await;
+
+    match shares {
+        Ok(s) => HttpResponse::Ok().json(s),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+#[delete("/api/chat/shares/{id}")]
+async fn revoke_session_share(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let result = sqlx::query(
+        "UPDATE session_shares SET revoked = TRUE WHERE id = $1 AND session_id IN (SELECT id FROM chat_sessions WHERE user_id = $2)"
+    )
+    .bind(path.into_inner())
+    .bind(&user.id)
+    .execute(&state.rag.pool)
+    .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => HttpResponse::NotFound().json(json!({"error": "Share not found"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Share link revoked"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
 
-        ## RESPONSE FORMAT
-        - **Analysis**: Start with a high-level analysis of the problem.
-        - **Tools**: List recommended tools (e.g., `nmap`, `sqlmap`, `python`).
-        - **Steps**: Numbered, actionable steps to solve the challenge.
-        - **Code**: Provide Python scripts or shell commands in code blocks.
-        - **Remediation**: (If applicable) How to patch the vulnerability.
+#[get("/api/shared/{token}")]
+async fn get_shared_session(
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    match load_shared_transcript(&state.rag.pool, &path.into_inner()).await {
+        Ok(transcript) => HttpResponse::Ok().json(transcript),
+        Err(resp) => resp,
+    }
+}
 
-        ## TONE & STYLE
-        - Be technical but clear.
-        - Use markdown extensively (bolding key terms, code blocks).
-        - If the user provides a flag format (e.g., `CTF{{...}}`), help them find the string matching that pattern.
-        - If context is missing, rely on your extensive general cybersecurity knowledge.",
-        context
-        )
+#[post("/api/shared/{token}/fork")]
+async fn fork_shared_session(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    let transcript = match load_shared_transcript(&state.rag.pool, &path.into_inner()).await {
+        Ok(t) => t,
+        Err(resp) => return resp,
     };
 
-    let mut messages = vec![
-        json!({"role": "system", "content": system_prompt})
-    ];
+    let mut tx = match state.rag.pool.begin().await {
+        Ok(tx) => tx,
+        Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    };
 
-    for msg in db_history {
-        messages.push(json!({"role": msg.role, "content": msg.content}));
+    let session_id = Uuid::new_v4().to_string();
+    let title = format!("Fork: {}", transcript.title.as_deref().unwrap_or("Shared session"));
+    if let Err(e) = sqlx::query("INSERT INTO chat_sessions (id, user_id, title, model) VALUES ($1, $2, $3, $4)")
+        .bind(&session_id)
+        .bind(&user.id)
+        .bind(&title)
+        .bind(&transcript.model)
+        .execute(&mut *tx)
+        .await
+    {
+        return HttpResponse::InternalServerError().json(json!({"error": e.to_string()}));
     }
-    // No need to push body.message again as it's in db_history
 
-    let request_body = json!({
-        "model": model_name,
-        "messages": messages,
-        "max_tokens": 80000
-    });
-    
-    println!("Sending request to OpenRouter: Model: {}", model);
+    let base_time = Utc::now().naive_utc();
+    for (i, msg) in transcript.messages.iter().enumerate() {
+        if let Err(e) = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content, created_at) VALUES ($1, $2, $3, $4, $5)")
+            .bind(Uuid::new_v4().to_string())
+            .bind(&session_id)
+            .bind(&msg.role)
+            .bind(&msg.content)
+            .bind(base_time + Duration::milliseconds(i as i64))
+            .execute(&mut *tx)
+            .await
+        {
+            return HttpResponse::InternalServerError().json(json!({"error": e.to_string()}));
+        }
+    }
 
-    let res = state.client
-        .post("https://openrouter.ai/api/v1/chat/completions")
-        .header("Authorization", format!("Bearer {}", api_key))
-        .header("Content-Type", "application/json")
-        .header("HTTP-Referer", "https://github.com/Krypton-OSS/KryptonSecAI") // Updated to generic URL for cross-device compatibility
-        .header("X-Title", "KryptonSecAI")
-        .json(&request_body)
-        .send()
-        .await;
+    match tx.commit().await {
+        Ok(_) => HttpResponse::Created().json(json!({"session_id": session_id})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
 
-    match res {
-        Ok(response) => {
-            if response.status().is_success() {
-                let json_resp: serde_json::Value = response.json().await.unwrap_or(json!({}));
-                let content = json_resp["choices"][0]["message"]["content"]
-                    .as_str()
-                    .unwrap_or("No response from AI")
-                    .to_string();
-                
-                // Cache response in Redis with TTL (1 hour)
-                if let Some(client) = &state.rag.redis {
-                    if let Ok(mut con) = client.get_multiplexed_async_connection().await {
-                        let _: () = con.set_ex(&cache_key, &content, 3600).await.unwrap_or(());
-                    }
-                }
+// Admin Settings Endpoints
 
-                // Save AI response
-                let ai_msg_id = Uuid::new_v4().to_string();
-                let _ = sqlx::query("INSERT INTO chat_messages (id, session_id, role, content) VALUES ($1, $2, 'assistant', $3)")
-                    .bind(&ai_msg_id)
-                    .bind(&session_id)
-                    .bind(&content)
-                    .execute(&state.rag.pool)
-                    .await;
-                
-                // Update session updated_at
-                let _ = sqlx::query("UPDATE chat_sessions SET updated_at = CURRENT_TIMESTAMP WHERE id = $1")
-                    .bind(&session_id)
-                    .execute(&state.rag.pool)
-                    .await;
-                
-                HttpResponse::Ok().json(ChatResponse { response: content, session_id })
-            } else {
-                let status = response.status();
-                let error_text = response.text().await.unwrap_or_default();
-                println!("OpenRouter Error: Status: {}, Body: {}", status, error_text);
-                HttpResponse::BadRequest().json(json!({"error": "Provider Error", "details": error_text}))
-            }
-        }
-        Err(e) => {
-            println!("OpenRouter Request Failed: {}", e);
-            HttpResponse::InternalServerError().json(json!({"error": e.to_string()}))
-        },
+#[get("/api/admin/settings")]
+async fn list_settings(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
     }
+    let values: serde_json::Map<String, serde_json::Value> = settings::all(&state.rag.pool)
+        .await
+        .into_iter()
+        .map(|(k, v)| (k, json!(v)))
+        .collect();
+    HttpResponse::Ok().json(values)
 }
 
-// Chat History Endpoints
+#[put("/api/admin/settings/{key}")]
+async fn update_setting(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+    body: web::Json<serde_json::Value>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    if user.role != "admin" {
+        return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
+    }
+    let key = path.into_inner();
+    if settings::default_for(&key).is_none() {
+        return HttpResponse::BadRequest().json(json!({"error": "Unknown setting"}));
+    }
+    let value = match body.get("value").and_then(|v| v.as_str()) {
+        Some(v) => v.to_string(),
+        None => return HttpResponse::BadRequest().json(json!({"error": "value is required"})),
+    };
+    if let Err(e) = settings::validate(&key, &value) {
+        return HttpResponse::BadRequest().json(json!({"error": e}));
+    }
 
-#[get("/api/chat/history")]
-async fn list_chat_sessions(
+    match settings::set(&state.rag.pool, &key, &value).await {
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Setting updated", "key": key, "value": value})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
+
+// Chat Folder Endpoints
+
+#[get("/api/chat/folders")]
+async fn list_chat_folders(
     user: AuthenticatedUser,
     state: web::Data<AppState>,
 ) -> impl Responder {
     if let Err(resp) = require_verified(&user, &state.rag.pool).await {
         return resp;
     }
-    let sessions = sqlx::query_as::<_, ChatSession>("SELECT * FROM chat_sessions WHERE user_id = $1 ORDER BY updated_at DESC")
+    let folders = sqlx::query_as::<_, ChatFolder>("SELECT * FROM chat_folders WHERE user_id = $1 ORDER BY name ASC")
         .bind(&user.id)
         .fetch_all(&state.rag.pool)
         .await;
-    
-    match sessions {
-        Ok(s) => HttpResponse::Ok().json(s),
+
+    match folders {
+        Ok(f) => HttpResponse::Ok().json(f),
         Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
     }
 }
 
-#[get("/api/chat/history/{id}")]
-async fn get_chat_session(
+#[post("/api/chat/folders")]
+async fn create_chat_folder(
     user: AuthenticatedUser,
     state: web::Data<AppState>,
-    path: web::Path<String>,
+    body: web::Json<ChatFolderRequest>,
 ) -> impl Responder {
     if let Err(resp) = require_verified(&user, &state.rag.pool).await {
         return resp;
     }
-    let session_id = path.into_inner();
-    
-    // Check ownership
-    let session_check = sqlx::query("SELECT 1 FROM chat_sessions WHERE id = $1 AND user_id = $2")
-        .bind(&session_id)
+    let name = body.name.trim();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "Folder name is required"}));
+    }
+
+    let id = Uuid::new_v4().to_string();
+    let result = sqlx::query("INSERT INTO chat_folders (id, user_id, name) VALUES ($1, $2, $3)")
+        .bind(&id)
         .bind(&user.id)
-        .fetch_optional(&state.rag.pool)
+        .bind(name)
+        .execute(&state.rag.pool)
         .await;
 
-    if let Ok(Some(_)) = session_check {
-        let messages = sqlx::query_as::<_, ChatMessage>("SELECT * FROM chat_messages WHERE session_id = $1 ORDER BY created_at ASC")
-            .bind(&session_id)
-            .fetch_all(&state.rag.pool)
-            .await;
-        
-        match messages {
-            Ok(m) => HttpResponse::Ok().json(m),
-            Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
-        }
-    } else {
-        HttpResponse::NotFound().json(json!({"error": "Session not found or access denied"}))
+    match result {
+        Ok(_) => HttpResponse::Created().json(json!({"id": id, "name": name})),
+        Err(_) => HttpResponse::BadRequest().json(json!({"error": "Folder already exists"})),
     }
 }
 
-#[delete("/api/chat/history/{id}")]
-async fn delete_chat_session(
+#[put("/api/chat/folders/{id}")]
+async fn rename_chat_folder(
     user: AuthenticatedUser,
     state: web::Data<AppState>,
     path: web::Path<String>,
+    body: web::Json<ChatFolderRequest>,
 ) -> impl Responder {
     if let Err(resp) = require_verified(&user, &state.rag.pool).await {
         return resp;
     }
-    let session_id = path.into_inner();
-    
-    // Check ownership
-    let session_check = sqlx::query("SELECT 1 FROM chat_sessions WHERE id = $1 AND user_id = $2")
-        .bind(&session_id)
+    let name = body.name.trim();
+    if name.is_empty() {
+        return HttpResponse::BadRequest().json(json!({"error": "Folder name is required"}));
+    }
+
+    let result = sqlx::query("UPDATE chat_folders SET name = $1 WHERE id = $2 AND user_id = $3")
+        .bind(name)
+        .bind(path.into_inner())
         .bind(&user.id)
-        .fetch_optional(&state.rag.pool)
+        .execute(&state.rag.pool)
         .await;
 
-    if let Ok(Some(_)) = session_check {
-        // Delete session (messages cascade delete)
-        let result = sqlx::query("DELETE FROM chat_sessions WHERE id = $1")
-            .bind(&session_id)
-            .execute(&state.rag.pool)
-            .await;
-        
-        match result {
-            Ok(_) => HttpResponse::Ok().json(json!({"message": "Session deleted"})),
-            Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
-        }
-    } else {
-        HttpResponse::NotFound().json(json!({"error": "Session not found or access denied"}))
+    match result {
+        Ok(r) if r.rows_affected() == 0 => HttpResponse::NotFound().json(json!({"error": "Folder not found"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Folder renamed"})),
+        Err(_) => HttpResponse::BadRequest().json(json!({"error": "Folder already exists"})),
     }
 }
 
+#[delete("/api/chat/folders/{id}")]
+async fn delete_chat_folder(
+    user: AuthenticatedUser,
+    state: web::Data<AppState>,
+    path: web::Path<String>,
+) -> impl Responder {
+    if let Err(resp) = require_verified(&user, &state.rag.pool).await {
+        return resp;
+    }
+    // Sessions in the folder are kept (folder_id is set to NULL)
+    let result = sqlx::query("DELETE FROM chat_folders WHERE id = $1 AND user_id = $2")
+        .bind(path.into_inner())
+        .bind(&user.id)
+        .execute(&state.rag.pool)
+        .await;
+
+    match result {
+        Ok(r) if r.rows_affected() == 0 => HttpResponse::NotFound().json(json!({"error": "Folder not found"})),
+        Ok(_) => HttpResponse::Ok().json(json!({"message": "Folder deleted"})),
+        Err(e) => HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+    }
+}
 
 // AI Model Management Endpoints
 
@@ -1152,17 +4648,23 @@ async fn add_model(
         return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
     }
 
+    if let Some(Err(e)) = body.retrieval_settings.as_ref().map(|s| s.validate()) {
+        return HttpResponse::BadRequest().json(json!({"error": e}));
+    }
+
     let id = Uuid::new_v4().to_string();
     let provider = body.provider.clone().unwrap_or_else(|| "openrouter".to_string());
+    let settings = body.retrieval_settings.as_ref().map(|s| json!(s).to_string());
 
     let result = sqlx::query(
-        "INSERT INTO ai_models (id, api_model_name, display_name, provider, system_prompt) VALUES ($1, $2, $3, $4, $5)",
+        "INSERT INTO ai_models (id, api_model_name, display_name, provider, system_prompt, retrieval_settings) VALUES ($1, $2, $3, $4, $5, $6)",
     )
     .bind(&id)
     .bind(&body.api_model_name)
     .bind(&body.display_name)
     .bind(&provider)
     .bind(&body.system_prompt)
+    .bind(settings)
     .execute(&state.rag.pool)
     .await;
 
@@ -1186,6 +4688,10 @@ async fn update_model(
         return HttpResponse::Forbidden().json(json!({"error": "Admin access required"}));
     }
 
+    if let Some(Err(e)) = body.retrieval_settings.as_ref().map(|s| s.validate()) {
+        return HttpResponse::BadRequest().json(json!({"error": e}));
+    }
+
     let id = path.into_inner();
     
     // Fetch existing model first
@@ -1201,15 +4707,17 @@ async fn update_model(
             if let Some(prov) = &body.provider { model.provider = prov.clone(); }
             if let Some(active) = body.is_active { model.is_active = active; }
             if let Some(prompt) = &body.system_prompt { model.system_prompt = Some(prompt.clone()); }
+            if let Some(settings) = &body.retrieval_settings { model.retrieval_settings = Some(json!(settings).to_string()); }
 
             let update_result = sqlx::query(
-                "UPDATE ai_models SET api_model_name = $1, display_name = $2, provider = $3, is_active = $4, system_prompt = $5 WHERE id = $6"
+                "UPDATE ai_models SET api_model_name = $1, display_name = $2, provider = $3, is_active = $4, system_prompt = $5, retrieval_settings = $6 WHERE id = $7"
             )
             .bind(model.api_model_name)
             .bind(model.display_name)
             .bind(model.provider)
             .bind(model.is_active)
             .bind(model.system_prompt)
+            .bind(model.retrieval_settings)
             .bind(id)
             .execute(&state.rag.pool)
             .await;
@@ -1258,6 +4766,7 @@ async fn delete_model(
 pub async fn upload_file(
     user: AuthenticatedUser,
     state: web::Data<AppState>,
+    query: web::Query<ImportUploadQuery>,
     mut payload: Multipart,
 ) -> impl Responder {
     if let Err(resp) = require_verified(&user, &state.rag.pool).await {
@@ -1267,17 +4776,36 @@ pub async fn upload_file(
         return HttpResponse::Forbidden().json(json!({"error": "Admin/Editor access required"}));
     }
 
+    let Some(on_conflict) = ConflictPolicy::parse(query.on_conflict.as_deref()) else {
+        return HttpResponse::BadRequest().json(json!({"error": "on_conflict must be skip, update or duplicate"}));
+    };
+    let adapter = match query.adapter.as_deref().filter(|a| !a.is_empty()) {
+        Some(slug) => match adapters::resolve(&state.rag.pool, slug).await {
+            Ok(Some(config)) => Some(json!(config).to_string()),
+            Ok(None) => return HttpResponse::BadRequest().json(json!({"error": format!("Unknown adapter '{}'", slug)})),
+            Err(e) => return HttpResponse::InternalServerError().json(json!({"error": e.to_string()})),
+        },
+        None => None,
+    };
+    let collection_id = match check_collection(&state.rag.pool, query.collection.as_deref()).await {
+        Ok(c) => c,
+        Err(resp) => return resp,
+    };
+
     while let Ok(Some(mut field)) = payload.try_next().await {
         let content_disposition = field.content_disposition();
-        let filename = content_disposition.get_filename().unwrap_or("unknown");
-        
-        if filename.ends_with(".json") {
-             let temp_path = format!("temp_upload_{}.json", uuid::Uuid::new_v4());
+        let filename = content_disposition.get_filename().unwrap_or("unknown").to_string();
+        let mime = field.content_type().map(|m| m.essence_str().to_string());
+
+        if let Some(format) = SourceFormat::detect(&filename, mime.as_deref()) {
+             let job_id = uuid::Uuid::new_v4().to_string();
+             let dir = upload_dir().join("imports");
+             let storage_path = dir.join(format!("{}.{}", job_id, format.extension())).to_string_lossy().to_string();
              // Create file
-             let path_clone = temp_path.clone();
-             let mut f = match web::block(move || std::fs::File::create(path_clone)).await {
+             let path_clone = storage_path.clone();
+             let mut f = match web::block(move || std::fs::create_dir_all(dir).and_then(|_| std::fs::File::create(path_clone))).await {
                  Ok(Ok(f)) => f,
-                 _ => return HttpResponse::InternalServerError().json(json!({"error": "Failed to create temp file"})),
+                 _ => return HttpResponse::InternalServerError().json(json!({"error": "Failed to create upload file"})),
              };
              
              // Write chunks
@@ -1292,108 +4820,141 @@ pub async fn upload_file(
                      _ => return HttpResponse::InternalServerError().json(json!({"error": "Failed to write to file"})),
                  }
              }
-             
+
+             let dry_run = query.dry_run.unwrap_or(false);
+             let created = sqlx::query("INSERT INTO import_jobs (id, uploaded_by, filename, storage_path, dry_run, format, adapter, on_conflict, collection_id) VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)")
+                 .bind(&job_id)
+                 .bind(&user.id)
+                 .bind(&filename)
+                 .bind(&storage_path)
+                 .bind(dry_run)
+                 .bind(format.name())
+                 .bind(&adapter)
+                 .bind(on_conflict.name())
+                 .bind(&collection_id)
+                 .execute(&state.rag.pool)
+                 .await;
+             if let Err(e) = created {
+                 let _ = std::fs::remove_file(&storage_path);
+                 return HttpResponse::InternalServerError().json(json!({"error": e.to_string()}));
+             }
+
              // Spawn background processing
-             let state_clone = state.clone();
-             let temp_path_clone = temp_path.clone();
-             
-             tokio::spawn(async move {
-                 println!("Starting background processing of {}", temp_path_clone);
-                 if let Err(e) = process_knowledge_file(state_clone, temp_path_clone.clone()).await {
-                     eprintln!("Error processing file {}: {}", temp_path_clone, e);
-                 } else {
-                     println!("Finished processing {}", temp_path_clone);
-                 }
-                 let _ = std::fs::remove_file(temp_path_clone);
-             });
+             tokio::spawn(imports::run_job(state.rag.clone(), job_id.clone()));
              
-             return HttpResponse::Ok().json(json!({"message": "File uploaded. Processing started in background."}));
+             let message = if dry_run {
+                 "File uploaded. Validation started in background (dry run, nothing will be written)."
+             } else {
+                 "File uploaded. Processing started in background."
+             };
+             return HttpResponse::Ok().json(json!({"message": message, "job_id": job_id, "dry_run": dry_run, "format": format.name()}));
         }
     }
     
-    HttpResponse::BadRequest().json(json!({"error": "No JSON file found"}))
+    HttpResponse::BadRequest().json(json!({"error": format!("No supported file found. Accepted: {}", SourceFormat::accepted())}))
 }
 
-async fn process_knowledge_file(state: web::Data<AppState>, file_path: String) -> Result<(), Box<dyn std::error::Error>> {
-    let file = std::fs::File::open(&file_path)?;
-    let mut reader = std::io::BufReader::new(file);
-    
-    // Count total items first (inefficient for huge files but needed for progress bar)
-    // Or just stream and update processed count without total (indeterminate progress bar if total unknown)
-    // Let's try to read once to count, then reset? No, stream deserializer is better.
-    // Ideally user sends total count or we just show "Processed X documents" without %.
-    // But for JSON array, we can't easily know total without parsing.
-    // Let's just track processed count.
-    
-    // Reset status
-    {
-        let mut status = state.import_status.lock().unwrap();
-        status.is_processing = true;
-        status.processed_documents = 0;
-        status.total_documents = 0;
-        status.errors = 0;
-        status.current_file = file_path.clone();
-        status.message = "Parsing file...".to_string();
-    }
-
-    // Try to parse as a JSON Array first (most common)
-    let documents: Vec<CreateDocumentRequest> = match serde_json::from_reader(&mut reader) {
-        Ok(docs) => docs,
-        Err(_) => {
-            // If array parsing fails, try to rewind and parse as JSON Lines / Stream
-            // Re-open file since BufReader might be consumed
-            let file = std::fs::File::open(&file_path)?;
-            let reader = std::io::BufReader::new(file);
-            let stream = serde_json::Deserializer::from_reader(reader).into_iter::<CreateDocumentRequest>();
-            
-            let mut docs = Vec::new();
-            for res in stream {
-                if let Ok(doc) = res {
-                    docs.push(doc);
-                }
+/// Flag patterns to redact from a hint-mode session below the `Solution` level, or `None`
+/// when nothing is withheld.
+async fn flag_redaction(pool: &DbPool, hint_mode: bool, hint_level: HintLevel) -> Option<Vec<regex::Regex>> {
+    if !hint_mode || hint_level >= HintLevel::Solution {
+        return None;
+    }
+    Some(hints::compile_patterns(&settings::get(pool, "flag_patterns").await))
+}
+
+/// Redacts flags from the chunk previews stored with assistant replies. Replies recorded before
+/// previews were redacted at write time can still hold them.
+fn redact_sources(messages: &mut [ChatMessage], patterns: &[regex::Regex]) {
+    for message in messages {
+        let Some(sources) = message.sources.as_deref() else { continue };
+        let Ok(mut parsed) = serde_json::from_str::<Vec<serde_json::Value>>(sources) else { continue };
+        for source in &mut parsed {
+            if let Some(preview) = source["preview"].as_str() {
+                source["preview"] = json!(hints::redact_flags(preview, patterns).0);
             }
-            docs
         }
-    };
-
-    let total_docs = documents.len();
-    {
-        let mut status = state.import_status.lock().unwrap();
-        status.total_documents = total_docs;
-        status.message = format!("Starting import of {} documents...", total_docs);
+        message.sources = Some(json!(parsed).to_string());
     }
-    
-    println!("Starting import of {} documents from {}", total_docs, file_path);
-    
-    let mut count = 0;
-    let mut error_count = 0;
-    
-    for doc in documents {
-         if let Err(e) = state.rag.add_document(&doc.content, doc.metadata).await {
-             eprintln!("Failed to add document: {}", e);
-             error_count += 1;
-         } else {
-             count += 1;
-             if count % 10 == 0 {
-                 let mut status = state.import_status.lock().unwrap();
-                 status.processed_documents = count;
-                 status.errors = error_count;
-                 status.message = format!("Processed {}/{} documents...", count, total_docs);
-             }
-         }
+}
+
+/// Sends a chat completion request, mapping transport and provider failures to the chat error responses.
+async fn call_openrouter(
+    client: &reqwest::Client,
+    api_key: &str,
+    request_body: &serde_json::Value,
+) -> Result<serde_json::Value, HttpResponse> {
+    let res = client
+        .post("https://openrouter.ai/api/v1/chat/completions")
+        .header("Authorization", format!("Bearer {}", api_key))
+        .header("Content-Type", "application/json")
+        .header("HTTP-Referer", "https://github.com/Krypton-OSS/KryptonSecAI") // Updated to generic URL for cross-device compatibility
+        .header("X-Title", "KryptonSecAI")
+        .json(request_body)
+        .send()
+        .await;
+
+    match res {
+        Ok(response) => {
+            if response.status().is_success() {
+                Ok(response.json().await.unwrap_or(json!({})))
+            } else {
+                let status = response.status();
+                let error_text = response.text().await.unwrap_or_default();
+                println!("OpenRouter Error: Status: {}, Body: {}", status, error_text);
+                Err(HttpResponse::BadRequest().json(json!({"error": "Provider Error", "details": error_text})))
+            }
+        }
+        Err(e) => {
+            println!("OpenRouter Request Failed: {}", e);
+            Err(HttpResponse::InternalServerError().json(json!({"error": e.to_string()})))
+        }
     }
-    
-    println!("Finished processing {}. Imported: {}, Errors: {}", file_path, count, error_count);
-    
-    {
-        let mut status = state.import_status.lock().unwrap();
-        status.is_processing = false;
-        status.processed_documents = count;
-        status.errors = error_count;
-        status.message = format!("Import complete. Imported: {}, Errors: {}", count, error_count);
+}
+
+async fn generate_session_title(
+    client: &reqwest::Client,
+    api_key: &str,
+    model: &str,
+    question: &str,
+    answer: &str,
+) -> Option<String> {
+    let excerpt: String = answer.chars().take(1000).collect();
+    let request_body = json!({
+        "model": model,
+        "messages": [
+            {"role": "system", "content": "Write a short title (max 6 words) for this conversation. Reply with the title only, no quotes or punctuation at the end."},
+            {"role": "user", "content": format!("User: {}\n\nAssistant: {}", question, excerpt)}
+        ],
+        "max_tokens": 30
+    });
+
+    let res = client
+        .post("https://openrouter.ai/api/v1/chat/completions")
+        .header("Authorization", format!("Bearer {}", api_key))
+        .header("Content-Type", "application/json")
+        .header("HTTP-Referer", "https://github.com/Krypton-OSS/KryptonSecAI")
+        .header("X-Title", "KryptonSecAI")
+        .json(&request_body)
+        .send()
+        .await
+        .ok()?;
+
+    if !res.status().is_success() {
+        return None;
     }
-    
-    Ok(())
+    let json_resp: serde_json::Value = res.json().await.ok()?;
+    let title = json_resp["choices"][0]["message"]["content"]
+        .as_str()?
+        .trim()
+        .trim_matches('"')
+        .lines()
+        .next()?
+        .chars()
+        .take(80)
+        .collect::<String>();
+
+    if title.is_empty() { None } else { Some(title) }
 }
 
 async fn send_verification_email(to_email: String, to_name: String, verify_link: String) -> Result<(), Box<dyn std::error::Error>> {
@@ -0,0 +1,221 @@
+use serde::Serialize;
+use serde_json::{json, Value};
+use std::process::Stdio;
+use std::sync::OnceLock;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::process::Command;
+use tokio::sync::Semaphore;
+
+/// Name of the model-facing tool; handled in the chat loop rather than `tools::execute`
+/// because it needs the caller's identity for auditing.
+pub const TOOL_NAME: &str = "run_code";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Language {
+    Python,
+    Shell,
+}
+
+impl Language {
+    pub fn parse(value: &str) -> Option<Language> {
+        match value.to_lowercase().as_str() {
+            "python" | "python3" | "py" => Some(Language::Python),
+            "sh" | "shell" | "bash" => Some(Language::Shell),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Language::Python => "python",
+            Language::Shell => "shell",
+        }
+    }
+
+    /// Interpreter reading the program from stdin, so code never appears in argv.
+    fn interpreter(&self) -> &'static [&'static str] {
+        match self {
+            Language::Python => &["python3", "-I", "-"],
+            Language::Shell => &["sh", "-s"],
+        }
+    }
+}
+
+/// Resource limits applied to every run, configurable through `SANDBOX_*` env vars.
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub wall_secs: u64,
+    pub cpu_secs: u64,
+    pub memory_mb: u64,
+    pub max_processes: u64,
+    pub max_output_bytes: usize,
+}
+
+impl Limits {
+    pub fn from_env() -> Limits {
+        let var = |key: &str, default: u64| {
+            std::env::var(key).ok().and_then(|v| v.parse().ok()).unwrap_or(default)
+        };
+        Limits {
+            wall_secs: var("SANDBOX_TIMEOUT_SECS", 10),
+            cpu_secs: var("SANDBOX_CPU_SECS", 5),
+            memory_mb: var("SANDBOX_MEMORY_MB", 256),
+            max_processes: var("SANDBOX_MAX_PROCESSES", 32),
+            max_output_bytes: var("SANDBOX_MAX_OUTPUT_BYTES", 64 * 1024) as usize,
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExecutionResult {
+    pub stdout: String,
+    pub stderr: String,
+    pub exit_code: Option<i32>,
+    pub timed_out: bool,
+    pub duration_ms: i64,
+}
+
+fn slots() -> &'static Semaphore {
+    static SLOTS: OnceLock<Semaphore> = OnceLock::new();
+    SLOTS.get_or_init(|| {
+        let n = std::env::var("SANDBOX_CONCURRENCY").ok().and_then(|v| v.parse().ok()).unwrap_or(2);
+        Semaphore::new(n)
+    })
+}
+
+/// Builds the bubblewrap command line: fresh namespaces (no network), read-only system dirs,
+/// a private tmpfs as the only writable path and rlimits applied with prlimit.
+///
+/// Each run gets its own user namespace with the code running as an unprivileged mapped uid.
+/// `RLIMIT_NPROC` is counted per user namespace (Linux 5.14+), so `--nproc` caps this run alone
+/// rather than every process of the server's uid.
+fn build_command(language: Language, limits: &Limits) -> Command {
+    let bwrap = std::env::var("SANDBOX_BWRAP").unwrap_or_else(|_| "bwrap".to_string());
+    let uid = std::env::var("SANDBOX_UID").unwrap_or_else(|_| "65534".to_string());
+    let mut cmd = Command::new(bwrap);
+    cmd.args(["--unshare-all", "--unshare-user", "--die-with-parent", "--new-session"])
+        .args(["--uid", &uid, "--gid", &uid])
+        .args(["--ro-bind", "/usr", "/usr"])
+        .args(["--ro-bind-try", "/bin", "/bin"])
+        .args(["--ro-bind-try", "/lib", "/lib"])
+        .args(["--ro-bind-try", "/lib64", "/lib64"])
+        .args(["--ro-bind-try", "/etc/alternatives", "/etc/alternatives"])
+        .args(["--proc", "/proc", "--dev", "/dev", "--tmpfs", "/tmp", "--chdir", "/tmp"])
+        .args(["--clearenv", "--setenv", "PATH", "/usr/local/bin:/usr/bin:/bin", "--setenv", "HOME", "/tmp"])
+        .arg("--")
+        .arg("prlimit")
+        .arg(format!("--as={}", limits.memory_mb * 1024 * 1024))
+        .arg(format!("--cpu={}", limits.cpu_secs))
+        .arg(format!("--nproc={}", limits.max_processes))
+        .arg(format!("--fsize={}", limits.max_output_bytes * 4))
+        .arg("--")
+        .args(language.interpreter());
+    cmd.stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .kill_on_drop(true);
+    cmd
+}
+
+async fn read_capped<R: tokio::io::AsyncRead + Unpin>(reader: Option<R>, cap: usize) -> String {
+    let mut buf = Vec::new();
+    if let Some(r) = reader {
+        // Draining past the cap would let a chatty script fill memory; it is killed at the wall limit instead
+        let _ = r.take(cap as u64 + 1).read_to_end(&mut buf).await;
+    }
+    let truncated = buf.len() > cap;
+    buf.truncate(cap);
+    let mut text = String::from_utf8_lossy(&buf).to_string();
+    if truncated {
+        text.push_str("\n[output truncated]");
+    }
+    text
+}
+
+/// Runs a snippet in the sandbox. Errors only when the sandbox itself cannot be started.
+pub async fn run(language: Language, code: &str, limits: &Limits) -> Result<ExecutionResult, String> {
+    let _permit = slots().acquire().await.map_err(|e| e.to_string())?;
+
+    let started = Instant::now();
+    let mut child = build_command(language, limits)
+        .spawn()
+        .map_err(|e| format!("Sandbox unavailable (is bubblewrap installed?): {}", e))?;
+
+    let stdout = tokio::spawn(read_capped(child.stdout.take(), limits.max_output_bytes));
+    let stderr = tokio::spawn(read_capped(child.stderr.take(), limits.max_output_bytes));
+
+    // Dropping stdin after the write gives the interpreter EOF
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(code.as_bytes()).await;
+    }
+
+    let (exit_code, timed_out) = match tokio::time::timeout(Duration::from_secs(limits.wall_secs), child.wait()).await {
+        Ok(Ok(status)) => (status.code(), false),
+        Ok(Err(e)) => return Err(e.to_string()),
+        Err(_) => {
+            let _ = child.kill().await;
+            (None, true)
+        }
+    };
+
+    Ok(ExecutionResult {
+        stdout: stdout.await.unwrap_or_default(),
+        stderr: stderr.await.unwrap_or_default(),
+        exit_code,
+        timed_out,
+        duration_ms: started.elapsed().as_millis() as i64,
+    })
+}
+
+/// Fenced code blocks in a markdown message that the sandbox can run, in order of appearance.
+pub fn extract_code_blocks(markdown: &str) -> Vec<(Language, String)> {
+    let mut blocks = Vec::new();
+    let mut current: Option<(Option<Language>, Vec<&str>)> = None;
+
+    for line in markdown.lines() {
+        let trimmed = line.trim_start();
+        if let Some(info) = trimmed.strip_prefix("```") {
+            match current.take() {
+                Some((Some(language), body)) => blocks.push((language, body.join("\n"))),
+                Some((None, _)) => {}
+                None => {
+                    let tag = info.split_whitespace().next().unwrap_or("");
+                    current = Some((Language::parse(tag), Vec::new()));
+                }
+            }
+        } else if let Some((_, body)) = current.as_mut() {
+            body.push(line);
+        }
+    }
+    blocks
+}
+
+/// Tool definition offered to the model when code execution is allowed for the user.
+pub fn tool_definition() -> Value {
+    json!({
+        "type": "function",
+        "function": {
+            "name": TOOL_NAME,
+            "description": "Run a short Python 3 or POSIX shell script in an isolated sandbox without network access and return stdout, stderr and the exit code.",
+            "parameters": {
+                "type": "object",
+                "properties": {
+                    "language": {"type": "string", "enum": ["python", "shell"]},
+                    "code": {"type": "string"}
+                },
+                "required": ["language", "code"]
+            }
+        }
+    })
+}
+
+/// Compact text form of a result for tool messages.
+pub fn format_result(result: &ExecutionResult) -> String {
+    let status = match (result.timed_out, result.exit_code) {
+        (true, _) => "timed out".to_string(),
+        (false, Some(code)) => format!("exit code {}", code),
+        (false, None) => "killed by signal".to_string(),
+    };
+    format!("[{} in {} ms]\n--- stdout ---\n{}\n--- stderr ---\n{}", status, result.duration_ms, result.stdout, result.stderr)
+}
@@ -0,0 +1,53 @@
+/// Fraction of the challenge points lost per unlocked challenge hint.
+pub const HINT_PENALTY: f64 = 0.10;
+/// Fraction lost when Krypton was used on the challenge at all.
+pub const ASSIST_PENALTY: f64 = 0.05;
+/// Extra fraction lost per hint-mode level escalated in Krypton sessions on the challenge.
+pub const ASSIST_LEVEL_PENALTY: f64 = 0.10;
+/// A solve is always worth at least this fraction of the base points.
+pub const MIN_AWARD: f64 = 0.10;
+
+/// Points awarded for a solve after decay.
+/// `assist_level` is `None` when no Krypton session was attached to the challenge,
+/// otherwise the highest hint level reached (full answers without hint mode count as the top level).
+/// Help from sessions not attached to the challenge can't be attributed, so it is not counted.
+pub fn award_points(base: i32, hints_unlocked: i64, assist_level: Option<i32>) -> i32 {
+    let mut penalty = HINT_PENALTY * hints_unlocked as f64;
+    if let Some(level) = assist_level {
+        penalty += ASSIST_PENALTY + ASSIST_LEVEL_PENALTY * level.max(0) as f64;
+    }
+    let factor = (1.0 - penalty).max(MIN_AWARD);
+    (base as f64 * factor).round() as i32
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_assistance_keeps_full_points() {
+        assert_eq!(award_points(100, 0, None), 100);
+    }
+
+    #[test]
+    fn each_hint_costs_a_tenth() {
+        assert_eq!(award_points(100, 1, None), 90);
+        assert_eq!(award_points(100, 2, None), 80);
+        assert_eq!(award_points(100, 3, None), 70);
+    }
+
+    #[test]
+    fn assist_level_adds_to_penalty() {
+        assert_eq!(award_points(100, 0, Some(0)), 95);
+        assert_eq!(award_points(100, 0, Some(3)), 65);
+        assert_eq!(award_points(100, 2, Some(3)), 45);
+        assert_eq!(award_points(100, 0, Some(-1)), 95);
+    }
+
+    #[test]
+    fn award_never_drops_below_floor() {
+        assert_eq!(award_points(100, 6, Some(3)), 10);
+        assert_eq!(award_points(100, 50, None), 10);
+        assert_eq!(award_points(0, 0, None), 0);
+    }
+}
@@ -0,0 +1,73 @@
+use crate::db::DbPool;
+
+/// Admin-controlled runtime settings with their defaults.
+/// Unknown keys are rejected by the settings endpoint.
+pub const DEFAULTS: &[(&str, &str)] = &[
+    // off | subscribers | everyone
+    ("sharing_policy", "off"),
+    // JSON array of regexes redacted from assistant output in hint mode
+    ("flag_patterns", crate::hints::DEFAULT_FLAG_PATTERNS),
+    // on | off: offer the built-in CTF utility tools to the model via function calling
+    ("chat_tools", "off"),
+    // off | subscribers | everyone: who may run code in the sandbox
+    ("code_execution_policy", "off"),
+    // off | rewrite | multi | hyde: rewrite follow-ups into standalone search queries before retrieval,
+    // optionally adding sub-queries or a hypothetical answer; see `query_rewrite::Mode`
+    ("query_rewriting", "off"),
+    // Model used for query rewriting; empty uses the chat's model. A small non-reasoning model keeps
+    // the extra round trip short, as reasoning models can spend the whole token budget thinking
+    ("query_rewrite_model", ""),
+];
+
+pub fn default_for(key: &str) -> Option<&'static str> {
+    DEFAULTS.iter().find(|(k, _)| *k == key).map(|(_, v)| *v)
+}
+
+/// Rejects values the setting's readers would not understand, so a typo can't silently
+/// switch a feature off or leave hint mode redacting with the default patterns.
+pub fn validate(key: &str, value: &str) -> Result<(), String> {
+    let allowed: &[&str] = match key {
+        "sharing_policy" | "code_execution_policy" => &["off", "subscribers", "everyone"],
+        "chat_tools" => &["on", "off"],
+        "query_rewriting" => &["off", "rewrite", "multi", "hyde"],
+        "flag_patterns" => return crate::hints::validate_patterns(value),
+        _ => return Ok(()),
+    };
+    if allowed.contains(&value) {
+        Ok(())
+    } else {
+        Err(format!("{} must be one of: {}", key, allowed.join(", ")))
+    }
+}
+
+pub async fn get(pool: &DbPool, key: &str) -> String {
+    let row: Option<(String,)> = sqlx::query_as("SELECT value FROM app_settings WHERE key = $1")
+        .bind(key)
+        .fetch_optional(pool)
+        .await
+        .unwrap_or(None);
+
+    match row {
+        Some((value,)) => value,
+        None => default_for(key).unwrap_or_default().to_string(),
+    }
+}
+
+pub async fn set(pool: &DbPool, key: &str, value: &str) -> Result<(), sqlx::Error> {
+    sqlx::query(
+        "INSERT INTO app_settings (key, value) VALUES ($1, $2) ON CONFLICT (key) DO UPDATE SET value = EXCLUDED.value, updated_at = CURRENT_TIMESTAMP",
+    )
+    .bind(key)
+    .bind(value)
+    .execute(pool)
+    .await?;
+    Ok(())
+}
+
+pub async fn all(pool: &DbPool) -> Vec<(String, String)> {
+    let mut values = Vec::with_capacity(DEFAULTS.len());
+    for (key, _) in DEFAULTS {
+        values.push((key.to_string(), get(pool, key).await));
+    }
+    values
+}
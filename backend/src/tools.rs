@@ -0,0 +1,406 @@
+use base64::Engine;
+use serde_json::{json, Value};
+
+/// Upper bound on model <-> tool round trips for a single chat request.
+pub const MAX_TOOL_ROUNDS: usize = 5;
+/// Tool output is truncated before it is sent back to the model.
+const MAX_OUTPUT_CHARS: usize = 8000;
+
+/// OpenAI-style `tools` definitions offered to the provider.
+pub fn definitions() -> Vec<Value> {
+    vec![
+        tool("base64_decode", "Decode standard or URL-safe base64 (padding optional).", json!({
+            "input": {"type": "string"}
+        }), &["input"]),
+        tool("hex_decode", "Decode a hex string (spaces, 0x prefixes and \\x escapes allowed).", json!({
+            "input": {"type": "string"}
+        }), &["input"]),
+        tool("rot_n", "Apply a ROT-N / Caesar shift to letters. Omit shift to get all 25 shifts.", json!({
+            "input": {"type": "string"},
+            "shift": {"type": "integer", "minimum": 1, "maximum": 25}
+        }), &["input"]),
+        tool("xor_bruteforce", "Brute force single-byte XOR over hex-encoded data and return the most printable candidates.", json!({
+            "hex": {"type": "string"},
+            "known_prefix": {"type": "string", "description": "Optional plaintext prefix such as 'flag{' to filter candidates."}
+        }), &["hex"]),
+        tool("xor_with_key", "XOR hex-encoded data with a repeating key (given as text or hex).", json!({
+            "hex": {"type": "string"},
+            "key": {"type": "string"},
+            "key_is_hex": {"type": "boolean"}
+        }), &["hex", "key"]),
+        tool("identify_hash", "Guess the hash algorithm from a hash string's length, charset and prefix.", json!({
+            "hash": {"type": "string"}
+        }), &["hash"]),
+        tool("convert_integer", "Convert an integer (decimal, 0x hex, 0b binary or 0o octal) to every base and to little/big-endian bytes.", json!({
+            "value": {"type": "string"}
+        }), &["value"]),
+        tool("vigenere_decrypt", "Decrypt Vigenère ciphertext with the given key.", json!({
+            "ciphertext": {"type": "string"},
+            "key": {"type": "string"}
+        }), &["ciphertext", "key"]),
+        tool("atbash", "Apply the Atbash cipher.", json!({
+            "input": {"type": "string"}
+        }), &["input"]),
+        tool("jwt_decode", "Decode a JWT's header and payload without verifying the signature.", json!({
+            "token": {"type": "string"}
+        }), &["token"]),
+    ]
+}
+
+fn tool(name: &str, description: &str, properties: Value, required: &[&str]) -> Value {
+    json!({
+        "type": "function",
+        "function": {
+            "name": name,
+            "description": description,
+            "parameters": {
+                "type": "object",
+                "properties": properties,
+                "required": required,
+            }
+        }
+    })
+}
+
+/// Executes a tool call locally. Errors are returned as text so the model can correct itself.
+pub fn execute(name: &str, args: &Value) -> Result<String, String> {
+    let arg = |key: &'static str| str_arg(args, key);
+
+    let output = match name {
+        "base64_decode" => base64_decode(arg("input")?)?,
+        "hex_decode" => bytes_to_text(&parse_hex(arg("input")?)?),
+        "rot_n" => match args["shift"].as_u64() {
+            Some(shift) => rot(arg("input")?, (shift % 26) as u8),
+            None => {
+                let input = arg("input")?;
+                (1..26u8)
+                    .map(|s| format!("ROT{:02}: {}", s, rot(input, s)))
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+        },
+        "xor_bruteforce" => xor_bruteforce(&parse_hex(arg("hex")?)?, args["known_prefix"].as_str()),
+        "xor_with_key" => {
+            let data = parse_hex(arg("hex")?)?;
+            let key = if args["key_is_hex"].as_bool().unwrap_or(false) {
+                parse_hex(arg("key")?)?
+            } else {
+                arg("key")?.as_bytes().to_vec()
+            };
+            if key.is_empty() {
+                return Err("key must not be empty".to_string());
+            }
+            let out: Vec<u8> = data.iter().zip(key.iter().cycle()).map(|(d, k)| d ^ k).collect();
+            bytes_to_text(&out)
+        }
+        "identify_hash" => identify_hash(arg("hash")?),
+        "convert_integer" => convert_integer(arg("value")?)?,
+        "vigenere_decrypt" => vigenere_decrypt(arg("ciphertext")?, arg("key")?)?,
+        "atbash" => atbash(arg("input")?),
+        "jwt_decode" => jwt_decode(arg("token")?)?,
+        _ => return Err(format!("unknown tool '{}'", name)),
+    };
+
+    Ok(output.chars().take(MAX_OUTPUT_CHARS).collect())
+}
+
+fn str_arg<'a>(args: &'a Value, key: &str) -> Result<&'a str, String> {
+    args[key].as_str().ok_or_else(|| format!("missing string argument '{}'", key))
+}
+
+fn bytes_to_text(bytes: &[u8]) -> String {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => text.to_string(),
+        Err(_) => format!("(non-UTF-8 bytes, shown lossy) {}\nhex: {}", String::from_utf8_lossy(bytes), to_hex(bytes)),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn parse_hex(input: &str) -> Result<Vec<u8>, String> {
+    let cleaned: String = input
+        .replace("0x", "")
+        .replace("\\x", "")
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != ',' && *c != ':')
+        .collect();
+    if !cleaned.is_ascii() {
+        return Err("hex input contains non-hex characters".to_string());
+    }
+    if !cleaned.len().is_multiple_of(2) {
+        return Err("hex input has an odd number of digits".to_string());
+    }
+    cleaned
+        .as_bytes()
+        .chunks(2)
+        .map(|pair| {
+            let digits = std::str::from_utf8(pair).unwrap_or_default();
+            u8::from_str_radix(digits, 16).map_err(|_| format!("invalid hex near '{}'", digits))
+        })
+        .collect()
+}
+
+fn base64_decode(input: &str) -> Result<String, String> {
+    let trimmed: String = input.chars().filter(|c| !c.is_whitespace()).collect();
+    let unpadded = trimmed.trim_end_matches('=');
+    let engines = [
+        base64::engine::general_purpose::STANDARD_NO_PAD,
+        base64::engine::general_purpose::URL_SAFE_NO_PAD,
+    ];
+    for engine in engines {
+        if let Ok(bytes) = engine.decode(unpadded) {
+            return Ok(bytes_to_text(&bytes));
+        }
+    }
+    Err("input is not valid base64".to_string())
+}
+
+fn rot(input: &str, shift: u8) -> String {
+    input
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => (((c as u8 - b'a' + shift) % 26) + b'a') as char,
+            'A'..='Z' => (((c as u8 - b'A' + shift) % 26) + b'A') as char,
+            _ => c,
+        })
+        .collect()
+}
+
+fn atbash(input: &str) -> String {
+    input
+        .chars()
+        .map(|c| match c {
+            'a'..='z' => (b'z' - (c as u8 - b'a')) as char,
+            'A'..='Z' => (b'Z' - (c as u8 - b'A')) as char,
+            _ => c,
+        })
+        .collect()
+}
+
+fn printable_ratio(bytes: &[u8]) -> f64 {
+    if bytes.is_empty() {
+        return 0.0;
+    }
+    let printable = bytes.iter().filter(|b| b.is_ascii_graphic() || **b == b' ' || **b == b'\n').count();
+    printable as f64 / bytes.len() as f64
+}
+
+fn xor_bruteforce(data: &[u8], known_prefix: Option<&str>) -> String {
+    let mut candidates: Vec<(u8, f64, Vec<u8>)> = (0..=255u8)
+        .map(|key| {
+            let out: Vec<u8> = data.iter().map(|b| b ^ key).collect();
+            // Favour English-looking output: printable first, then letters and spaces
+            let letters = out.iter().filter(|b| b.is_ascii_alphabetic() || **b == b' ').count() as f64 / out.len().max(1) as f64;
+            (key, printable_ratio(&out) * 2.0 + letters, out)
+        })
+        .filter(|(_, _, out)| known_prefix.map(|p| out.starts_with(p.as_bytes())).unwrap_or(true))
+        .collect();
+    candidates.sort_by(|a, b| b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal));
+
+    if candidates.is_empty() {
+        return "No single-byte key produces the known prefix.".to_string();
+    }
+    candidates
+        .iter()
+        .take(5)
+        .map(|(key, score, out)| format!("key=0x{:02x} score={:.2}: {}", key, score, String::from_utf8_lossy(out)))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn identify_hash(hash: &str) -> String {
+    let h = hash.trim();
+    let prefixed = [
+        ("$2a$", "bcrypt"), ("$2b$", "bcrypt"), ("$2y$", "bcrypt"),
+        ("$argon2id$", "Argon2id"), ("$argon2i$", "Argon2i"),
+        ("$6$", "SHA-512 crypt"), ("$5$", "SHA-256 crypt"), ("$1$", "MD5 crypt"),
+        ("$apr1$", "Apache MD5"), ("$y$", "yescrypt"), ("pbkdf2_sha256$", "Django PBKDF2-SHA256"),
+    ];
+    if let Some((_, name)) = prefixed.iter().find(|(p, _)| h.starts_with(p)) {
+        return format!("Likely {} (identified by prefix).", name);
+    }
+
+    let is_hex = !h.is_empty() && h.chars().all(|c| c.is_ascii_hexdigit());
+    let guesses: &[&str] = match (is_hex, h.len()) {
+        (true, 8) => &["CRC32", "Adler-32"],
+        (true, 16) => &["MySQL 3.x (OLD_PASSWORD)", "half MD5"],
+        (true, 32) => &["MD5", "NTLM", "MD4", "LM"],
+        (true, 40) => &["SHA-1", "RIPEMD-160", "MySQL 4.1+ (without leading *)"],
+        (true, 56) => &["SHA-224", "SHA3-224"],
+        (true, 64) => &["SHA-256", "SHA3-256", "BLAKE2s-256"],
+        (true, 96) => &["SHA-384", "SHA3-384"],
+        (true, 128) => &["SHA-512", "SHA3-512", "BLAKE2b-512", "Whirlpool"],
+        _ if h.starts_with('*') && h.len() == 41 => &["MySQL 4.1+"],
+        _ => &[],
+    };
+    if guesses.is_empty() {
+        format!("Unknown hash format (length {}, hex: {}).", h.len(), is_hex)
+    } else {
+        format!("Candidates (most likely first): {}", guesses.join(", "))
+    }
+}
+
+fn convert_integer(value: &str) -> Result<String, String> {
+    let v = value.trim().replace('_', "");
+    let (negative, digits) = match v.strip_prefix('-') {
+        Some(rest) => (true, rest.to_string()),
+        None => (false, v.clone()),
+    };
+    let (radix, body) = if let Some(hex) = digits.strip_prefix("0x").or_else(|| digits.strip_prefix("0X")) {
+        (16, hex)
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        (2, bin)
+    } else if let Some(oct) = digits.strip_prefix("0o") {
+        (8, oct)
+    } else {
+        (10, digits.as_str())
+    };
+    // The sign was taken above; a second one would let i128::MIN through to the negation
+    if body.starts_with(['+', '-']) {
+        return Err("could not parse integer: unexpected sign".to_string());
+    }
+    let parsed = i128::from_str_radix(body, radix).map_err(|e| format!("could not parse integer: {}", e))?;
+    let n = if negative { -parsed } else { parsed };
+
+    let as_u64 = n as u64;
+    let as_u32 = n as u32;
+    let mut out = vec![
+        format!("decimal: {}", n),
+        format!("hex: {:#x}", n),
+        format!("binary: {:#b}", n),
+        format!("octal: {:#o}", n),
+        format!("u32 little-endian bytes: {}", to_hex(&as_u32.to_le_bytes())),
+        format!("u32 big-endian bytes: {}", to_hex(&as_u32.to_be_bytes())),
+        format!("u64 little-endian bytes: {}", to_hex(&as_u64.to_le_bytes())),
+        format!("u64 big-endian bytes: {}", to_hex(&as_u64.to_be_bytes())),
+    ];
+    let ascii_be: Vec<u8> = as_u64.to_be_bytes().into_iter().skip_while(|b| *b == 0).collect();
+    if !ascii_be.is_empty() && ascii_be.iter().all(|b| b.is_ascii_graphic() || *b == b' ') {
+        out.push(format!("as ASCII (big-endian): {}", String::from_utf8_lossy(&ascii_be)));
+    }
+    Ok(out.join("\n"))
+}
+
+fn vigenere_decrypt(ciphertext: &str, key: &str) -> Result<String, String> {
+    let shifts: Vec<u8> = key
+        .chars()
+        .filter(|c| c.is_ascii_alphabetic())
+        .map(|c| c.to_ascii_lowercase() as u8 - b'a')
+        .collect();
+    if shifts.is_empty() {
+        return Err("key must contain letters".to_string());
+    }
+    let mut i = 0;
+    Ok(ciphertext
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphabetic() {
+                let shift = 26 - shifts[i % shifts.len()];
+                i += 1;
+                rot(&c.to_string(), shift % 26).chars().next().unwrap_or(c)
+            } else {
+                c
+            }
+        })
+        .collect())
+}
+
+fn jwt_decode(token: &str) -> Result<String, String> {
+    let parts: Vec<&str> = token.trim().split('.').collect();
+    if parts.len() < 2 {
+        return Err("a JWT has at least a header and payload separated by '.'".to_string());
+    }
+    let decode_part = |part: &str| -> Result<Value, String> {
+        let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(part.trim_end_matches('='))
+            .map_err(|_| "segment is not valid base64url".to_string())?;
+        serde_json::from_slice(&bytes).map_err(|_| "segment is not valid JSON".to_string())
+    };
+    let header = decode_part(parts[0])?;
+    let payload = decode_part(parts[1])?;
+
+    let mut notes = Vec::new();
+    if header["alg"].as_str().map(|a| a.eq_ignore_ascii_case("none")).unwrap_or(false) {
+        notes.push("alg is 'none': the signature is not checked by vulnerable verifiers");
+    }
+    if parts.get(2).map(|s| s.is_empty()).unwrap_or(true) {
+        notes.push("token has no signature");
+    }
+    if header["alg"].as_str().map(|a| a.starts_with("HS")).unwrap_or(false) {
+        notes.push("HMAC-signed: try cracking a weak secret (e.g. hashcat mode 16500)");
+    }
+
+    Ok(serde_json::to_string_pretty(&json!({
+        "header": header,
+        "payload": payload,
+        "notes": notes,
+    }))
+    .unwrap_or_default())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_hex_accepts_prefixes_and_separators() {
+        assert_eq!(parse_hex("0x41 0x42").unwrap(), b"AB");
+        assert_eq!(parse_hex("\\x41\\x42").unwrap(), b"AB");
+        assert_eq!(parse_hex("41:42,43").unwrap(), b"ABC");
+    }
+
+    #[test]
+    fn parse_hex_rejects_bad_input() {
+        assert!(parse_hex("abc").is_err());
+        assert!(parse_hex("zz").is_err());
+        assert!(parse_hex("é1").is_err());
+        assert!(parse_hex("4é").is_err());
+    }
+
+    #[test]
+    fn base64_decode_with_and_without_padding() {
+        assert_eq!(base64_decode("aGVsbG8=").unwrap(), "hello");
+        assert_eq!(base64_decode("aGVsbG8").unwrap(), "hello");
+        assert_eq!(base64_decode("Pz8_").unwrap(), "???");
+        assert!(base64_decode("not base64!").is_err());
+    }
+
+    #[test]
+    fn classical_ciphers_round_trip() {
+        let text = "Attack at Dawn, 1337!";
+        assert_eq!(rot(&rot(text, 13), 13), text);
+        assert_eq!(rot(&rot(text, 3), 23), text);
+        assert_eq!(atbash(&atbash(text)), text);
+        assert_eq!(vigenere_decrypt("LXFOPVEFRNHR", "lemon").unwrap(), "ATTACKATDAWN");
+        assert!(vigenere_decrypt("abc", "123").is_err());
+    }
+
+    #[test]
+    fn jwt_decode_flags_alg_none_without_signature() {
+        let encode = |v: &str| base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(v);
+        let token = format!("{}.{}.", encode(r#"{"alg":"none"}"#), encode(r#"{"sub":"admin"}"#));
+        let decoded: Value = serde_json::from_str(&jwt_decode(&token).unwrap()).unwrap();
+        assert_eq!(decoded["payload"]["sub"], "admin");
+        let notes = decoded["notes"].to_string();
+        assert!(notes.contains("alg is 'none'"));
+        assert!(notes.contains("no signature"));
+        assert!(jwt_decode("not-a-jwt").is_err());
+    }
+
+    #[test]
+    fn convert_integer_handles_negatives() {
+        let out = convert_integer("-1").unwrap();
+        assert!(out.contains("decimal: -1"));
+        assert!(out.contains("u32 little-endian bytes: ffffffff"));
+        assert!(convert_integer("0x41").unwrap().contains("as ASCII (big-endian): A"));
+    }
+
+    #[test]
+    fn convert_integer_rejects_overflow_and_double_signs() {
+        assert!(convert_integer("--170141183460469231731687303715884105728").is_err());
+        assert!(convert_integer("-0x-80").is_err());
+        assert!(convert_integer("0x1ffffffffffffffffffffffffffffffff").is_err());
+    }
+}
@@ -3,7 +3,7 @@ import React, { useState, useEffect, useCallback } from "react";
 import axios, { isAxiosError } from "axios";
 import Link from "next/link";
 import { useRouter } from "next/navigation";
-import { ArrowLeft, Upload, CheckCircle, AlertCircle, Users, CreditCard, Trash2, Edit, Cpu, Plus, X } from "lucide-react";
+import { ArrowLeft, Upload, CheckCircle, AlertCircle, Users, CreditCard, Trash2, Edit, Cpu, Plus, X, History } from "lucide-react";
 import { getApiBaseUrl } from "@/lib/api";
 
 interface Document {
@@ -11,6 +11,33 @@ interface Document {
   content: string;
   metadata: string | Record<string, unknown>;
   filename?: string;
+  collection_id?: string;
+}
+
+interface KbCollection {
+  id: string;
+  slug: string;
+  name: string;
+  visibility: "public" | "subscribers" | "roles" | "private";
+  roles: string[];
+  document_count: number;
+}
+
+interface DeletedDocument {
+  id: string;
+  content: string;
+  metadata: string | Record<string, unknown>;
+  deleted_at: string;
+  deleted_by_name?: string;
+}
+
+interface DocumentVersion {
+  document_id: string;
+  version: number;
+  action: string;
+  restored_from?: number;
+  author_name?: string;
+  created_at: string;
 }
 
 interface User {
@@ -47,13 +74,24 @@ interface AIModel {
   system_prompt?: string;
 }
 
-interface ImportStatus {
-    total_documents: number;
-    processed_documents: number;
-    errors: number;
-    is_processing: boolean;
-    current_file: string;
-    message: string;
+interface ImportJob {
+    id: string;
+    filename: string;
+    status: "queued" | "running" | "completed" | "failed" | "cancelled";
+    processed_records: number;
+    imported_count: number;
+    updated_count: number;
+    error_count: number;
+    dry_run: boolean;
+    error?: string;
+}
+
+interface ImportAdapter {
+    id?: string;
+    slug: string;
+    name: string;
+    description?: string;
+    builtin: boolean;
 }
 
 export default function AdminPage() {
@@ -71,7 +109,17 @@ export default function AdminPage() {
   const [docPage, setDocPage] = useState(1);
   const [docLimit] = useState(20);
   const [docTotal, setDocTotal] = useState(0);
-  const [importStatus, setImportStatus] = useState<ImportStatus | null>(null);
+  const [showTrash, setShowTrash] = useState(false);
+  const [trash, setTrash] = useState<DeletedDocument[]>([]);
+  const [historyDocId, setHistoryDocId] = useState<string | null>(null);
+  const [versions, setVersions] = useState<DocumentVersion[]>([]);
+  const [importJobId, setImportJobId] = useState<string | null>(null);
+  const [importJob, setImportJob] = useState<ImportJob | null>(null);
+  const [importAdapters, setImportAdapters] = useState<ImportAdapter[]>([]);
+  const [selectedAdapter, setSelectedAdapter] = useState("");
+  const [onConflict, setOnConflict] = useState<"skip" | "update" | "duplicate">("skip");
+  const [collections, setCollections] = useState<KbCollection[]>([]);
+  const [selectedCollection, setSelectedCollection] = useState("");
 
   // User Management State
   const [users, setUsers] = useState<User[]>([]);
@@ -168,6 +216,42 @@ export default function AdminPage() {
       }
   }, [docLimit, docPage]);
 
+  const fetchTrash = useCallback(async () => {
+      const token = localStorage.getItem("token");
+      try {
+          const res = await axios.get<DeletedDocument[]>(`${getApiBaseUrl()}/api/admin/documents/trash?limit=100`, {
+              headers: { Authorization: `Bearer ${token}` }
+          });
+          setTrash(res.data);
+      } catch (err) {
+          console.error("Failed to fetch trash", err);
+      }
+  }, []);
+
+  const fetchCollections = useCallback(async () => {
+      const token = localStorage.getItem("token");
+      try {
+          const res = await axios.get<KbCollection[]>(`${getApiBaseUrl()}/api/admin/collections`, {
+              headers: { Authorization: `Bearer ${token}` }
+          });
+          setCollections(res.data);
+      } catch (err) {
+          console.error("Failed to fetch collections", err);
+      }
+  }, []);
+
+  const fetchImportAdapters = useCallback(async () => {
+      const token = localStorage.getItem("token");
+      try {
+          const res = await axios.get<ImportAdapter[]>(`${getApiBaseUrl()}/api/admin/import-adapters`, {
+              headers: { Authorization: `Bearer ${token}` }
+          });
+          setImportAdapters(res.data);
+      } catch (err) {
+          console.error("Failed to fetch import adapters", err);
+      }
+  }, []);
+
   useEffect(() => {
     const token = localStorage.getItem("token");
     const userRole = localStorage.getItem("role") || "";
@@ -193,24 +277,32 @@ export default function AdminPage() {
       }
       if (activeTab === "knowledge") {
           fetchDocuments();
+          fetchImportAdapters();
+          fetchCollections();
       }
     }
-  }, [router, activeTab, fetchUsers, fetchVouchers, fetchVoucherRequests, fetchModels, fetchDocuments]);
+  }, [router, activeTab, fetchUsers, fetchVouchers, fetchVoucherRequests, fetchModels, fetchDocuments, fetchImportAdapters, fetchCollections]);
 
   useEffect(() => {
       let interval: NodeJS.Timeout;
-      if (kbLoading || importStatus?.is_processing) {
+      if (importJobId) {
           interval = setInterval(async () => {
               const token = localStorage.getItem("token");
               try {
-                  const res = await axios.get(`${getApiBaseUrl()}/api/admin/import-status`, {
+                  const res = await axios.get<ImportJob>(`${getApiBaseUrl()}/api/admin/imports/${importJobId}`, {
                       headers: { Authorization: `Bearer ${token}` }
                   });
-                  setImportStatus(res.data);
-                  if (!res.data.is_processing && res.data.message.includes("Import complete")) {
+                  const job = res.data;
+                  setImportJob(job);
+                  if (job.status === "completed" || job.status === "cancelled") {
                       setKbLoading(false);
-                      setKbStatus({ type: "success", message: res.data.message });
+                      setImportJobId(null);
+                      setKbStatus({ type: "success", message: `Import ${job.status}. Imported: ${job.imported_count}, Errors: ${job.error_count}` });
                       fetchDocuments();
+                  } else if (job.status === "failed") {
+                      setKbLoading(false);
+                      setImportJobId(null);
+                      setKbStatus({ type: "error", message: job.error || "Import failed." });
                   }
               } catch (e) {
                   console.error("Poll error", e);
@@ -218,7 +310,7 @@ export default function AdminPage() {
           }, 1000);
       }
       return () => clearInterval(interval);
-  }, [kbLoading, importStatus?.is_processing, fetchDocuments]);
+  }, [importJobId, fetchDocuments]);
 
   const handleDeleteDocument = async (id: string) => {
       if(!confirm("Are you sure?")) return;
@@ -228,11 +320,82 @@ export default function AdminPage() {
               headers: { Authorization: `Bearer ${token}` }
           });
           fetchDocuments();
+          if (showTrash) fetchTrash();
       } catch {
           alert("Failed to delete");
       }
   };
 
+  const handleMoveDocument = async (id: string, collectionId: string) => {
+      const token = localStorage.getItem("token");
+      try {
+          await axios.post(`${getApiBaseUrl()}/api/admin/documents/move`, { document_ids: [id], collection_id: collectionId }, {
+              headers: { Authorization: `Bearer ${token}` }
+          });
+          fetchDocuments();
+          fetchCollections();
+      } catch {
+          alert("Failed to move document");
+      }
+  };
+
+  const handleUndeleteDocument = async (id: string) => {
+      const token = localStorage.getItem("token");
+      try {
+          await axios.post(`${getApiBaseUrl()}/api/admin/documents/${id}/undelete`, {}, {
+              headers: { Authorization: `Bearer ${token}` }
+          });
+          fetchTrash();
+          fetchDocuments();
+      } catch (err) {
+          alert(isAxiosError(err) ? err.response?.data?.error || "Failed to restore" : "Failed to restore");
+      }
+  };
+
+  const handlePurgeDocument = async (id: string) => {
+      if(!confirm("Permanently delete this document and its history?")) return;
+      const token = localStorage.getItem("token");
+      try {
+          await axios.delete(`${getApiBaseUrl()}/api/admin/documents/trash/${id}`, {
+              headers: { Authorization: `Bearer ${token}` }
+          });
+          fetchTrash();
+      } catch {
+          alert("Failed to purge");
+      }
+  };
+
+  const toggleHistory = async (id: string) => {
+      if (historyDocId === id) {
+          setHistoryDocId(null);
+          return;
+      }
+      const token = localStorage.getItem("token");
+      try {
+          const res = await axios.get<DocumentVersion[]>(`${getApiBaseUrl()}/api/admin/documents/${id}/versions`, {
+              headers: { Authorization: `Bearer ${token}` }
+          });
+          setVersions(res.data);
+          setHistoryDocId(id);
+      } catch {
+          alert("Failed to load history");
+      }
+  };
+
+  const handleRestoreVersion = async (id: string, version: number) => {
+      if(!confirm(`Restore version ${version}?`)) return;
+      const token = localStorage.getItem("token");
+      try {
+          await axios.post(`${getApiBaseUrl()}/api/admin/documents/${id}/versions/${version}/restore`, {}, {
+              headers: { Authorization: `Bearer ${token}` }
+          });
+          setHistoryDocId(null);
+          fetchDocuments();
+      } catch (err) {
+          alert(isAxiosError(err) ? err.response?.data?.error || "Failed to restore" : "Failed to restore");
+      }
+  };
+
   const handleUpdateDocument = async () => {
       if (!editingDocument) return;
       const token = localStorage.getItem("token");
@@ -427,7 +590,11 @@ export default function AdminPage() {
             formData.append("file", blob, "manual_upload.json");
         }
 
-        const response = await axios.post(`${getApiBaseUrl()}/api/admin/upload/file`, formData, {
+        const params = new URLSearchParams({ on_conflict: onConflict });
+        if (selectedAdapter) params.set("adapter", selectedAdapter);
+        if (selectedCollection) params.set("collection", selectedCollection);
+        const query = `?${params.toString()}`;
+        const response = await axios.post(`${getApiBaseUrl()}/api/admin/upload/file${query}`, formData, {
             headers: {
                 Authorization: `Bearer ${token}`,
                 "Content-Type": "multipart/form-data"
@@ -439,6 +606,8 @@ export default function AdminPage() {
         });
         
         setKbStatus({ type: "success", message: response.data.message });
+        setImportJob(null);
+        setImportJobId(response.data.job_id);
         setJsonInput("");
         setSelectedFile(null);
     } catch (error) {
@@ -535,14 +704,46 @@ export default function AdminPage() {
                     <div className="flex items-center gap-4 mb-4">
                         <label className="flex items-center gap-2 cursor-pointer bg-white/5 hover:bg-white/10 border border-white/10 px-4 py-2 rounded-lg transition-colors group">
                             <Upload size={16} className="text-white group-hover:scale-110 transition-transform" />
-                            <span className="text-sm font-medium text-gray-300 group-hover:text-white">Import JSON File</span>
+                            <span className="text-sm font-medium text-gray-300 group-hover:text-white">Import File (JSON, Markdown, PDF, HTML, CSV, TXT, ZIP)</span>
                             <input 
                                 type="file" 
-                                accept=".json"
+                                accept=".json,.jsonl,.md,.markdown,.pdf,.html,.htm,.csv,.txt,.zip"
                                 onChange={handleFileUpload}
                                 className="hidden" 
                             />
                         </label>
+                        <select
+                            value={selectedAdapter}
+                            onChange={(e) => setSelectedAdapter(e.target.value)}
+                            title={importAdapters.find(a => a.slug === selectedAdapter)?.description}
+                            className="bg-black border border-white/10 rounded-lg px-3 py-2 text-sm text-gray-300 focus:outline-none focus:border-white"
+                        >
+                            <option value="">No adapter ({`{ content, metadata }`} records)</option>
+                            {importAdapters.map(a => (
+                                <option key={a.slug} value={a.slug}>{a.name}{a.builtin ? "" : " (saved)"}</option>
+                            ))}
+                        </select>
+                        <select
+                            value={onConflict}
+                            onChange={(e) => setOnConflict(e.target.value as "skip" | "update" | "duplicate")}
+                            title="What to do with records that match an existing document"
+                            className="bg-black border border-white/10 rounded-lg px-3 py-2 text-sm text-gray-300 focus:outline-none focus:border-white"
+                        >
+                            <option value="skip">Skip existing</option>
+                            <option value="update">Update existing</option>
+                            <option value="duplicate">Keep duplicates</option>
+                        </select>
+                        <select
+                            value={selectedCollection}
+                            onChange={(e) => setSelectedCollection(e.target.value)}
+                            title="Collection the imported documents are added to"
+                            className="bg-black border border-white/10 rounded-lg px-3 py-2 text-sm text-gray-300 focus:outline-none focus:border-white"
+                        >
+                            <option value="">Default collection</option>
+                            {collections.map(c => (
+                                <option key={c.id} value={c.id}>{c.name} ({c.visibility})</option>
+                            ))}
+                        </select>
                         <span className="text-xs text-gray-500">or paste content below</span>
                     </div>
 
@@ -565,10 +766,10 @@ export default function AdminPage() {
                     {kbLoading && (
                         <div className="mb-6">
                             <div className="flex justify-between text-xs text-gray-400 mb-1">
-                                <span>{importStatus?.is_processing ? importStatus.message : "Uploading..."}</span>
+                                <span>{importJob ? `Importing ${importJob.filename} (${importJob.status})...` : "Uploading..."}</span>
                                 <span>
-                                    {importStatus?.is_processing 
-                                        ? `${importStatus.processed_documents} processed${importStatus.errors > 0 ? ` (${importStatus.errors} errors)` : ''}` 
+                                    {importJob 
+                                        ? `${importJob.processed_records} processed${importJob.updated_count > 0 ? `, ${importJob.updated_count} updated` : ''}${importJob.error_count > 0 ? ` (${importJob.error_count} errors)` : ''}` 
                                         : `${uploadProgress}%`
                                     }
                                 </span>
@@ -576,7 +777,7 @@ export default function AdminPage() {
                             <div className="w-full bg-white/10 rounded-full h-2">
                                 <div 
                                     className="bg-white h-2 rounded-full transition-all duration-300 ease-out relative overflow-hidden" 
-                                    style={{ width: importStatus?.is_processing ? '100%' : `${uploadProgress}%` }}
+                                    style={{ width: importJob ? '100%' : `${uploadProgress}%` }}
                                 >
                                     <div className="absolute inset-0 bg-white/20 animate-[shimmer_2s_infinite]"></div>
                                 </div>
@@ -599,6 +800,12 @@ export default function AdminPage() {
                     <div className="flex items-center justify-between mb-6">
                         <h2 className="text-2xl font-bold text-white">Existing Knowledge ({docTotal})</h2>
                         <div className="flex gap-2 items-center">
+                             <button 
+                                 onClick={() => { if (!showTrash) fetchTrash(); setShowTrash(!showTrash); }}
+                                 className="px-3 py-1 bg-white/5 hover:bg-white/10 rounded-lg text-sm transition-colors"
+                             >
+                                 {showTrash ? "Hide Trash" : "Trash"}
+                             </button>
                              <button 
                                  onClick={() => fetchDocuments(docPage - 1)}
                                  disabled={docPage <= 1}
@@ -616,6 +823,40 @@ export default function AdminPage() {
                              </button>
                         </div>
                     </div>
+                    {showTrash && (
+                        <div className="mb-6 space-y-2">
+                            <h3 className="text-lg font-bold text-white">Trash ({trash.length})</h3>
+                            {trash.map((doc) => (
+                                <div key={doc.id} className="bg-white/5 rounded-xl p-3 border border-red-500/20 flex justify-between items-start gap-4">
+                                    <div className="min-w-0">
+                                        <div className="font-mono text-xs text-gray-500">
+                                            {doc.id} · deleted {new Date(doc.deleted_at).toLocaleString()}{doc.deleted_by_name ? ` by ${doc.deleted_by_name}` : ""}
+                                        </div>
+                                        <div className="text-sm text-gray-400 line-clamp-2 font-mono">{doc.content}</div>
+                                    </div>
+                                    <div className="flex gap-2 shrink-0">
+                                        <button
+                                            onClick={() => handleUndeleteDocument(doc.id)}
+                                            className="px-3 py-1 rounded-lg bg-white/10 hover:bg-white/20 text-sm transition-colors"
+                                        >
+                                            Restore
+                                        </button>
+                                        {role === "admin" && (
+                                            <button
+                                                onClick={() => handlePurgeDocument(doc.id)}
+                                                className="px-3 py-1 rounded-lg bg-red-500/10 hover:bg-red-500/20 text-red-400 text-sm transition-colors"
+                                            >
+                                                Purge
+                                            </button>
+                                        )}
+                                    </div>
+                                </div>
+                            ))}
+                            {trash.length === 0 && (
+                                <div className="text-sm text-gray-500">Trash is empty.</div>
+                            )}
+                        </div>
+                    )}
                     <div className="space-y-4">
                         {documents.map((doc) => (
                             <div key={doc.id} className="bg-white/5 rounded-xl p-4 border border-white/10">
@@ -652,7 +893,17 @@ export default function AdminPage() {
                                     <div>
                                         <div className="flex justify-between items-start mb-2">
                                             <div className="font-mono text-xs text-gray-500 mb-1">{doc.id}</div>
-                                            <div className="flex gap-2">
+                                            <div className="flex gap-2 items-center">
+                                                <select
+                                                    value={doc.collection_id ?? ""}
+                                                    onChange={(e) => handleMoveDocument(doc.id, e.target.value)}
+                                                    title="Collection"
+                                                    className="bg-black border border-white/10 rounded-lg px-2 py-1 text-xs text-gray-400 focus:outline-none focus:border-white"
+                                                >
+                                                    {collections.map(c => (
+                                                        <option key={c.id} value={c.id}>{c.name}</option>
+                                                    ))}
+                                                </select>
                                                 <button 
                                                     onClick={() => setEditingDocument(doc)}
                                                     className="p-1.5 rounded-lg hover:bg-white/10 text-gray-400 hover:text-white transition-colors"
@@ -660,6 +911,13 @@ export default function AdminPage() {
                                                 >
                                                     <Edit size={16} />
                                                 </button>
+                                                <button 
+                                                    onClick={() => toggleHistory(doc.id)}
+                                                    className="p-1.5 rounded-lg hover:bg-white/10 text-gray-400 hover:text-white transition-colors"
+                                                    title="History"
+                                                >
+                                                    <History size={16} />
+                                                </button>
                                                 <button 
                                                     onClick={() => handleDeleteDocument(doc.id)}
                                                     className="p-1.5 rounded-lg hover:bg-white/10 text-gray-400 hover:text-red-400 transition-colors"
@@ -677,6 +935,26 @@ export default function AdminPage() {
                                                 {typeof doc.metadata === 'string' ? doc.metadata : JSON.stringify(doc.metadata)}
                                             </div>
                                         )}
+                                        {historyDocId === doc.id && (
+                                            <div className="mt-3 space-y-1 border-t border-white/10 pt-3">
+                                                {versions.map((v) => (
+                                                    <div key={v.version} className="flex justify-between items-center text-xs text-gray-400 font-mono">
+                                                        <span>
+                                                            v{v.version} · {v.action}{v.restored_from ? ` from v${v.restored_from}` : ""} · {new Date(v.created_at).toLocaleString()}{v.author_name ? ` · ${v.author_name}` : ""}
+                                                        </span>
+                                                        <button
+                                                            onClick={() => handleRestoreVersion(doc.id, v.version)}
+                                                            className="px-2 py-0.5 rounded bg-white/5 hover:bg-white/10 transition-colors"
+                                                        >
+                                                            Restore
+                                                        </button>
+                                                    </div>
+                                                ))}
+                                                {versions.length === 0 && (
+                                                    <div className="text-xs text-gray-500">No changes recorded yet.</div>
+                                                )}
+                                            </div>
+                                        )}
                                     </div>
                                 )}
                             </div>
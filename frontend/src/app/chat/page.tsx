@@ -15,7 +15,7 @@ import { getApiBaseUrl } from "@/lib/api";
 import Loader from "@/components/loading";
 
 interface Message {
-  role: "user" | "assistant" | "system";
+  role: "user" | "assistant" | "system" | "tool";
   content: string;
 }
 
@@ -31,7 +31,7 @@ interface HistoryItem {
 }
 
 interface ChatHistoryMessage {
-    role: "user" | "assistant" | "system";
+    role: "user" | "assistant" | "system" | "tool";
     content: string;
 }
 